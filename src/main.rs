@@ -1,16 +1,28 @@
-// #![feature(test)]
 pub mod nes;
 use std::env;
+use nes::config::Config;
+use nes::region::Region;
+use nes::scale::{Overscan, PixelAspect, ScaleFilter};
+use nes::vs_system::VsPpu;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
     println!("{:?}", args);
     match args.len() {
-        1 => nes::run("rom/nestest.nes", false),
-        2..=4 => {
+        1 => nes::run("rom/nestest.nes", &Config::new()),
+        2..=32 => {
             let mut rom: &String = &"".to_string();
-            let mut is_debug = false;
+            let mut config: Config = Config::new();
+            let mut is_regress = false;
+            let mut is_update_golden = false;
+            let mut is_bench = false;
+            // overscan or aspect given, saved for the rom
+            let mut aspect: Option<PixelAspect> = None;
+            let mut overscan: Option<Overscan> = None;
+            // record without window
+            let mut frames: Option<u32> = None;
+            let mut movie: Option<&String> = None;
             for (i, a) in args.iter().enumerate() {
                 match a.as_str() {
                     "-r" | "--rom" => {
@@ -19,12 +31,160 @@ fn main() {
                         }
                     },
                     "-d" | "--debug" => {
-                        is_debug = true;
+                        config.is_debug = true;
+                    },
+                    "--no-sprite-limit" => {
+                        config.is_sprite_limit_disabled = true;
+                    },
+                    "--palette" if i + 1 < args.len() => {
+                        config.palette = Some(args[i+1].clone());
+                    },
+                    "--ntsc" => {
+                        config.is_ntsc_filter = true;
+                    },
+                    // nearest, nearest2-8, scale2x, scale3x
+                    "--filter" if i + 1 < args.len() => {
+                        config.scale.filter = match ScaleFilter::parse(&args[i+1]) {
+                            Ok(f) => f,
+                            Err(e) => panic!("{}", e),
+                        };
+                    },
+                    "--scanlines" => {
+                        config.scale.is_scanline = true;
+                    },
+                    "--aspect" if i + 1 < args.len() => {
+                        aspect = match PixelAspect::parse(&args[i+1]) {
+                            Ok(a) => Some(a),
+                            Err(e) => panic!("{}", e),
+                        };
+                    },
+                    "--overscan" if i + 1 < args.len() => {
+                        overscan = match Overscan::parse(&args[i+1]) {
+                            Ok(o) => Some(o),
+                            Err(e) => panic!("{}", e),
+                        };
+                    },
+                    "--record" if i + 1 < args.len() => {
+                        config.record = Some(args[i+1].clone());
+                    },
+                    "--frames" if i + 1 < args.len() => {
+                        frames = Some(args[i+1].parse().expect("invalid frames"));
+                    },
+                    "--movie" if i + 1 < args.len() => {
+                        movie = Some(&args[i+1]);
+                    },
+                    "--save-dir" if i + 1 < args.len() => {
+                        config.save_dir = Some(args[i+1].clone());
+                    },
+                    "--zip-member" if i + 1 < args.len() => {
+                        config.load.member = Some(args[i+1].clone());
+                    },
+                    "--patch" if i + 1 < args.len() => {
+                        config.load.patches.push(args[i+1].clone());
+                    },
+                    "--fds-bios" if i + 1 < args.len() => {
+                        config.load.fds_bios = Some(args[i+1].clone());
+                    },
+                    "--region" if i + 1 < args.len() => {
+                        match Region::parse(&args[i+1]) {
+                            Ok(region) => config.region = Some(region),
+                            Err(e) => panic!("{}", e),
+                        }
+                    },
+                    "--dip" if i + 1 < args.len() => {
+                        let text: &str = &args[i+1];
+                        let dip = match text.strip_prefix("0x") {
+                            Some(hex) => u8::from_str_radix(hex, 16),
+                            None => text.parse::<u8>(),
+                        };
+                        config.vs_dip = dip.unwrap_or_else(|_| panic!("invalid DIP switches {}", text));
+                    },
+                    "--vs-ppu" if i + 1 < args.len() => {
+                        match VsPpu::parse(&args[i+1]) {
+                            Ok(vs_ppu) => config.load.vs_ppu = Some(vs_ppu),
+                            Err(e) => panic!("{}", e),
+                        }
+                    },
+                    "--vs-swap" => {
+                        config.is_vs_swap_controllers = true;
+                    },
+                    "--no-db" => {
+                        config.load.is_db_disabled = true;
+                    },
+                    "--regress" => {
+                        is_regress = true;
+                    },
+                    "--update-golden" => {
+                        is_regress = true;
+                        is_update_golden = true;
+                    },
+                    "--bench" => {
+                        is_bench = true;
                     },
                     _ => (),
                 }
             }
-            nes::run(rom, is_debug);
+            if args[1] == "--make-patch" {
+                if args.len() != 5 {
+                    panic!("usage: --make-patch <original rom> <modified rom> <out.ips|ups|bps>");
+                }
+                let read = |p: &String| std::fs::read(p).unwrap_or_else(|e| panic!("{}: {}", p, e));
+                match nes::patch::create(&read(&args[2]), &read(&args[3]), &args[4]) {
+                    Ok(data) => std::fs::write(&args[4], data)
+                        .unwrap_or_else(|e| panic!("{}: {}", args[4], e)),
+                    Err(e) => panic!("{}", e),
+                }
+                println!("patch: {}", args[4]);
+                return;
+            }
+            if args[1] == "--import-nes20db" {
+                if args.len() != 4 {
+                    panic!("usage: --import-nes20db <nes20db.xml> <out gamedb.txt>");
+                }
+                let xml = std::fs::read_to_string(&args[2]).unwrap_or_else(|e| panic!("{}: {}", args[2], e));
+                match nes::gamedb::import_nes20db(&xml) {
+                    Ok(text) => std::fs::write(&args[3], text)
+                        .unwrap_or_else(|e| panic!("{}: {}", args[3], e)),
+                    Err(e) => panic!("{}", e),
+                }
+                return;
+            }
+            if is_bench {
+                nes::bench::run(if rom.is_empty() {None} else {Some(rom)});
+                return;
+            }
+            if is_regress {
+                let manifest = if rom.is_empty() {nes::regress::MANIFEST} else {rom};
+                match nes::regress::run_all(manifest, is_update_golden) {
+                    Ok(true) => (),
+                    Ok(false) => std::process::exit(1),
+                    Err(e) => panic!("{}", e),
+                }
+                return;
+            }
+            if let Err(e) = nes::rom_settings::load(rom, &mut config.scale) {
+                println!("settings: {}", e);
+            }
+            if aspect.is_some() || overscan.is_some() {
+                config.scale.aspect = aspect.unwrap_or(config.scale.aspect);
+                config.scale.overscan = overscan.unwrap_or(config.scale.overscan);
+                if let Err(e) = nes::rom_settings::save(rom, &config.scale) {
+                    println!("settings: {}", e);
+                }
+            }
+            if let (Some(path), Some(frames)) = (&config.record, frames) {
+                let script = match movie {
+                    Some(m) => nes::script::InputScript::load(m).unwrap_or_else(|e| panic!("{}", e)),
+                    None => nes::script::InputScript::new(),
+                };
+                let cas = nes::cassette::Cassette::load(rom, &config.load).unwrap_or_else(|e| panic!("{}", e));
+                match nes::record_headless(&cas, &config, frames, &script, path) {
+                    Ok(n) => println!("record: {} frames to {}", n, path),
+                    Err(e) => panic!("{}", e),
+                }
+                return;
+            }
+            nes::run(rom, &config);
         }
         _ => panic!("invalid args {:?}", args),
     } 
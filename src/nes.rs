@@ -1,5 +1,3 @@
-// extern crate test;
-
 pub mod apu;
 pub mod cassette;
 pub mod cpu;
@@ -9,6 +7,29 @@ pub mod render;
 pub mod ram;
 pub mod game;
 pub mod optable;
+pub mod script;
+pub mod regress;
+pub mod bench;
+pub mod config;
+pub mod colors;
+pub mod ntsc;
+pub mod scale;
+pub mod rom_settings;
+pub mod checksum;
+pub mod screenshot;
+pub mod video;
+pub mod eeprom;
+pub mod bandai;
+pub mod battery;
+pub mod gamedb;
+pub mod inflate;
+pub mod archive;
+pub mod patch;
+pub mod unif;
+pub mod fds;
+pub mod fds_audio;
+pub mod region;
+pub mod vs_system;
 
 extern crate sdl2;
 
@@ -20,30 +41,64 @@ use crate::nes::ppu::*;
 use crate::nes::render::*;
 use crate::nes::ram::Ram;
 use crate::nes::cassette::Cassette;
+use crate::nes::script::InputScript;
+use crate::nes::config::Config;
+use crate::nes::region::Region;
+use crate::nes::vs_system::VsPpu;
 
 const WRAM_SIZE: usize = 0x0800; // 2KiB
-const VRAM_SIZE: usize = 0x2000; // 2KiB?
+const VRAM_SIZE: usize = 0x0800; // 2KiB
 
 use std::time::{Duration, Instant};
 
-pub fn run(cassette_path: &str, is_debug: bool) {
+pub fn run(cassette_path: &str, config: &Config) {
+    let is_debug: bool = config.is_debug;
     let mut wram: Ram = Ram::new(WRAM_SIZE);
     let mut vram: Ram = Ram::new(VRAM_SIZE);
-    let cas: Cassette = Cassette::new(cassette_path);
+    let cas: Cassette = match Cassette::load(cassette_path, &config.load) {
+        Ok(cas) => cas,
+        Err(e) => panic!("{}", e),
+    };
     let mut interrupts: Interrupts = Interrupts::new();
     let mut image: Image = Image::new();
+    let region: Region = Region::select(config.region, &cas.header);
+    println!("region: {} {}Hz", region.name(), region.cpu_clock());
     let mut apu: Apu = Apu::new();
+    apu.set_region(region);
     let mut ppu: Ppu = Ppu::new(&cas, &mut vram);
+    ppu.set_sprite_limit(!config.is_sprite_limit_disabled);
+    ppu.set_region(region);
     let mut cpu: Cpu = Cpu::new(&cas, &mut wram);
-    let mut game: Game = Game::new(is_debug).unwrap();
+    cpu.set_region(region);
+    cpu.set_vs_switches(config.vs_dip, config.is_vs_swap_controllers);
+    let save_path: String = battery::save_path(cassette_path, config.save_dir.as_deref());
+    if cpu.battery_data().is_some() {
+        match battery::load(&save_path) {
+            Ok(Some(data)) => match cpu.load_battery(&data) {
+                Ok(()) => println!("battery: loaded {}", save_path),
+                Err(e) => println!("battery: {}: {}", save_path, e),
+            },
+            Ok(None) => (),
+            Err(e) => println!("battery: {}", e),
+        }
+    }
+    // the window fits the scaled screen, small filters are enlarged by SCALE
+    let (width, height) = config.scale.output_size(H_SIZE, V_SIZE);
+    let window_scale: usize = if config.scale.filter.factor() == 1 { SCALE as usize } else { 1 };
+    let mut game: Game = Game::new(
+        is_debug, ((window_scale * width) as u32, (window_scale * height) as u32), region.fps()).unwrap();
     // let mut debug_bg: Game = Game::new().unwrap();
-    let mut render: Render = Render::new();
+    let mut render: Render = new_render(config, &cas);
+    let mut recorder: Option<video::AviWriter> = None;
 
     cpu.reset(&mut ppu, &mut apu, &mut interrupts);
 
     let mut start = Instant::now();
     let mut end = start.elapsed();
     let mut is_dbg_rendered: bool = false;
+    let mut frame: u64 = 0;
+    // F12 saves the next completed frame
+    let mut is_screenshot_requested: bool = false;
     loop {
         let status: GameStatus =
             game.check_key(&mut cpu).unwrap();
@@ -52,6 +107,7 @@ pub fn run(cassette_path: &str, is_debug: bool) {
         apu.run(cycle, &mut interrupts);    
 
         if is_render_ready {
+            let audio: Vec<i16> = cpu.take_audio_samples();
             render.render(&mut image);
             game.update(&render.data,
                 UpdateMode::Game).unwrap();
@@ -64,27 +120,196 @@ pub fn run(cassette_path: &str, is_debug: bool) {
                     // is_dbg_rendered = true;
                 // }
             }
+            game.present();
+            if is_screenshot_requested {
+                let metadata = screenshot::Metadata {
+                    rom_crc32: cas.crc32,
+                    frame,
+                };
+                match screenshot::capture(
+                        &cas.name(), &render.render_raw(&image), &render.data, &metadata) {
+                    Ok((raw, shown)) => println!("screenshot: {} {}", raw, shown),
+                    Err(e) => println!("screenshot: {}", e),
+                }
+                is_screenshot_requested = false;
+            }
+            if let Some(path) = &config.record {
+                if recorder.is_none() {
+                    recorder = Some(video::AviWriter::create(
+                        path, render.data.width, render.data.height, region.frame_rate()).unwrap());
+                    println!("record: {}", video::AUDIO_NOTE);
+                }
+                if let Err(e) = recorder.as_mut().unwrap().write_frame(&render.data, &audio) {
+                    println!("record: {}", e);
+                    break;
+                }
+            }
+            frame += 1;
+            if frame.is_multiple_of(battery::FLUSH_INTERVAL) {
+                flush_battery(&mut cpu, &save_path);
+            }
             end = start.elapsed();
             let erapsed: f32 = end.subsec_nanos() as f32 / 1_000_000_000 as f32;
             println!("fps:{}, sec:{}", 1.0 / erapsed, erapsed);
             start = Instant::now();
         }
-        if status == GameStatus::Exit {
-            println!("Exit...");
-            break;
+        match status {
+            GameStatus::Exit => {
+                println!("Exit...");
+                break;
+            },
+            GameStatus::Screenshot => is_screenshot_requested = true,
+            GameStatus::SwitchDiskSide => {
+                if cpu.switch_disk_side() {
+                    println!("fds: switching disk side");
+                }
+            },
+            GameStatus::Ok => (),
+        }
+    }
+    if let Some(recorder) = recorder {
+        match recorder.finish() {
+            Ok(frames) => println!("record: {} frames", frames),
+            Err(e) => println!("record: {}", e),
+        }
+    }
+    flush_battery(&mut cpu, &save_path);
+}
+
+// write the battery backup if it changed
+fn flush_battery(cpu: &mut Cpu, path: &str) {
+    if !cpu.take_battery_dirty() {
+        return;
+    }
+    if let Some(data) = cpu.battery_data() {
+        if let Err(e) = battery::save(path, &data) {
+            println!("battery: {}", e);
+        }
+    }
+}
+
+// Render with the palette, filter and scale of config, PAL colors on PAL and Dendy
+// and the RGB PPU colors on Vs. System and PlayChoice-10
+pub fn new_render(config: &Config, cas: &Cassette) -> Render {
+    let mut render: Render = Render::new();
+    let region: Region = Region::select(config.region, &cas.header);
+    let is_pal: bool = region.is_emphasis_swapped();
+    let vs_ppu: Option<VsPpu> = VsPpu::from_header(&cas.header);
+    let palette: Option<&str> = config.palette.as_deref().or(if is_pal { Some("2c07") } else { None });
+    match (palette, vs_ppu) {
+        (Some(name), _) => {
+            let table: Vec<u64> = colors::load(name, is_pal).unwrap_or_else(|e| panic!("{}", e));
+            render.set_colors(match vs_ppu {
+                Some(vs_ppu) => vs_ppu.remap(&table),
+                None => table,
+            });
+        },
+        (None, Some(vs_ppu)) => {
+            println!("ppu: {}", vs_ppu.name());
+            render.set_colors(vs_ppu.colors());
+        },
+        (None, None) => (),
+    }
+    if config.is_ntsc_filter {
+        let params = if is_pal { colors::NtscParams::pal() } else { colors::NtscParams::new() };
+        render.set_ntsc_filter(Some(ntsc::NtscFilter::new(params)));
+    }
+    render.set_scale(config.scale.clone());
+    render
+}
+
+// run without window, on_frame is called after every frame
+pub fn run_headless<F>(
+    cas: &Cassette,
+    config: &Config,
+    frames: u32,
+    script: &InputScript,
+    mut on_frame: F
+) where F: FnMut(u32, &Image, &[i16]) {
+    let mut wram: Ram = Ram::new(WRAM_SIZE);
+    let mut vram: Ram = Ram::new(VRAM_SIZE);
+    let mut interrupts: Interrupts = Interrupts::new();
+    let mut image: Image = Image::new();
+    let region: Region = Region::select(config.region, &cas.header);
+    let mut apu: Apu = Apu::new();
+    apu.set_region(region);
+    let mut ppu: Ppu = Ppu::new(cas, &mut vram);
+    ppu.set_sprite_limit(!config.is_sprite_limit_disabled);
+    ppu.set_region(region);
+    let mut cpu: Cpu = Cpu::new(cas, &mut wram);
+    cpu.set_region(region);
+    cpu.set_vs_switches(config.vs_dip, config.is_vs_swap_controllers);
+
+    cpu.reset(&mut ppu, &mut apu, &mut interrupts);
+
+    let mut frame: u32 = 0;
+    script.apply(frame, &mut cpu.keypad1);
+    while frame < frames {
+        let cycle: u64 = cpu.run(&mut ppu, &mut apu, &mut interrupts);
+        let is_render_ready: bool = ppu.run(cycle, &mut image, &mut interrupts);
+        apu.run(cycle, &mut interrupts);
+
+        if is_render_ready {
+            on_frame(frame, &image, &cpu.take_audio_samples());
+            frame += 1;
+            script.apply(frame, &mut cpu.keypad1);
+        }
+    }
+}
+
+// record frames of the rom driven by the input script into an AVI file
+pub fn record_headless(
+    cas: &Cassette,
+    config: &Config,
+    frames: u32,
+    script: &InputScript,
+    path: &str
+) -> Result<u32, String> {
+    let region: Region = Region::select(config.region, &cas.header);
+    let mut render: Render = new_render(config, cas);
+    let mut recorder: Option<video::AviWriter> = None;
+    let mut result: Result<(), String> = Ok(());
+    run_headless(cas, config, frames, script, |_, image, audio| {
+        if result.is_err() {
+            return;
+        }
+        render.render(image);
+        if recorder.is_none() {
+            match video::AviWriter::create(
+                    path, render.data.width, render.data.height, region.frame_rate()) {
+                Ok(r) => {
+                    println!("record: {}", video::AUDIO_NOTE);
+                    recorder = Some(r);
+                },
+                Err(e) => {
+                    result = Err(e);
+                    return;
+                },
+            }
         }
+        result = recorder.as_mut().unwrap().write_frame(&render.data, audio);
+    });
+    result?;
+    match recorder {
+        Some(r) => r.finish(),
+        None => Ok(0),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use test::Bencher;
 
-    #[bench]
-    fn bench_nes(b: &mut Bencher) {
-        b.iter(|| run("rom/firedemo.nes", true));
+    #[test]
+    fn record_headless_writes_frames() {
+        let path = std::env::temp_dir().join("rustnes_record_test.avi");
+        let path = path.to_str().unwrap();
+        let cas: Cassette = bench::synthetic_cassette();
+        let result = record_headless(&cas, &Config::new(), 4, &InputScript::new(), path);
+        let size = std::fs::metadata(path).map(|m| m.len());
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(result, Ok(4));
+        // 4 frames of 256x240x3 at least
+        assert!(size.unwrap() > 4 * 256 * 240 * 3);
     }
-    
-    // ...
-}
\ No newline at end of file
+}
@@ -23,10 +23,9 @@
 
 use super::Interrupts;
 use super::Ram;
+use super::region::Region;
 
-const CYCLE_240HZ: usize = 7457;
 const APU_REGISTER_SIZE: usize = 0x18;
-const CPU_CLOCK: f32 = 1789772.5;
 const GLOBAL_GAIN: f32 = 0.01;
 const SAMPLING_FREQUENCY: usize = 44100;
 const COUNTER_TABLE: [u8; 32] = [
@@ -50,6 +49,21 @@ const DMC_TIMER_PERIOD_TABLE: [u16; 16] = [
   0x06A, 0x054, 0x048, 0x036,
 ];
 
+// 2A07, Dendy uses the NTSC tables
+const PAL_NOISE_TIMER_PERIOD_TABLE: [u16; 16] = [
+  0x004, 0x008, 0x00E, 0x01E,
+  0x03C, 0x058, 0x076, 0x094,
+  0x0BC, 0x0EC, 0x162, 0x1D8,
+  0x2C4, 0x3B0, 0x762, 0xEC2,
+];
+
+const PAL_DMC_TIMER_PERIOD_TABLE: [u16; 16] = [
+  0x18E, 0x162, 0x13C, 0x12A,
+  0x114, 0x0EC, 0x0D2, 0x0C6,
+  0x0B0, 0x094, 0x084, 0x076,
+  0x062, 0x04E, 0x042, 0x032,
+];
+
 #[derive(Debug)]
 pub struct Apu {
     cycle: u64,
@@ -58,6 +72,11 @@ pub struct Apu {
     envelope_counter: u8,
     is_sequencer_mode: bool,
     is_enable_irq: bool,
+    region: Region,
+    noise_period_table: &'static [u16; 16],
+    dmc_period_table: &'static [u16; 16],
+    noise_timer_period: u16,
+    dmc_timer_period: u16,
 }
 
 impl Apu {
@@ -69,6 +88,22 @@ impl Apu {
             envelope_counter: 0,
             is_sequencer_mode: false,
             is_enable_irq: false,
+            region: Region::Ntsc,
+            noise_period_table: &NOISE_TIMER_PERIOD_TABLE,
+            dmc_period_table: &DMC_TIMER_PERIOD_TABLE,
+            noise_timer_period: NOISE_TIMER_PERIOD_TABLE[0],
+            dmc_timer_period: DMC_TIMER_PERIOD_TABLE[0],
+        }
+    }
+    // frame counter period and timer period tables
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        if region == Region::Pal {
+            self.noise_period_table = &PAL_NOISE_TIMER_PERIOD_TABLE;
+            self.dmc_period_table = &PAL_DMC_TIMER_PERIOD_TABLE;
+        } else {
+            self.noise_period_table = &NOISE_TIMER_PERIOD_TABLE;
+            self.dmc_period_table = &DMC_TIMER_PERIOD_TABLE;
         }
     }
     pub fn read(&self, interrupts: &mut Interrupts, addr: u16) -> u8{
@@ -96,11 +131,15 @@ impl Apu {
             },
             // noise control register
             0x400C..=0x400F => {
-
+                if addr == 0x400E {
+                    self.noise_timer_period = self.noise_period_table[(data & 0x0F) as usize];
+                }
             },
             // DPCM control register
             0x4010..=0x4013 => {
-
+                if addr == 0x4010 {
+                    self.dmc_timer_period = self.dmc_period_table[(data & 0x0F) as usize];
+                }
             },
             // audio channel control register
             0x4015 => {
@@ -153,8 +192,9 @@ impl Apu {
     }
     pub fn run(&mut self, cycle: u64, interrupts: &mut Interrupts) {
         self.cycle += cycle;
-        if self.cycle >= CYCLE_240HZ as u64 {
-            self.cycle -= CYCLE_240HZ as u64;
+        let period: u64 = self.region.frame_counter_period();
+        if self.cycle >= period {
+            self.cycle -= period;
             if self.is_sequencer_mode {
                 self.update_mode1_sequence(interrupts);
             } else {
@@ -162,4 +202,24 @@ impl Apu {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn period_tables() {
+        let mut apu: Apu = Apu::new();
+        for (region, noise, dmc) in [
+            (Region::Ntsc, 0x0CA, 0x1AC),
+            (Region::Pal, 0x0BC, 0x18E),
+            (Region::Dendy, 0x0CA, 0x1AC),
+        ].iter() {
+            apu.set_region(*region);
+            apu.write(0x400E, 0x88);
+            apu.write(0x4010, 0x40);
+            assert_eq!((apu.noise_timer_period, apu.dmc_timer_period), (*noise, *dmc));
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use super::checksum::crc32;
+use super::inflate::inflate;
+
+/*
+    [Archive] roms in zip and gzip files, detected by the magic
+    | format | magic       | member                                   |
+    +--------+-------------+------------------------------------------+
+    | zip    | 50 4B 03 04 | given name, or first .nes/.nsf/.fds/.unf |
+    | gzip   | 1F 8B       | the single stream                        |
+    zip members are stored (0) or deflated (8), the crc32 of each
+    extracted member is checked. zip64 and encryption are not supported.
+*/
+
+pub const ROM_EXTENSIONS: [&str; 4] = ["nes", "nsf", "fds", "unf"];
+const ZIP_LOCAL: u32 = 0x04034B50;
+const ZIP_CENTRAL: u32 = 0x02014B50;
+const ZIP_END: u32 = 0x06054B50;
+const ZIP_END_SIZE: usize = 22;
+
+pub fn is_zip(data: &[u8]) -> bool {
+    data.len() >= 4 && u32le(data, 0) == ZIP_LOCAL
+}
+
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.len() >= 2 && data[0] == 0x1F && data[1] == 0x8B
+}
+
+fn u16le(data: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes([data[i], data[i + 1]])
+}
+
+fn u32le(data: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]])
+}
+
+fn check_crc32(name: &str, data: &[u8], expected: u32) -> Result<(), String> {
+    let actual: u32 = crc32(data);
+    if actual != expected {
+        return Err(format!("{}: crc32 {:08X}, expected {:08X}", name, actual, expected));
+    }
+    Ok(())
+}
+
+pub struct ZipEntry {
+    pub name: String,
+    method: u16,
+    crc32: u32,
+    compressed_size: usize,
+    size: usize,
+    local_offset: usize,
+}
+
+pub fn zip_entries(data: &[u8]) -> Result<Vec<ZipEntry>, String> {
+    // end of central directory, followed by a comment of up to 64KiB
+    let end: usize = (0..=data.len().saturating_sub(ZIP_END_SIZE)).rev()
+        .take(0x10000 + ZIP_END_SIZE)
+        .find(|i| u32le(data, *i) == ZIP_END)
+        .ok_or("zip end of central directory not found")?;
+    let count: usize = u16le(data, end + 10) as usize;
+    let mut p: usize = u32le(data, end + 16) as usize;
+    let mut entries: Vec<ZipEntry> = Vec::with_capacity(count);
+    for _ in 0..count {
+        if p + 46 > data.len() || u32le(data, p) != ZIP_CENTRAL {
+            return Err("invalid zip central directory".to_string());
+        }
+        let name_len: usize = u16le(data, p + 28) as usize;
+        let extra_len: usize = u16le(data, p + 30) as usize;
+        let comment_len: usize = u16le(data, p + 32) as usize;
+        if u16le(data, p + 8) & 0x01 > 0 {
+            return Err("encrypted zip is not supported".to_string());
+        }
+        let name_end: usize = (p + 46 + name_len).min(data.len());
+        entries.push(ZipEntry {
+            name: String::from_utf8_lossy(&data[p + 46..name_end]).to_string(),
+            method: u16le(data, p + 10),
+            crc32: u32le(data, p + 16),
+            compressed_size: u32le(data, p + 20) as usize,
+            size: u32le(data, p + 24) as usize,
+            local_offset: u32le(data, p + 42) as usize,
+        });
+        p += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+pub fn zip_extract(data: &[u8], entry: &ZipEntry) -> Result<Vec<u8>, String> {
+    let p: usize = entry.local_offset;
+    if p + 30 > data.len() || u32le(data, p) != ZIP_LOCAL {
+        return Err(format!("{}: invalid zip local header", entry.name));
+    }
+    let start: usize = p + 30 + u16le(data, p + 26) as usize + u16le(data, p + 28) as usize;
+    let end: usize = start + entry.compressed_size;
+    if end > data.len() {
+        return Err(format!("{}: zip member is truncated", entry.name));
+    }
+    let out: Vec<u8> = match entry.method {
+        0 => data[start..end].to_vec(),
+        8 => inflate(&data[start..end]).map_err(|e| format!("{}: {}", entry.name, e))?.0,
+        m => return Err(format!("{}: zip compression method {} is not supported", entry.name, m)),
+    };
+    if out.len() != entry.size {
+        return Err(format!("{}: {} bytes extracted, expected {}", entry.name, out.len(), entry.size));
+    }
+    check_crc32(&entry.name, &out, entry.crc32)?;
+    Ok(out)
+}
+
+// the named member, or the first one with a rom extension
+pub fn unzip_rom(data: &[u8], member: Option<&str>) -> Result<(String, Vec<u8>), String> {
+    let entries = zip_entries(data)?;
+    let entry = match member {
+        Some(name) => entries.iter().find(|e| e.name == name)
+            .ok_or(format!("{} not found in zip", name))?,
+        None => entries.iter().find(|e| {
+            let lower = e.name.to_lowercase();
+            ROM_EXTENSIONS.iter().any(|ext| lower.ends_with(&format!(".{}", ext)))
+        }).ok_or("no .nes, .nsf, .fds or .unf file in zip")?,
+    };
+    Ok((entry.name.clone(), zip_extract(data, entry)?))
+}
+
+pub fn gunzip(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < 18 || data[2] != 8 {
+        return Err("invalid gzip header".to_string());
+    }
+    let flags: u8 = data[3];
+    let mut p: usize = 10;
+    // FEXTRA
+    if flags & 0x04 > 0 {
+        p += 2 + u16le(data, p) as usize;
+    }
+    // FNAME, FCOMMENT
+    for flag in [0x08, 0x10].iter() {
+        if flags & flag > 0 {
+            while p < data.len() && data[p] != 0 {
+                p += 1;
+            }
+            p += 1;
+        }
+    }
+    // FHCRC
+    if flags & 0x02 > 0 {
+        p += 2;
+    }
+    if p >= data.len() {
+        return Err("gzip header is truncated".to_string());
+    }
+    let (out, used) = inflate(&data[p..])?;
+    let trailer: usize = p + used;
+    if trailer + 8 > data.len() {
+        return Err("gzip trailer is missing".to_string());
+    }
+    check_crc32("gzip", &out, u32le(data, trailer))?;
+    if u32le(data, trailer + 4) != out.len() as u32 {
+        return Err("gzip size mismatch".to_string());
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gunzip_text() {
+        // "hello hello hello hello\n", fixed huffman block with a back reference
+        let gz: [u8; 29] = [
+            0x1F, 0x8B, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xCB, 0x48,
+            0xCD, 0xC9, 0xC9, 0x57, 0xC8, 0x40, 0x27, 0xB9, 0x00, 0x00, 0x88, 0x59,
+            0x0B, 0x18, 0x00, 0x00, 0x00];
+        assert_eq!(gunzip(&gz).unwrap(), b"hello hello hello hello\n".to_vec());
+        let mut bad = gz;
+        bad[25] = 0x19;
+        assert!(gunzip(&bad).is_err());
+    }
+
+    // readme.txt deflated with dynamic huffman codes, first.nes stored,
+    // second.nes a deflated NROM image
+    const MEMBERS_ZIP: &[u8] = include_bytes!("testdata/members.zip");
+
+    #[test]
+    fn zip_members() {
+        let entries = zip_entries(MEMBERS_ZIP).unwrap();
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["readme.txt", "first.nes", "second.nes"]);
+        assert_eq!(entries.iter().map(|e| e.method).collect::<Vec<u16>>(), [8, 0, 8]);
+        // dynamic huffman block type in the first byte of the stream
+        let readme = &entries[0];
+        let start: usize = readme.local_offset + 30 + readme.name.len();
+        assert_eq!((MEMBERS_ZIP[start] >> 1) & 0x03, 2);
+        let text: Vec<u8> = zip_extract(MEMBERS_ZIP, readme).unwrap();
+        assert!(text.starts_with(b"rustnes archive test fixture\n"));
+        assert_eq!(text.len(), 224);
+
+        // the first rom by default, or the given member
+        assert_eq!(unzip_rom(MEMBERS_ZIP, None).unwrap(), ("first.nes".to_string(), (0..64).collect()));
+        let (name, rom) = unzip_rom(MEMBERS_ZIP, Some("second.nes")).unwrap();
+        assert_eq!((name.as_str(), rom.len()), ("second.nes", 16 + 0x4000 + 0x2000));
+        assert!(rom.starts_with(b"NES\x1A"));
+        assert_eq!(unzip_rom(MEMBERS_ZIP, Some("third.nes")).err().unwrap(), "third.nes not found in zip");
+
+        // a damaged stored member fails its crc32
+        let mut bad: Vec<u8> = MEMBERS_ZIP.to_vec();
+        let first = &entries[1];
+        bad[first.local_offset + 30 + first.name.len() + 5] ^= 0xFF;
+        assert_eq!(unzip_rom(&bad, None).err().unwrap(), "first.nes: crc32 0E883752, expected 100ECE8C");
+    }
+}
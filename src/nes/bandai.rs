@@ -0,0 +1,142 @@
+use super::cassette::{Header, Mirroring};
+use super::eeprom::{Eeprom, EepromType};
+
+/*
+    [Bandai FCG] mapper 16 and 159
+    | board   | mapper      | registers     | save         |
+    +---------+-------------+---------------+--------------+
+    | FCG-1/2 | 16.4        | $6000-$7FFF   | none         |
+    | LZ93D50 | 16.5        | $8000-$FFFF   | 24C02        |
+    | LZ93D50 | 159         | $8000-$FFFF   | 24C01        |
+    iNES mapper 16 without submapper decodes both ranges and has
+    a 24C02 when the battery bit is set.
+
+    [Registers] address & 0x0F
+    | reg | function                                              |
+    +-----+-------------------------------------------------------+
+    | 0-7 | 1KiB CHR bank of 0x0000-0x03FF .. 0x1C00-0x1FFF        |
+    |  8  | 16KiB PRG bank of $8000-$BFFF, $C000 is the last bank |
+    |  9  | mirroring 0 vertical, 1 horizontal, 2/3 single screen  |
+    |  A  | bit 0 IRQ enable, acknowledges the IRQ                |
+    |  B  | IRQ counter low                                       |
+    |  C  | IRQ counter high                                      |
+    |  D  | EEPROM bit 5 SCL, bit 6 SDA; read SDA at $6000 bit 4  |
+    the IRQ counter counts down every CPU cycle and fires when it
+    passes 0. LZ93D50 writes B/C to a latch copied by a write to A.
+*/
+
+pub enum FcgWrite {
+    CharBank(usize, usize),
+    Mirroring(Mirroring),
+    IrqAcknowledge,
+    None,
+}
+
+#[derive(Debug)]
+pub struct BandaiFcg {
+    prog_bank: u8,
+    irq_counter: u16,
+    irq_latch: u16,
+    is_irq_enabled: bool,
+    is_latched: bool,
+    is_low_decoded: bool,
+    is_high_decoded: bool,
+    pub eeprom: Option<Eeprom>,
+}
+
+impl BandaiFcg {
+    // None when the cassette is not a Bandai FCG board
+    pub fn new(header: &Header) -> Option<BandaiFcg> {
+        let (is_low, is_high, eeprom) = match (header.mapper, header.submapper) {
+            (16, 4) => (true, false, None),
+            (16, 5) => (false, true, Some(EepromType::C24C02)),
+            (16, _) => (true, true, if header.has_battery { Some(EepromType::C24C02) } else { None }),
+            (159, _) => (false, true, Some(EepromType::C24C01)),
+            _ => return None,
+        };
+        Some(BandaiFcg {
+            prog_bank: 0,
+            irq_counter: 0,
+            irq_latch: 0,
+            is_irq_enabled: false,
+            is_latched: is_high && !is_low,
+            is_low_decoded: is_low,
+            is_high_decoded: is_high,
+            eeprom: eeprom.map(Eeprom::new),
+        })
+    }
+
+    pub fn is_register(&self, addr: u16) -> bool {
+        match addr {
+            0x6000..=0x7FFF => self.is_low_decoded,
+            0x8000..=0xFFFF => self.is_high_decoded,
+            _ => false,
+        }
+    }
+
+    // $6000-$7FFF, open bus except the EEPROM data
+    pub fn read(&self) -> u8 {
+        match &self.eeprom {
+            Some(eeprom) => (eeprom.read() as u8) << 4,
+            None => 0,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) -> FcgWrite {
+        match addr & 0x0F {
+            reg @ 0x00..=0x07 => return FcgWrite::CharBank(reg as usize, data as usize),
+            0x08 => self.prog_bank = data & 0x0F,
+            0x09 => return FcgWrite::Mirroring(match data & 0x03 {
+                0 => Mirroring::Vertical,
+                1 => Mirroring::Horizontal,
+                2 => Mirroring::SingleScreenA,
+                _ => Mirroring::SingleScreenB,
+            }),
+            0x0A => {
+                self.is_irq_enabled = data & 0x01 > 0;
+                if self.is_latched {
+                    self.irq_counter = self.irq_latch;
+                }
+                return FcgWrite::IrqAcknowledge;
+            },
+            0x0B => {
+                self.irq_latch = (self.irq_latch & 0xFF00) | data as u16;
+                if !self.is_latched {
+                    self.irq_counter = self.irq_latch;
+                }
+            },
+            0x0C => {
+                self.irq_latch = (self.irq_latch & 0x00FF) | ((data as u16) << 8);
+                if !self.is_latched {
+                    self.irq_counter = self.irq_latch;
+                }
+            },
+            0x0D => {
+                if let Some(eeprom) = &mut self.eeprom {
+                    eeprom.write(data & 0x20 > 0, data & 0x40 > 0);
+                }
+            },
+            _ => (),
+        }
+        FcgWrite::None
+    }
+
+    pub fn get_prog_rom_addr(&self, addr: u16, prog_size: usize) -> usize {
+        let bank: usize = if addr < 0xC000 {
+            self.prog_bank as usize
+        } else {
+            prog_size / 0x4000 - 1
+        };
+        (bank * 0x4000 + (addr & 0x3FFF) as usize) % prog_size
+    }
+
+    // true when the IRQ fires
+    pub fn tick(&mut self, cycles: u64) -> bool {
+        if !self.is_irq_enabled {
+            return false;
+        }
+        let is_fired: bool = (self.irq_counter as u64) < cycles;
+        self.irq_counter = self.irq_counter.wrapping_sub(cycles as u16);
+        is_fired
+    }
+}
@@ -0,0 +1,46 @@
+use std::fs;
+use std::path::Path;
+
+/*
+    [Battery backup] <rom>.sav next to the rom, or <save dir>/<rom>.sav
+    raw battery backed PRG RAM, or the serial EEPROM of Bandai FCG
+    boards, without header. FDS disk writes are an IPS patch of the
+    image. it is flushed every FLUSH_INTERVAL frames
+    when written and on exit. the file is written to <path>.tmp and
+    renamed so a crash never leaves a half written save.
+*/
+
+// about 5 seconds
+pub const FLUSH_INTERVAL: u64 = 300;
+
+pub fn save_path(rom: &str, save_dir: Option<&str>) -> String {
+    let path = Path::new(rom).with_extension("sav");
+    match save_dir {
+        Some(dir) => {
+            let name = path.file_name()
+                .map_or("save.sav".to_string(), |n| n.to_string_lossy().to_string());
+            format!("{}/{}", dir, name)
+        },
+        None => path.to_string_lossy().to_string(),
+    }
+}
+
+// missing file is None
+pub fn load(path: &str) -> Result<Option<Vec<u8>>, String> {
+    match fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{}: {}", path, e)),
+    }
+}
+
+pub fn save(path: &str, data: &[u8]) -> Result<(), String> {
+    if let Some(dir) = Path::new(path).parent() {
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", path, e))?;
+        }
+    }
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, data).map_err(|e| format!("{}: {}", tmp, e))?;
+    fs::rename(&tmp, path).map_err(|e| format!("{}: {}", path, e))
+}
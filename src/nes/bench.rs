@@ -0,0 +1,126 @@
+use std::time::Instant;
+
+use super::apu::Apu;
+use super::cassette::*;
+use super::config::Config;
+use super::cpu::Cpu;
+use super::interrupts::Interrupts;
+use super::ppu::*;
+use super::ram::Ram;
+use super::render::Render;
+use super::script::InputScript;
+use super::{VRAM_SIZE, WRAM_SIZE};
+
+pub const BENCH_INSTRUCTIONS: u64 = 10_000_000;
+pub const BENCH_FRAMES: u32 = 600;
+
+/*
+    [Synthetic rom] NROM-128, mapped at 0xC000
+    C000  SEI / CLD / LDX #$FF / TXS
+    C005  fill name table 0x2000-0x23FF with 0x00..0xFF
+    C01C  fill palette 0x3F00-0x3F1F with 0x00..0x1F
+    C030  enable bg and sprite, enable NMI
+    C03A  loop: inc $00, store to $0200,X, busy wait, JMP loop
+    C04D  nmi: sprite DMA from $0200, RTI
+*/
+const SYNTHETIC_PROGRAM: [u8; 0x53] = [
+    0x78, 0xD8, 0xA2, 0xFF, 0x9A,
+    0xA9, 0x20, 0x8D, 0x06, 0x20, 0xA9, 0x00, 0x8D, 0x06, 0x20,
+    0xA2, 0x00, 0xA0, 0x04,
+    0x8E, 0x07, 0x20, 0xE8, 0xD0, 0xFA, 0x88, 0xD0, 0xF7,
+    0xA9, 0x3F, 0x8D, 0x06, 0x20, 0xA9, 0x00, 0x8D, 0x06, 0x20,
+    0xA2, 0x00,
+    0x8E, 0x07, 0x20, 0xE8, 0xE0, 0x20, 0xD0, 0xF8,
+    0xA9, 0x1E, 0x8D, 0x01, 0x20, 0xA9, 0x80, 0x8D, 0x00, 0x20,
+    0xA5, 0x00, 0x18, 0x69, 0x01, 0x85, 0x00, 0xAA, 0x9D, 0x00, 0x02,
+    0xA0, 0x10, 0x88, 0xD0, 0xFD,
+    0x4C, 0x3A, 0xC0,
+    0xA9, 0x02, 0x8D, 0x14, 0x40, 0x40,
+];
+const SYNTHETIC_NMI: u16 = 0xC04D;
+const SYNTHETIC_RESET: u16 = 0xC000;
+const SYNTHETIC_IRQ: u16 = 0xC052;
+
+pub fn synthetic_rom() -> Vec<u8> {
+    let mut buf: Vec<u8> = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00];
+    buf.resize(NES_HSIZE, 0);
+
+    let mut prog: Vec<u8> = vec![0xEA; PROG_ROM_UNIT_SIZE];
+    prog[..SYNTHETIC_PROGRAM.len()].copy_from_slice(&SYNTHETIC_PROGRAM);
+    for (i, v) in [SYNTHETIC_NMI, SYNTHETIC_RESET, SYNTHETIC_IRQ].iter().enumerate() {
+        prog[0x3FFA + 2 * i] = (v & 0xFF) as u8;
+        prog[0x3FFB + 2 * i] = (v >> 8) as u8;
+    }
+    buf.extend(prog);
+
+    // pseudo random patterns so that every tile has opaque pixels
+    let mut seed: u32 = 0x12345678;
+    for _ in 0..CHAR_ROM_UNIT_SIZE {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        buf.push(seed as u8);
+    }
+    buf
+}
+
+pub fn synthetic_cassette() -> Cassette {
+    Cassette::from_bytes("synthetic", synthetic_rom()).unwrap()
+}
+
+// instructions per second of the cpu core alone
+pub fn bench_cpu(cas: &Cassette, instructions: u64) -> f64 {
+    let mut wram: Ram = Ram::new(WRAM_SIZE);
+    let mut vram: Ram = Ram::new(VRAM_SIZE);
+    let mut interrupts: Interrupts = Interrupts::new();
+    let mut apu: Apu = Apu::new();
+    let mut ppu: Ppu = Ppu::new(cas, &mut vram);
+    let mut cpu: Cpu = Cpu::new(cas, &mut wram);
+
+    cpu.reset(&mut ppu, &mut apu, &mut interrupts);
+    let start = Instant::now();
+    for _ in 0..instructions {
+        cpu.run(&mut ppu, &mut apu, &mut interrupts);
+    }
+    instructions as f64 / start.elapsed().as_secs_f64()
+}
+
+// frames per second of headless emulation, optionally with Render conversion
+pub fn bench_frames(cas: &Cassette, frames: u32, with_render: bool) -> f64 {
+    let mut render: Render = Render::new();
+    let start = Instant::now();
+    super::run_headless(cas, &Config::new(), frames, &InputScript::new(), |_, image, _| {
+        if with_render {
+            render.render(image);
+        }
+    });
+    frames as f64 / start.elapsed().as_secs_f64()
+}
+
+pub fn run(cassette_path: Option<&str>) {
+    let cas: Cassette = match cassette_path {
+        Some(path) => match Cassette::new(path) {
+            Ok(cas) => cas,
+            Err(e) => panic!("{}", e),
+        },
+        None => synthetic_cassette(),
+    };
+    let ips = bench_cpu(&cas, BENCH_INSTRUCTIONS);
+    println!("cpu:           {:>12.0} instructions/s", ips);
+    let fps = bench_frames(&cas, BENCH_FRAMES, false);
+    println!("ppu:           {:>12.1} frames/s", fps);
+    let fps = bench_frames(&cas, BENCH_FRAMES, true);
+    println!("ppu + render:  {:>12.1} frames/s", fps);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn synthetic_rom_runs() {
+        let cas = synthetic_cassette();
+        assert!(bench_cpu(&cas, 10_000) > 0.0);
+        assert!(bench_frames(&cas, 2, true) > 0.0);
+    }
+}
@@ -1,69 +1,499 @@
 use std::fs::File;
 use std::io::prelude::*;
-use std::str;
+
+use super::archive;
+use super::checksum::{crc32, crc32_update, sha1, to_hex};
+use super::fds;
+use super::gamedb::{self, GameInfo};
+use super::patch;
+use super::unif;
+use super::vs_system::VsPpu;
 
 pub const PROG_ROM_MAX_SIZE: usize = 0x8000;
 pub const CHAR_ROM_MAX_SIZE: usize = 0x2000;
 pub const PROG_ROM_UNIT_SIZE: usize = 0x4000;
 pub const CHAR_ROM_UNIT_SIZE: usize = 0x2000;
 pub const NES_HSIZE: usize = 0x0010;
+pub const TRAINER_SIZE: usize = 0x0200;
+
+/*
+    [iNES / NES 2.0 header]
+    | byte | description                                          |
+    +------+------------------------------------------------------+
+    |  0-3 | "NES" 0x1A                                           |
+    |   4  | PRG ROM size, 16KiB unit (lsb on NES 2.0)            |
+    |   5  | CHR ROM size, 8KiB unit (lsb on NES 2.0)             |
+    |   6  | bit 4-7 mapper 0-3, bit 3 four screen, bit 2 trainer |
+    |      | bit 1 battery, bit 0 vertical mirroring              |
+    |   7  | bit 4-7 mapper 4-7, bit 2-3 0x02 means NES 2.0       |
+    |      | bit 0-1 console type                                 |
+    |   8  | NES 2.0: bit 4-7 submapper, bit 0-3 mapper 8-11      |
+    |      | iNES: PRG RAM size, 8KiB unit                        |
+    |   9  | NES 2.0: bit 4-7 CHR ROM msb, bit 0-3 PRG ROM msb    |
+    |      | iNES: bit 0 PAL                                      |
+    |  10  | NES 2.0: bit 4-7 PRG NVRAM, bit 0-3 PRG RAM (64<<n)  |
+    |  11  | NES 2.0: bit 4-7 CHR NVRAM, bit 0-3 CHR RAM (64<<n)  |
+    |  12  | NES 2.0: bit 0-1 timing                              |
+    |  13  | NES 2.0: Vs. PPU and hardware type, extended console |
+    |  14  | NES 2.0: bit 0-1 misc roms                           |
+    |  15  | NES 2.0: bit 0-5 default expansion device            |
+
+    msb 0x0F of a rom size means exponent form, lsb = EEEEEEMM,
+    size = 2^E * (MM * 2 + 1). an iNES header with garbage in byte
+    7-15 such as "DiskDude!" is archaic, only the low mapper nibble
+    is used.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeaderFormat {
+    Archaic,
+    INes,
+    Nes20,
+    // no header, built from UNIF chunks
+    Unif,
+    // disk image, PRG ROM is the BIOS
+    Fds,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    PlayChoice10,
+    // NES 2.0 byte 13 bit 0-3
+    Extended(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mirroring {
+    // 0x2000 = 0x2400, 0x2800 = 0x2C00
+    Horizontal,
+    // 0x2000 = 0x2800, 0x2400 = 0x2C00
+    Vertical,
+    // all name tables on the lower/upper 1KiB of VRAM
+    SingleScreenA,
+    SingleScreenB,
+    // cartridge provides 2KiB extra VRAM
+    FourScreen,
+}
+
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub format: HeaderFormat,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub prog_rom_size: usize,
+    pub char_rom_size: usize,
+    pub prog_ram_size: usize,
+    pub prog_nvram_size: usize,
+    pub char_ram_size: usize,
+    pub char_nvram_size: usize,
+    pub mirroring: Mirroring,
+    pub has_battery: bool,
+    pub has_trainer: bool,
+    pub console_type: ConsoleType,
+    pub timing: Timing,
+    // NES 2.0 byte 13 of Vs. System
+    pub vs_ppu_type: u8,
+    pub vs_hardware_type: u8,
+    pub misc_roms: u8,
+    pub expansion_device: u8,
+    // "DiskDude!" found in byte 7-15
+    pub is_disk_dude: bool,
+}
+
+// NES 2.0 rom size from lsb and msb nibble, the exponent form goes up to 2^63 * 7
+fn nes20_rom_size(lsb: u8, msb: u8, unit: usize) -> Result<usize, String> {
+    let size: Option<usize> = if msb == 0x0F {
+        1usize.checked_shl((lsb >> 2) as u32)
+            .and_then(|n| n.checked_mul((lsb & 0x03) as usize * 2 + 1))
+    } else {
+        ((msb as usize) << 8 | lsb as usize).checked_mul(unit)
+    };
+    size.ok_or_else(|| format!("NES 2.0 rom size {:#04X} {:#X} does not fit in memory", lsb, msb))
+}
+
+// NES 2.0 ram size, 0 is none and n is 64 << n bytes
+fn nes20_ram_size(shift: u8) -> usize {
+    if shift == 0 { 0 } else { 64 << shift }
+}
+
+impl Header {
+    pub fn parse(buf: &[u8]) -> Result<Header, String> {
+        if buf.len() < NES_HSIZE {
+            return Err(format!("file is {} bytes, shorter than the header", buf.len()));
+        }
+        if buf[0..4] != [0x4E, 0x45, 0x53, 0x1A] {
+            return Err("invalid rom file, no NES header".to_string());
+        }
+        let is_disk_dude = &buf[7..15] == b"DiskDude";
+        let format = if buf[7] & 0x0C == 0x08 {
+            HeaderFormat::Nes20
+        } else if buf[7] & 0x0C == 0 && buf[12..16] == [0, 0, 0, 0] {
+            HeaderFormat::INes
+        } else {
+            HeaderFormat::Archaic
+        };
+        // archaic headers only keep byte 0-6
+        let b = |i: usize| if format == HeaderFormat::Archaic && i >= 7 { 0 } else { buf[i] };
+
+        let mirroring = if b(6) & 0x08 > 0 {
+            Mirroring::FourScreen
+        } else if b(6) & 0x01 > 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        let mut mapper: u16 = ((b(6) >> 4) | (b(7) & 0xF0)) as u16;
+        let console_type = match b(7) & 0x03 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::PlayChoice10,
+            _ => ConsoleType::Extended(b(13) & 0x0F),
+        };
+        let mut header = Header {
+            format,
+            mapper,
+            submapper: 0,
+            prog_rom_size: b(4) as usize * PROG_ROM_UNIT_SIZE,
+            char_rom_size: b(5) as usize * CHAR_ROM_UNIT_SIZE,
+            // iNES assumes 8KiB of PRG RAM even when byte 8 is 0
+            prog_ram_size: (b(8).max(1)) as usize * 0x2000,
+            prog_nvram_size: 0,
+            char_ram_size: if b(5) == 0 { CHAR_ROM_UNIT_SIZE } else { 0 },
+            char_nvram_size: 0,
+            mirroring,
+            has_battery: b(6) & 0x02 > 0,
+            has_trainer: b(6) & 0x04 > 0,
+            console_type,
+            timing: if b(9) & 0x01 > 0 { Timing::Pal } else { Timing::Ntsc },
+            vs_ppu_type: 0,
+            vs_hardware_type: 0,
+            misc_roms: 0,
+            expansion_device: 0,
+            is_disk_dude,
+        };
+        if header.has_battery && format != HeaderFormat::Nes20 {
+            // iNES battery covers the whole PRG RAM
+            header.prog_nvram_size = header.prog_ram_size;
+            header.prog_ram_size = 0;
+        }
+        if format == HeaderFormat::Nes20 {
+            mapper |= ((b(8) & 0x0F) as u16) << 8;
+            header.mapper = mapper;
+            header.submapper = b(8) >> 4;
+            header.prog_rom_size = nes20_rom_size(b(4), b(9) & 0x0F, PROG_ROM_UNIT_SIZE)?;
+            header.char_rom_size = nes20_rom_size(b(5), b(9) >> 4, CHAR_ROM_UNIT_SIZE)?;
+            header.prog_ram_size = nes20_ram_size(b(10) & 0x0F);
+            header.prog_nvram_size = nes20_ram_size(b(10) >> 4);
+            header.char_ram_size = nes20_ram_size(b(11) & 0x0F);
+            header.char_nvram_size = nes20_ram_size(b(11) >> 4);
+            header.timing = match b(12) & 0x03 {
+                0 => Timing::Ntsc,
+                1 => Timing::Pal,
+                2 => Timing::MultiRegion,
+                _ => Timing::Dendy,
+            };
+            if console_type == ConsoleType::VsSystem {
+                header.vs_ppu_type = b(13) & 0x0F;
+                header.vs_hardware_type = b(13) >> 4;
+            }
+            header.misc_roms = b(14) & 0x03;
+            header.expansion_device = b(15) & 0x3F;
+        }
+        Ok(header)
+    }
+}
+
+// how a rom file is turned into a Cassette
+#[derive(Debug, Clone)]
+pub struct LoadOptions {
+    // keep the header even when the rom database knows better
+    pub is_db_disabled: bool,
+    // zip member to load instead of the first rom in it
+    pub member: Option<String>,
+    // applied in order, <rom>.ips/.ups/.bps next to the rom when empty
+    pub patches: Vec<String>,
+    // disksys.rom next to the image or in the current directory when None
+    pub fds_bios: Option<String>,
+    // Vs. System PPU instead of NES 2.0 byte 13, iNES headers have none
+    pub vs_ppu: Option<VsPpu>,
+}
+
+impl LoadOptions {
+    pub fn new() -> LoadOptions {
+        LoadOptions {
+            is_db_disabled: false,
+            member: None,
+            patches: Vec::new(),
+            fds_bios: None,
+            vs_ppu: None,
+        }
+    }
+}
+
+impl Default for LoadOptions {
+    fn default() -> LoadOptions {
+        LoadOptions::new()
+    }
+}
 
 #[derive(Debug)]
 pub struct Cassette {
     path: String,
     rom_size: u64,
-    pub is_horizontal_mirror: bool,
-    pub mapper: u8,
+    pub header: Header,
+    pub mirroring: Mirroring,
+    pub mapper: u16,
     pub prog_size: usize,
     pub char_size: usize,
     pub prog_rom: Vec<u8>,
-    pub char_rom: Vec<u8>
+    pub char_rom: Vec<u8>,
+    // crc32 and sha1 of prog rom and char rom, without header
+    pub crc32: u32,
+    pub sha1: String,
+    // rom database entry which overrode the header
+    pub game: Option<GameInfo>,
+    // loaded into 0x7000-0x71FF
+    pub trainer: Option<Vec<u8>>,
+    // FDS sides without fwNES header
+    pub disk: Option<Vec<u8>>,
 }
 
 impl Cassette {
-    pub fn new(path: &str) -> Cassette{
-        let mut f = File::open(path).expect("file not found");
+    pub fn new(path: &str) -> Result<Cassette, String> {
+        Cassette::load(path, &LoadOptions::new())
+    }
+
+    pub fn load(path: &str, options: &LoadOptions) -> Result<Cassette, String> {
+        let mut f = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
 
         let mut buf = Vec::new();
-        let size = f.read_to_end(&mut buf);
+        f.read_to_end(&mut buf).map_err(|e| format!("{}: {}", path, e))?;
+        if archive::is_gzip(&buf) {
+            buf = archive::gunzip(&buf).map_err(|e| format!("{}: {}", path, e))?;
+        } else if archive::is_zip(&buf) {
+            let (name, data) = archive::unzip_rom(&buf, options.member.as_deref())
+                .map_err(|e| format!("{}: {}", path, e))?;
+            println!("zip member: {}", name);
+            buf = data;
+        }
+        let patches: Vec<String> = if options.patches.is_empty() {
+            patch::find_patches(path)
+        } else {
+            options.patches.clone()
+        };
+        buf = patch::apply_files(&buf, &patches)?;
+        if fds::is_fds(&buf) {
+            let bios: Vec<u8> = Cassette::load_fds_bios(path, options)?;
+            return Cassette::from_fds(path, &buf, bios).map_err(|e| format!("{}: {}", path, e));
+        }
 
-        let temp = &buf[0..3].to_vec();
-        let magic = str::from_utf8(temp).unwrap();
-        if magic != "NES" {
-            panic!("invalid rom file!");
+        let mut cas = Cassette::from_bytes(path, buf).map_err(|e| format!("{}: {}", path, e))?;
+        if !options.is_db_disabled {
+            cas.apply_database(&gamedb::load());
         }
-        let prog_size: usize = (buf[4] as usize) * PROG_ROM_UNIT_SIZE;
-        let char_size: usize = (buf[5] as usize) * CHAR_ROM_UNIT_SIZE;
-        let prog_rom_s: usize = NES_HSIZE;
+        cas.apply_vs_ppu(options.vs_ppu);
+        Ok(cas)
+    }
+
+    // override the PPU of a Vs. System rom, iNES dumps fall back to RP2C03
+    pub fn apply_vs_ppu(&mut self, vs_ppu: Option<VsPpu>) {
+        match (self.header.console_type, vs_ppu) {
+            (ConsoleType::VsSystem, Some(vs_ppu)) => self.header.vs_ppu_type = vs_ppu.ppu_type(),
+            (ConsoleType::VsSystem, None) if self.header.format != HeaderFormat::Nes20 => {
+                println!("warning: iNES header has no Vs. PPU type, RP2C03 is assumed, see --vs-ppu");
+            },
+            (_, Some(_)) => println!("warning: --vs-ppu is ignored, not a Vs. System rom"),
+            _ => (),
+        }
+    }
+
+    // override the header by the entry of the same crc32/sha1
+    pub fn apply_database(&mut self, games: &[GameInfo]) {
+        let game = match gamedb::find(games, self.crc32, &self.sha1) {
+            Some(game) => game.clone(),
+            None => return,
+        };
+        println!("database: {}, board: {}, region: {:?}", game.title, game.board, game.timing);
+        for change in game.apply(&mut self.header) {
+            println!("database: {}", change);
+        }
+        self.mapper = self.header.mapper;
+        self.mirroring = self.header.mirroring;
+        self.game = Some(game);
+    }
+
+    pub fn from_bytes(path: &str, buf: Vec<u8>) -> Result<Cassette, String> {
+        if unif::is_unif(&buf) {
+            return Cassette::from_unif(path, &buf);
+        }
+        let header: Header = Header::parse(&buf)?;
+        if header.format == HeaderFormat::Archaic {
+            println!("archaic iNES header{}, byte 7-15 are ignored",
+                if header.is_disk_dude { " polluted by \"DiskDude!\"" } else { "" });
+        }
+        let prog_size: usize = header.prog_rom_size;
+        let char_size: usize = header.char_rom_size;
+        if prog_size == 0 {
+            return Err("header declares no PRG ROM".to_string());
+        }
+        let trainer_size: usize = if header.has_trainer { TRAINER_SIZE } else { 0 };
+        let expected: usize = (NES_HSIZE + trainer_size).checked_add(prog_size)
+            .and_then(|n| n.checked_add(char_size))
+            .ok_or_else(|| format!("header declares {} + {} bytes of PRG and CHR ROM", prog_size, char_size))?;
+        if buf.len() < expected {
+            return Err(format!(
+                "file is {} bytes, header declares {} bytes \
+                (header {} + trainer {} + PRG {} + CHR {}), {} bytes missing",
+                buf.len(), expected, NES_HSIZE, trainer_size, prog_size, char_size,
+                expected - buf.len()));
+        }
+        if buf.len() > expected {
+            // PlayChoice-10 INST-ROM/PROM and NES 2.0 misc roms follow CHR ROM
+            let is_expected = header.misc_roms > 0 ||
+                header.console_type == ConsoleType::PlayChoice10;
+            if !is_expected {
+                println!("warning: file is {} bytes, header declares {} bytes, \
+                    {} bytes after CHR ROM are ignored",
+                    buf.len(), expected, buf.len() - expected);
+            }
+        }
+        let trainer: Option<Vec<u8>> = if header.has_trainer {
+            Some(buf[NES_HSIZE..NES_HSIZE + TRAINER_SIZE].to_vec())
+        } else {
+            None
+        };
+        let prog_rom_s: usize = NES_HSIZE + trainer_size;
         let char_rom_s: usize = prog_rom_s + prog_size;
         let prog_rom: Vec<u8> = buf[prog_rom_s..(prog_rom_s + prog_size)].to_vec();
         let char_rom: Vec<u8> = buf[char_rom_s..(char_rom_s + char_size)].to_vec();
 
-        let metadata = std::fs::metadata(path);
-        let rom_size: u64 = metadata.unwrap().len();
-
-        let is_horizontal_mirror = (buf[6] & 0x01) != 0x01;
-        let mapper = ((buf[6] & 0xF0) >> 4) | buf[7] & 0xF0;
-
         println!("prog rom [{:#06X}:{:#06X}], {:#X}",
             prog_rom_s, prog_rom_s + prog_size, prog_size);
         println!("char rom [{:#06X}:{:#06X}], {:#X}",
             char_rom_s, char_rom_s + char_size, char_size);
-        println!("is_horizontal_mirror: {:?}", is_horizontal_mirror);
-        println!("mapper: {:?}", mapper);
+        Ok(Cassette::build(path, buf.len() as u64, header, prog_rom, char_rom, trainer))
+    }
+
+    pub fn from_unif(path: &str, buf: &[u8]) -> Result<Cassette, String> {
+        let unif = unif::parse(buf)?;
+        println!("unif board: {}, name: {}", unif.board, unif.name.as_deref().unwrap_or("-"));
+        Ok(Cassette::build(path, buf.len() as u64, unif.header, unif.prog_rom, unif.char_rom, None))
+    }
+
+    fn load_fds_bios(path: &str, options: &LoadOptions) -> Result<Vec<u8>, String> {
+        let candidates: Vec<String> = match &options.fds_bios {
+            Some(bios) => vec![bios.clone()],
+            None => vec![
+                std::path::Path::new(path).with_file_name("disksys.rom").to_string_lossy().to_string(),
+                "disksys.rom".to_string(),
+            ],
+        };
+        for bios in candidates.iter() {
+            if let Ok(data) = std::fs::read(bios) {
+                if data.len() < fds::BIOS_SIZE {
+                    return Err(format!("{}: BIOS is {} bytes, expected {}", bios, data.len(), fds::BIOS_SIZE));
+                }
+                println!("fds bios: {}", bios);
+                // the last 8KiB, some dumps have a header
+                return Ok(data[data.len() - fds::BIOS_SIZE..].to_vec());
+            }
+        }
+        Err(format!("FDS BIOS not found, tried {}", candidates.join(", ")))
+    }
+
+    pub fn from_fds(path: &str, buf: &[u8], bios: Vec<u8>) -> Result<Cassette, String> {
+        let disk: Vec<u8> = fds::parse(buf)?;
+        println!("fds sides: {}", disk.len() / fds::SIDE_SIZE);
+        let header = Header {
+            format: HeaderFormat::Fds,
+            mapper: 20,
+            submapper: 0,
+            prog_rom_size: bios.len(),
+            char_rom_size: 0,
+            prog_ram_size: fds::RAM_SIZE,
+            prog_nvram_size: 0,
+            char_ram_size: CHAR_ROM_UNIT_SIZE,
+            char_nvram_size: 0,
+            mirroring: Mirroring::Horizontal,
+            has_battery: false,
+            has_trainer: false,
+            console_type: ConsoleType::Nes,
+            timing: Timing::Ntsc,
+            vs_ppu_type: 0,
+            vs_hardware_type: 0,
+            misc_roms: 0,
+            expansion_device: 0,
+            is_disk_dude: false,
+        };
+        let mut cas = Cassette::build(path, buf.len() as u64, header, bios, Vec::new(), None);
+        // identify the disk, not the BIOS
+        cas.crc32 = crc32(&disk);
+        cas.sha1 = to_hex(&sha1(&disk));
+        cas.disk = Some(disk);
+        Ok(cas)
+    }
 
-        println!("{:?}", &buf[0..16]);
+    fn build(
+        path: &str,
+        rom_size: u64,
+        header: Header,
+        prog_rom: Vec<u8>,
+        char_rom: Vec<u8>,
+        trainer: Option<Vec<u8>>
+    ) -> Cassette {
+        let mirroring = header.mirroring;
+        let mapper = header.mapper;
+        let prog_size: usize = prog_rom.len();
+        let char_size: usize = char_rom.len();
+
+        println!("mirroring: {:?}", mirroring);
+        println!("mapper: {}.{}", mapper, header.submapper);
+        println!("format: {:?}, console: {:?}, timing: {:?}",
+            header.format, header.console_type, header.timing);
+        println!("prg ram: {:#X}, prg nvram: {:#X}, chr ram: {:#X}, chr nvram: {:#X}",
+            header.prog_ram_size, header.prog_nvram_size,
+            header.char_ram_size, header.char_nvram_size);
+        println!("battery: {}, trainer: {}, expansion: {:#04X}",
+            header.has_battery, header.has_trainer, header.expansion_device);
 
         Cassette {
             path: path.to_string(),
             rom_size: rom_size,
-            is_horizontal_mirror: is_horizontal_mirror,
+            header,
+            mirroring,
             mapper: mapper,
             prog_size: prog_size,
             char_size: char_size,
+            crc32: crc32_update(crc32(&prog_rom), &char_rom),
+            sha1: to_hex(&sha1(&[&prog_rom[..], &char_rom[..]].concat())),
+            game: None,
             prog_rom: prog_rom,
-            char_rom: char_rom
-        }        
+            char_rom,
+            trainer,
+            disk: None,
+        }
+    }
+
+    // file name without directory and extension, game.nes.gz is game
+    pub fn name(&self) -> String {
+        let path = std::path::Path::new(&self.path);
+        let stem = path.file_stem()
+            .map_or(self.path.clone(), |s| s.to_string_lossy().to_string());
+        match stem.rsplit_once('.') {
+            Some((name, ext)) if archive::ROM_EXTENSIONS.contains(&ext.to_lowercase().as_str()) =>
+                name.to_string(),
+            _ => stem,
+        }
     }
 
     pub fn prog_rom_read(&self, addr: u16) -> u8 {
@@ -73,4 +503,95 @@ impl Cassette {
     pub fn char_rom_read(&self, addr: u16) -> u8 {
         self.char_rom[addr as usize]
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(bytes: [u8; 12]) -> Header {
+        let mut buf: Vec<u8> = vec![0x4E, 0x45, 0x53, 0x1A];
+        buf.extend_from_slice(&bytes);
+        Header::parse(&buf).unwrap()
+    }
+
+    #[test]
+    fn parse_headers() {
+        // iNES, mapper 1 with battery and trainer
+        let h = header([0x08, 0x00, 0x16, 0x00, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(h.format, HeaderFormat::INes);
+        assert_eq!((h.mapper, h.has_battery, h.has_trainer), (1, true, true));
+        assert_eq!((h.prog_rom_size, h.char_ram_size, h.prog_nvram_size), (0x20000, 0x2000, 0x2000));
+
+        // NES 2.0, mapper 0x105.2, exponent PRG size, Vs. System, Dendy
+        let h = header([0x1A, 0x02, 0x51, 0x09, 0x21, 0x0F, 0x71, 0x07, 0x03, 0x34, 0x01, 0x02]);
+        assert_eq!(h.format, HeaderFormat::Nes20);
+        assert_eq!((h.mapper, h.submapper), (0x105, 2));
+        assert_eq!(h.prog_rom_size, (1 << 6) * 5);
+        assert_eq!(h.char_rom_size, 0x02 * CHAR_ROM_UNIT_SIZE);
+        assert_eq!((h.prog_ram_size, h.prog_nvram_size), (0x80, 0x2000));
+        assert_eq!((h.char_ram_size, h.char_nvram_size), (0x2000, 0));
+        assert_eq!((h.console_type, h.timing), (ConsoleType::VsSystem, Timing::Dendy));
+        assert_eq!((h.vs_ppu_type, h.vs_hardware_type), (4, 3));
+        assert_eq!((h.misc_roms, h.expansion_device), (1, 2));
+
+        // DiskDude! in byte 7-15 must not leak into the mapper
+        let mut bytes = [0x02, 0x01, 0x41, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        bytes[3..12].copy_from_slice(b"DiskDude!");
+        let h = header(bytes);
+        assert_eq!(h.format, HeaderFormat::Archaic);
+        assert!(h.is_disk_dude);
+        assert_eq!((h.mapper, h.console_type), (4, ConsoleType::Nes));
+    }
+
+    #[test]
+    fn database_overrides_header() {
+        // mapper 1 and horizontal in the header
+        let mut buf: Vec<u8> = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        buf.extend(vec![0x00; 0x4000]);
+        let mut cas = Cassette::from_bytes("t", buf).unwrap();
+        let text = format!("{:08X} - 0.0 V 0 0 0 8192 NTSC NES-NROM-128 test", cas.crc32);
+        cas.apply_database(&gamedb::parse(&text).unwrap());
+        assert_eq!((cas.mapper, cas.mirroring), (0, Mirroring::Vertical));
+        assert_eq!(cas.game.unwrap().board, "NES-NROM-128");
+    }
+
+    #[test]
+    fn trainer_and_size_check() {
+        // 16K PRG, 8K CHR, trainer
+        let mut buf: Vec<u8> = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        buf.extend(vec![0xAA; TRAINER_SIZE]);
+        buf.extend(vec![0x11; 0x4000]);
+        buf.extend(vec![0x22; 0x2000]);
+        let cas = Cassette::from_bytes("t", buf.clone()).unwrap();
+        assert_eq!(cas.trainer, Some(vec![0xAA; TRAINER_SIZE]));
+        assert_eq!((cas.prog_rom[0], cas.char_rom[0]), (0x11, 0x22));
+
+        buf.truncate(buf.len() - 1);
+        let e = Cassette::from_bytes("t", buf).err().unwrap();
+        assert!(e.contains("25104 bytes") && e.contains("1 bytes missing"), "{}", e);
+    }
+
+    #[test]
+    fn huge_nes20_sizes() {
+        assert!(nes20_rom_size(0xFF, 0x0F, PROG_ROM_UNIT_SIZE).is_err());
+        assert_eq!(nes20_rom_size(0xFC, 0x0F, PROG_ROM_UNIT_SIZE), Ok(1 << 63));
+        // each size fits but the sum does not
+        let mut buf: Vec<u8> = vec![0x4E, 0x45, 0x53, 0x1A, 0xFC, 0xFC, 0x00, 0x08, 0, 0xFF, 0, 0, 0, 0, 0, 0];
+        buf.extend(vec![0x00; 0x4000]);
+        let e = Cassette::from_bytes("t", buf.clone()).err().unwrap();
+        assert!(e.starts_with("header declares"), "{}", e);
+        buf[4] = 0xFF;
+        assert!(Header::parse(&buf).is_err());
+    }
+
+    #[test]
+    fn zip_member_option() {
+        let mut options = LoadOptions::new();
+        options.is_db_disabled = true;
+        // first.nes, the default member, is not a rom
+        assert!(Cassette::load("src/nes/testdata/members.zip", &options).is_err());
+        options.member = Some("second.nes".to_string());
+        let cas = Cassette::load("src/nes/testdata/members.zip", &options).unwrap();
+        assert_eq!((cas.mapper, cas.prog_size, cas.char_size), (0, 0x4000, 0x2000));
+    }
+}
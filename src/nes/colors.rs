@@ -0,0 +1,327 @@
+use std::f64::consts::PI;
+use std::fs;
+
+/*
+    [Color table]
+    512 RGB colors indexed by the screen pixel of Image,
+    emphasis bits 8-6 and color index bits 5-0.
+
+    [.pal file]
+    | size | description                                 |
+    +------+---------------------------------------------+
+    |  192 | 64 RGB triplets, emphasis is generated      |
+    | 1536 | 8 emphasis x 64 RGB triplets                |
+
+    [Presets]
+    default  table used since the first release
+    2c02     NTSC PPU, generated with NtscParams::new()
+    2c03     RGB PPU of Vs. System and PlayChoice
+    2c07     PAL PPU, generated with NtscParams::pal()
+
+    generated presets take overrides, e.g. 2c02:hue=-5,gamma=2.0
+    keys are hue, saturation, contrast, brightness and gamma.
+    on PAL and Dendy the red and green emphasis bits are swapped,
+    except for 2c07, 2c03 and full 1536 byte .pal files which are
+    made for their own PPU.
+*/
+
+pub const COLOR_NUM: usize = 64;
+pub const TABLE_SIZE: usize = 8 * COLOR_NUM;
+
+const DEFAULT_COLORS: [u64; COLOR_NUM] = [
+    0x808080, 0x003DA6, 0x0012B0, 0x440096,
+    0xA1005E, 0xC70028, 0xBA0600, 0x8C1700,
+    0x5C2F00, 0x104500, 0x054A00, 0x00472E,
+    0x004166, 0x000000, 0x050505, 0x050505,
+    0xC7C7C7, 0x0077FF, 0x2155FF, 0x8237FA,
+    0xEB2FB5, 0xFF2950, 0xFF2200, 0xD63200,
+    0xC46200, 0x358000, 0x058F00, 0x008A55,
+    0x0099CC, 0x212121, 0x090909, 0x090909,
+    0xFFFFFF, 0x0FD7FF, 0x69A2FF, 0xD480FF,
+    0xFF45F3, 0xFF618B, 0xFF8833, 0xFF9C12,
+    0xFABC20, 0x9FE30E, 0x2BF035, 0x0CF0A4,
+    0x05FBFF, 0x5E5E5E, 0x0D0D0D, 0x0D0D0D,
+    0xFFFFFF, 0xA6FCFF, 0xB3ECFF, 0xDAABEB,
+    0xFFA8F9, 0xFFABB3, 0xFFD2B0, 0xFFEFA6,
+    0xFFF79C, 0xD7E895, 0xA6EDAF, 0xA2F2DA,
+    0x99FFFC, 0xDDDDDD, 0x111111, 0x111111
+];
+
+// 2C03 RGB PPU, 3 bits per channel
+const RGB_2C03: [u16; COLOR_NUM] = [
+    0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420,
+    0o320, 0o120, 0o031, 0o040, 0o022, 0o000, 0o000, 0o000,
+    0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630,
+    0o430, 0o140, 0o040, 0o053, 0o044, 0o000, 0o000, 0o000,
+    0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740, 0o750,
+    0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000,
+    0o777, 0o567, 0o657, 0o757, 0o747, 0o755, 0o764, 0o772,
+    0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+];
+
+// other channels are darkened to about 75% by each emphasis bit
+const EMPHASIS_ATTENUATION: f64 = 0.746;
+
+// composite voltage of each luma level, relative to sync
+const SIGNAL_LOW: [f64; 4] = [0.350, 0.518, 0.962, 1.550];
+const SIGNAL_HIGH: [f64; 4] = [1.094, 1.506, 1.962, 1.962];
+const SIGNAL_BLACK: f64 = 0.518;
+const SIGNAL_WHITE: f64 = 1.962;
+
+#[derive(Debug, Clone)]
+pub struct NtscParams {
+    // degrees
+    pub hue: f64,
+    pub saturation: f64,
+    pub contrast: f64,
+    pub brightness: f64,
+    pub gamma: f64,
+    // 2C07 swaps the red and green emphasis bits
+    pub is_pal: bool,
+}
+
+impl NtscParams {
+    pub fn new() -> NtscParams {
+        NtscParams {
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            brightness: 0.0,
+            gamma: 2.2,
+            is_pal: false,
+        }
+    }
+
+    pub fn pal() -> NtscParams {
+        NtscParams {
+            hue: -15.0,
+            saturation: 0.9,
+            is_pal: true,
+            ..NtscParams::new()
+        }
+    }
+}
+
+impl Default for NtscParams {
+    fn default() -> NtscParams {
+        NtscParams::new()
+    }
+}
+
+fn to_rgb(r: f64, g: f64, b: f64) -> u64 {
+    let c = |v: f64| (v.clamp(0.0, 1.0) * 255.0 + 0.5) as u64;
+    c(r) << 16 | c(g) << 8 | c(b)
+}
+
+// red and green emphasis bits of the 2C07
+pub fn swap_red_green(emphasis: usize) -> usize {
+    emphasis & 0x04 | (emphasis & 0x01) << 1 | (emphasis & 0x02) >> 1
+}
+
+fn swap_emphasis(table: Vec<u64>) -> Vec<u64> {
+    (0..TABLE_SIZE)
+        .map(|i| table[swap_red_green(i / COLOR_NUM) * COLOR_NUM + i % COLOR_NUM])
+        .collect()
+}
+
+// 64 colors repeated for every emphasis, tinted by attenuation
+fn with_emphasis(colors: &[u64]) -> Vec<u64> {
+    let mut table: Vec<u64> = Vec::with_capacity(TABLE_SIZE);
+    for emphasis in 0..8 {
+        for color in colors.iter() {
+            let mut rgb: u64 = 0;
+            // red, green, blue is bit 0, 1, 2 of emphasis
+            for (i, shift) in [16, 8, 0].iter().enumerate() {
+                let mut c: f64 = ((color >> shift) & 0xFF) as f64;
+                for bit in 0..3 {
+                    if bit != i && emphasis & (1 << bit) > 0 {
+                        c *= EMPHASIS_ATTENUATION;
+                    }
+                }
+                rgb |= (c as u64) << shift;
+            }
+            table.push(rgb);
+        }
+    }
+    table
+}
+
+pub fn default_table() -> Vec<u64> {
+    with_emphasis(&DEFAULT_COLORS)
+}
+
+// the RGB PPU drives an emphasized channel at full level
+pub fn rgb_2c03_table() -> Vec<u64> {
+    let mut table: Vec<u64> = Vec::with_capacity(TABLE_SIZE);
+    for emphasis in 0..8 {
+        for color in RGB_2C03.iter() {
+            let mut rgb: u64 = 0;
+            for (i, shift) in [16, 8, 0].iter().enumerate() {
+                let level: u64 = if emphasis & (1 << i) > 0 {
+                    7
+                } else {
+                    (*color as u64 >> (6 - 3 * i)) & 0x07
+                };
+                rgb |= (level * 255 / 7) << shift;
+            }
+            table.push(rgb);
+        }
+    }
+    table
+}
+
+// the color carrier is in phase with hue at 12 steps per cycle
+fn is_in_color_phase(hue: usize, phase: usize) -> bool {
+    (hue + phase) % 12 < 6
+}
+
+// composite level of the color at one of 12 phases
+fn signal(color: usize, emphasis: usize, phase: usize) -> f64 {
+    let hue: usize = color & 0x0F;
+    let level: usize = if hue > 0x0D { 1 } else { (color >> 4) & 0x03 };
+    let low: f64 = if hue == 0 { SIGNAL_HIGH[level] } else { SIGNAL_LOW[level] };
+    let high: f64 = if hue < 0x0D { SIGNAL_HIGH[level] } else { SIGNAL_LOW[level] };
+    let mut v: f64 = if is_in_color_phase(hue, phase) { high } else { low };
+    // emphasis darkens the phases of red, green and blue
+    if (emphasis & 0x01 > 0 && is_in_color_phase(0x0C, phase)) ||
+        (emphasis & 0x02 > 0 && is_in_color_phase(0x04, phase)) ||
+        (emphasis & 0x04 > 0 && is_in_color_phase(0x08, phase)) {
+        v *= EMPHASIS_ATTENUATION;
+    }
+    v
+}
+
+// signal scaled to 0.0 at black and 1.0 at white
+pub fn normalized_signal(color: usize, emphasis: usize, phase: usize) -> f64 {
+    (signal(color, emphasis, phase) - SIGNAL_BLACK) / (SIGNAL_WHITE - SIGNAL_BLACK)
+}
+
+// angle of the I axis at the given phase of the color carrier
+pub fn carrier_angle(phase: usize, params: &NtscParams) -> f64 {
+    PI * (phase as f64 + 4.0) / 6.0 + params.hue.to_radians()
+}
+
+// demodulated YIQ to RGB, with the picture settings of params
+pub fn yiq_to_rgb(y: f64, i: f64, q: f64, params: &NtscParams) -> u64 {
+    let y: f64 = y * params.contrast + params.brightness;
+    let i: f64 = i * params.saturation;
+    let q: f64 = q * params.saturation;
+    let gamma = |v: f64| if v <= 0.0 { 0.0 } else { v.powf(2.2 / params.gamma) };
+    to_rgb(
+        gamma(y + 0.946882 * i + 0.623557 * q),
+        gamma(y - 0.274788 * i - 0.635691 * q),
+        gamma(y - 1.108545 * i + 1.709007 * q),
+    )
+}
+
+// decode the composite signal of every color into YIQ and then RGB
+pub fn generate_ntsc(params: &NtscParams) -> Vec<u64> {
+    let mut table: Vec<u64> = Vec::with_capacity(TABLE_SIZE);
+    for emphasis in 0..8 {
+        let emphasis_: usize = if params.is_pal {
+            swap_red_green(emphasis)
+        } else {
+            emphasis
+        };
+        for color in 0..COLOR_NUM {
+            let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+            for phase in 0..12 {
+                let v: f64 = normalized_signal(color, emphasis_, phase);
+                let angle: f64 = carrier_angle(phase, params);
+                y += v;
+                i += v * angle.cos();
+                q += v * angle.sin();
+            }
+            table.push(yiq_to_rgb(y / 12.0, i / 6.0, q / 6.0, params));
+        }
+    }
+    table
+}
+
+pub fn parse_pal(buf: &[u8]) -> Result<Vec<u64>, String> {
+    if buf.len() != 3 * COLOR_NUM && buf.len() != 3 * TABLE_SIZE {
+        return Err(format!("invalid .pal size {}, expected 192 or 1536", buf.len()));
+    }
+    let colors: Vec<u64> = buf.chunks(3)
+        .map(|c| (c[0] as u64) << 16 | (c[1] as u64) << 8 | c[2] as u64)
+        .collect();
+    Ok(if colors.len() == COLOR_NUM { with_emphasis(&colors) } else { colors })
+}
+
+// key=value list separated by comma
+fn parse_overrides(mut params: NtscParams, text: &str) -> Result<NtscParams, String> {
+    for item in text.split(',').filter(|s| !s.is_empty()) {
+        let (key, value) = item.split_once('=')
+            .ok_or(format!("expected key=value, got {}", item))?;
+        let value: f64 = value.parse()
+            .map_err(|_| format!("invalid value {}", item))?;
+        match key {
+            "hue" => params.hue = value,
+            "saturation" => params.saturation = value,
+            "contrast" => params.contrast = value,
+            "brightness" => params.brightness = value,
+            "gamma" => params.gamma = value,
+            _ => return Err(format!("unknown key {}", key)),
+        }
+    }
+    Ok(params)
+}
+
+// preset name or path of a .pal file
+pub fn load(name: &str, is_emphasis_swapped: bool) -> Result<Vec<u64>, String> {
+    let (preset, overrides) = name.split_once(':').unwrap_or((name, ""));
+    let swap = |table: Vec<u64>| if is_emphasis_swapped { swap_emphasis(table) } else { table };
+    match preset {
+        "default" => Ok(swap(default_table())),
+        "2c02" => Ok(swap(generate_ntsc(&parse_overrides(NtscParams::new(), overrides)?))),
+        "2c03" => Ok(rgb_2c03_table()),
+        "2c07" => Ok(generate_ntsc(&parse_overrides(NtscParams::pal(), overrides)?)),
+        _ => {
+            let buf = fs::read(name).map_err(|e| format!("{}: {}", name, e))?;
+            let table: Vec<u64> = parse_pal(&buf).map_err(|e| format!("{}: {}", name, e))?;
+            Ok(if buf.len() == 3 * COLOR_NUM { swap(table) } else { table })
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pal_sizes() {
+        // 64 colors get the emphasis generated, red keeps its level
+        let buf: Vec<u8> = (0..COLOR_NUM).flat_map(|_| [0x80, 0x80, 0x80]).collect();
+        let table = parse_pal(&buf).unwrap();
+        assert_eq!(table.len(), TABLE_SIZE);
+        assert_eq!(table[0], 0x808080);
+        assert_eq!(table[COLOR_NUM], 0x805F5F);
+        assert_eq!(table[7 * COLOR_NUM], 0x474747);
+
+        // a full table is used as is
+        let buf: Vec<u8> = (0..TABLE_SIZE).flat_map(|i| [(i >> 8) as u8, i as u8, 0x55]).collect();
+        let table = parse_pal(&buf).unwrap();
+        assert_eq!(table.len(), TABLE_SIZE);
+        assert_eq!((table[0], table[0x1FF]), (0x000055, 0x01FF55));
+
+        assert_eq!(parse_pal(&[0; 195]), Err(String::from("invalid .pal size 195, expected 192 or 1536")));
+        assert!(parse_pal(&[]).is_err());
+    }
+
+    #[test]
+    fn presets() {
+        let params = NtscParams { hue: -5.0, gamma: 2.0, ..NtscParams::new() };
+        let table = load("2c02:hue=-5,gamma=2.0", false).unwrap();
+        assert_eq!(table, generate_ntsc(&params));
+        assert_ne!(table, load("2c02", false).unwrap());
+
+        // red and green emphasis swapped on PAL, but not for 2c03
+        let table = load("default", true).unwrap();
+        assert_eq!(table[COLOR_NUM], default_table()[2 * COLOR_NUM]);
+        assert_eq!(load("2c03", true).unwrap(), rgb_2c03_table());
+
+        assert_eq!(load("2c02:tint=1", false), Err(String::from("unknown key tint")));
+        assert_eq!(load("2c07:hue", false), Err(String::from("expected key=value, got hue")));
+        assert_eq!(load("2c02:gamma=x", false), Err(String::from("invalid value gamma=x")));
+    }
+}
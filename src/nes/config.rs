@@ -0,0 +1,52 @@
+use super::cassette::LoadOptions;
+use super::region::Region;
+use super::scale::ScaleOptions;
+
+// emulator settings given from command line
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub is_debug: bool,
+    // draw every sprite on a line instead of the first 8
+    pub is_sprite_limit_disabled: bool,
+    // preset name or .pal file, see colors.rs
+    pub palette: Option<String>,
+    // composite video filter, see ntsc.rs
+    pub is_ntsc_filter: bool,
+    // upscaling filter, scanlines and pixel aspect, see scale.rs
+    pub scale: ScaleOptions,
+    // AVI file recorded until exit, see video.rs
+    pub record: Option<String>,
+    // directory of battery backup, next to the rom when None, see battery.rs
+    pub save_dir: Option<String>,
+    // rom database and archive options, see cassette.rs
+    pub load: LoadOptions,
+    // overrides the header and database timing, see region.rs
+    pub region: Option<Region>,
+    // Vs. System DIP switches, switch 1 is bit 0, see vs_system.rs
+    pub vs_dip: u8,
+    pub is_vs_swap_controllers: bool,
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config {
+            is_debug: false,
+            is_sprite_limit_disabled: false,
+            palette: None,
+            is_ntsc_filter: false,
+            scale: ScaleOptions::new(),
+            record: None,
+            save_dir: None,
+            load: LoadOptions::new(),
+            region: None,
+            vs_dip: 0,
+            is_vs_swap_controllers: false,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config::new()
+    }
+}
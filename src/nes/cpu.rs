@@ -5,7 +5,13 @@
 use std::process;
 
 use super::Apu;
+use super::bandai::{BandaiFcg, FcgWrite};
 use super::Cassette;
+use super::fds::Fds;
+use super::patch;
+use super::region::Region;
+use super::vs_system::VsSystem;
+use super::cassette::Mirroring;
 use super::Ram;
 use super::interrupts::Interrupts;
 use super::optable::{AddrModes, OpCodes, OpInfo, OP_TABLE};
@@ -19,6 +25,9 @@ const BREAK: u8 = 1 << 4;
 const RESERVED: u8 = 1 << 5;
 const OVERFLOW: u8 = 1 << 6;
 const NEGATIVE: u8 = 1 << 7;
+
+const PROG_RAM_WINDOW: usize = 0x2000; // 8KiB at 0x6000-0x7FFF
+const TRAINER_OFFSET: usize = 0x1000; // trainer is at 0x7000-0x71FF
 #[derive(Debug)]
 pub struct KeyPadRegister {
     pub a: bool,
@@ -78,18 +87,18 @@ impl KeyPadRegister {
 
 #[derive(Debug)]
 pub struct Mapper {
-    mapper: u8,
+    mapper: u16,
     bank: u8
 }
 
 impl Mapper {
-    pub fn new(mapper: u8, bank: u8) -> Mapper {
+    pub fn new(mapper: u16, bank: u8) -> Mapper {
         Mapper {
             mapper: mapper,
             bank: bank
         }
     }
-    pub fn get_mapper(&self) -> u8 {
+    pub fn get_mapper(&self) -> u16 {
         self.mapper
     }
     pub fn set_bank(&mut self, bank: u8) {
@@ -101,6 +110,22 @@ impl Mapper {
     pub fn get_char_ram_addr(&self, addr: u16) -> u16 {
         addr + (self.bank as u16) * 0x2000
     }
+    // AxROM, 32KiB bank by bit 0-2
+    pub fn get_prog_rom_addr(&self, addr: u16) -> usize {
+        (self.bank & 0x07) as usize * 0x8000 + (addr - 0x8000) as usize
+    }
+    // mirroring selected by bank write, None when fixed by the cassette
+    pub fn get_mirroring(&self) -> Option<Mirroring> {
+        match self.mapper {
+            // AxROM, one screen by bit 4
+            7 => Some(if self.bank & 0x10 > 0 {
+                Mirroring::SingleScreenB
+            } else {
+                Mirroring::SingleScreenA
+            }),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -151,6 +176,15 @@ pub struct Cpu<'a> {
     reg: Register,
     cas: &'a Cassette,
     wram: &'a mut Ram,
+    // battery backed part first, then volatile part
+    prog_ram: Ram,
+    prog_nvram_size: usize,
+    is_battery_dirty: bool,
+    bandai: Option<BandaiFcg>,
+    // RAM adapter, prog_ram is its 32KiB at 0x6000-0xDFFF
+    fds: Option<Fds>,
+    // DIP switches and coins of Vs. System
+    pub vs: Option<VsSystem>,
     mapper: Mapper,
     pub keypad1: KeyPadRegister,
     pub keypad2: KeyPadRegister,
@@ -161,7 +195,7 @@ impl<'a> Cpu<'a> {
     pub fn new(cas: &'a Cassette, wram: &'a mut Ram) -> Cpu<'a> {
         let nestest_log = "nestest.log";
         let log: String =
-            std::fs::read_to_string(nestest_log).unwrap();
+            std::fs::read_to_string(nestest_log).unwrap_or_default();
         let nestest_log: Vec<String> =
                 log.split("\n")
                     .fold(Vec::new(),
@@ -171,6 +205,14 @@ impl<'a> Cpu<'a> {
             s.push(i.to_string());
             s
         });
+        let mut prog_ram_size: usize = cas.header.prog_nvram_size + cas.header.prog_ram_size;
+        if cas.trainer.is_some() {
+            prog_ram_size = prog_ram_size.max(PROG_RAM_WINDOW);
+        }
+        let mut prog_ram: Ram = Ram::new(prog_ram_size);
+        if let Some(trainer) = &cas.trainer {
+            prog_ram.data[TRAINER_OFFSET..TRAINER_OFFSET + trainer.len()].copy_from_slice(trainer);
+        }
         Cpu {
             index: 0,
             cycle: 0,
@@ -180,12 +222,97 @@ impl<'a> Cpu<'a> {
             reg: Register::new(),
             cas: cas,
             wram: wram,
+            prog_ram,
+            prog_nvram_size: cas.header.prog_nvram_size,
+            is_battery_dirty: false,
+            bandai: BandaiFcg::new(&cas.header),
+            fds: cas.disk.as_ref().map(|disk| Fds::new(disk)),
+            vs: VsSystem::new(&cas.header, 0, false),
             mapper: Mapper::new(cas.mapper, 0),
             keypad1: KeyPadRegister::new(),
             keypad2: KeyPadRegister::new(),
             mx: 0,
         }
     }
+    // battery backed PRG RAM, EEPROM or IPS patch of the disk writes,
+    // None without battery
+    pub fn battery_data(&self) -> Option<Vec<u8>> {
+        if let Some(fds) = &self.fds {
+            let original: &[u8] = self.cas.disk.as_ref().unwrap();
+            return patch::create_ips(original, &fds.image()).ok();
+        }
+        if let Some(eeprom) = self.bandai.as_ref().and_then(|b| b.eeprom.as_ref()) {
+            return Some(eeprom.data.clone());
+        }
+        if self.prog_nvram_size > 0 {
+            return Some(self.prog_ram.data[..self.prog_nvram_size].to_vec());
+        }
+        None
+    }
+    pub fn load_battery(&mut self, data: &[u8]) -> Result<(), String> {
+        if self.fds.is_some() {
+            let disk: Vec<u8> = patch::apply_ips(self.cas.disk.as_ref().unwrap(), data)?;
+            let mut fds: Fds = Fds::new(&disk);
+            // keep the audio unit, which has the region clock
+            std::mem::swap(&mut fds.audio, &mut self.fds.as_mut().unwrap().audio);
+            self.fds = Some(fds);
+            return Ok(());
+        }
+        let ram: &mut [u8] = match self.bandai.as_mut().and_then(|b| b.eeprom.as_mut()) {
+            Some(eeprom) => &mut eeprom.data,
+            None => &mut self.prog_ram.data[..self.prog_nvram_size],
+        };
+        if ram.len() != data.len() {
+            println!("warning: save is {} bytes, battery backup is {} bytes", data.len(), ram.len());
+        }
+        let size: usize = ram.len().min(data.len());
+        ram[..size].copy_from_slice(&data[..size]);
+        Ok(())
+    }
+    // written since the last call
+    pub fn take_battery_dirty(&mut self) -> bool {
+        let mut is_dirty: bool = self.is_battery_dirty;
+        if let Some(fds) = &mut self.fds {
+            is_dirty |= fds.is_dirty;
+            fds.is_dirty = false;
+        }
+        if let Some(eeprom) = self.bandai.as_mut().and_then(|b| b.eeprom.as_mut()) {
+            is_dirty |= eeprom.is_dirty;
+            eeprom.is_dirty = false;
+        }
+        self.is_battery_dirty = false;
+        is_dirty
+    }
+    // CPU clock of the FDS audio sample rate conversion
+    pub fn set_region(&mut self, region: Region) {
+        if let Some(fds) = &mut self.fds {
+            fds.audio.set_region(region);
+        }
+    }
+    // DIP switch 1 is bit 0
+    pub fn set_vs_switches(&mut self, dip: u8, is_swap_controllers: bool) {
+        if let Some(vs) = &mut self.vs {
+            vs.dip = dip;
+            vs.is_swap_controllers = is_swap_controllers;
+        }
+    }
+    // eject the disk and insert the next side, false without disk
+    pub fn switch_disk_side(&mut self) -> bool {
+        match &mut self.fds {
+            Some(fds) => {
+                fds.switch_side();
+                true
+            },
+            None => false,
+        }
+    }
+    // expansion audio since the last call, 44100Hz mono, only --record uses it
+    pub fn take_audio_samples(&mut self) -> Vec<i16> {
+        match &mut self.fds {
+            Some(fds) => fds.audio.take_samples(),
+            None => Vec::new(),
+        }
+    }
     pub fn reset(&mut self, ppu: &mut Ppu, apu: &mut Apu, interrupts: &mut Interrupts) {
         self.index = 0;
         self.cycle = 0;
@@ -224,13 +351,39 @@ impl<'a> Cpu<'a> {
         match addr {
             0x0000 ..= 0x1FFF => self.wram.read(addr),
             0x2000 ..= 0x3FFF => {
-                ppu.read(addr - 0x2000) // ppu read
+                ppu.read((addr - 0x2000) & 0x0007) // ppu read, mirrored every 8 bytes
             },
             0x4015 => apu.read(interrupts, addr), // apu
+            0x4016 | 0x4017 if self.vs.is_some() => {
+                let vs: &VsSystem = self.vs.as_ref().unwrap();
+                let is_first: bool = (addr == 0x4016) != vs.is_swap_controllers;
+                let extra: u8 = if addr == 0x4016 { vs.read_4016() } else { vs.read_4017() };
+                let pad: u8 = if is_first { self.keypad1.read() } else { self.keypad2.read() };
+                pad | extra
+            },
             0x4016 => self.keypad1.read(), // keypad 1p
             0x4017 => self.keypad2.read(), // keypad 1p
             0x4000 ..= 0x401F => 0, // apu?
-            0x6000 ..= 0x7FFF => 0, // extram
+            0x4020 ..= 0x409F if self.fds.is_some() => self.fds.as_mut().unwrap().read(interrupts, addr),
+            0x4020 ..= 0x5FFF => 0, // expansion area, unmapped
+            0x6000 ..= 0xDFFF if self.fds.is_some() => self.prog_ram.data[(addr - 0x6000) as usize],
+            0xE000 ..= 0xFFFF if self.fds.is_some() => self.cas.prog_rom_read(addr - 0xE000),
+            0x6000 ..= 0x7FFF if self.bandai.is_some() => self.bandai.as_ref().unwrap().read(),
+            0x6000 ..= 0x7FFF => { // extram
+                if self.prog_ram.size == 0 {
+                    return 0;
+                }
+                self.prog_ram.data[(addr - 0x6000) as usize % self.prog_ram.size]
+            },
+            0x8000 ..= 0xFFFF if self.bandai.is_some() => {
+                let addr_: usize =
+                    self.bandai.as_ref().unwrap().get_prog_rom_addr(addr, self.cas.prog_size);
+                self.cas.prog_rom[addr_]
+            },
+            0x8000 ..= 0xFFFF if self.mapper.get_mapper() == 7 => {
+                let addr_: usize = self.mapper.get_prog_rom_addr(addr);
+                self.cas.prog_rom[addr_ % self.cas.prog_size]
+            },
             0x8000 ..= 0xBFFF => self.cas.prog_rom_read(addr - 0x8000),
             0xC000 ..= 0xFFFF => {
                 if self.cas.prog_size <= 0x4000 {
@@ -239,15 +392,14 @@ impl<'a> Cpu<'a> {
                     self.cas.prog_rom_read(addr - 0x8000)
                 }
             },
-            _ => panic!("invalid addr {:#X}", addr)
         }
     }
     fn write(&mut self, ppu: &mut Ppu, apu: &mut Apu, interrupts: &mut Interrupts, addr: u16, data: u8) {
         // println!(" write {:#X} {:#X}", addr, data);
         match addr {
             0x0000 ..= 0x1FFF => self.wram.write(addr, data),
-            0x2000 ..= 0x2007 => {
-                ppu.write(addr - 0x2000, data); // ppu write
+            0x2000 ..= 0x3FFF => {
+                ppu.write((addr - 0x2000) & 0x0007, data); // ppu write, mirrored every 8 bytes
             },
             0x4014 => {
                 let ram_addr_s: u16 = (data as u16 * SPRITE_RAM_SIZE as u16) as u16;
@@ -260,19 +412,58 @@ impl<'a> Cpu<'a> {
                 }
                 // self.cycle += 514; // ?
             }, // dma 
+            0x4016 if self.vs.is_some() => {
+                // strobes both controllers
+                self.keypad1.write(data);
+                self.keypad2.write(data);
+                if let Some(bank) = self.vs.as_ref().unwrap().write_4016(data) {
+                    for slot in 0..8 {
+                        ppu.set_char_bank(slot, bank * 8 + slot);
+                    }
+                }
+            },
             0x4016 => {
                 self.keypad1.write(data); // keypad 1p
             },
             0x4017 => self.keypad2.write(data), // keypad 2p
-            0x4000 ..= 0x4020 => {
+            0x4020 ..= 0x409F if self.fds.is_some() => {
+                if let Some(mirroring) = self.fds.as_mut().unwrap().write(interrupts, addr, data) {
+                    ppu.set_mirroring(mirroring);
+                }
+            },
+            0x4000 ..= 0x401F => {
                 apu.write(addr, data);
             }, // apu
-            0x6000 ..= 0x7FFF => self.wram.write(addr - 0x8000, data),
+            0x4020 if self.vs.is_some() => (), // coin counter
+            0x4020 ..= 0x5FFF => (), // expansion area, unmapped
+            0x6000 ..= 0xDFFF if self.fds.is_some() => self.prog_ram.data[(addr - 0x6000) as usize] = data,
+            0xE000 ..= 0xFFFF if self.fds.is_some() => (),
+            0x6000 ..= 0xFFFF if self.bandai.as_ref().is_some_and(|b| b.is_register(addr)) => {
+                match self.bandai.as_mut().unwrap().write(addr, data) {
+                    FcgWrite::CharBank(slot, bank) => ppu.set_char_bank(slot, bank),
+                    FcgWrite::Mirroring(mirroring) => ppu.set_mirroring(mirroring),
+                    FcgWrite::IrqAcknowledge => interrupts.deassert_irq(),
+                    FcgWrite::None => (),
+                }
+            },
+            0x6000 ..= 0x7FFF => { // extram
+                if self.prog_ram.size == 0 {
+                    return;
+                }
+                let offset: usize = (addr - 0x6000) as usize % self.prog_ram.size;
+                self.prog_ram.data[offset] = data;
+                if offset < self.prog_nvram_size {
+                    self.is_battery_dirty = true;
+                }
+            },
+            0x8000 ..= 0xFFFF if self.bandai.is_some() => (),
             0x8000 ..= 0xFFFF => {
                 println!("bank : {}", data);
                 self.mapper.set_bank(data);
+                if let Some(mirroring) = self.mapper.get_mirroring() {
+                    ppu.set_mirroring(mirroring);
+                }
             },
-            _ => panic!("invalid addr {:#X}", addr),
         }
     }
     fn bfetch(&mut self, ppu: &mut Ppu, apu: &mut Apu, interrupts: &mut Interrupts, ) -> u8{
@@ -1026,6 +1217,102 @@ impl<'a> Cpu<'a> {
             if self.has_branched {1} else {0};
         self.cycle += cycle;
         self.index += 1;
+        if let Some(bandai) = &mut self.bandai {
+            if bandai.tick(cycle) {
+                interrupts.assert_irq();
+            }
+        }
+        if let Some(fds) = &mut self.fds {
+            fds.run(interrupts, cycle);
+        }
         cycle
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::bench::synthetic_rom;
+    use super::super::{VRAM_SIZE, WRAM_SIZE};
+
+    #[test]
+    fn axrom_mirroring() {
+        let mut mapper: Mapper = Mapper::new(7, 0x00);
+        assert_eq!(mapper.get_mirroring(), Some(Mirroring::SingleScreenA));
+        mapper.set_bank(0x17);
+        assert_eq!(mapper.get_mirroring(), Some(Mirroring::SingleScreenB));
+        assert_eq!(Mapper::new(0, 0x10).get_mirroring(), None);
+
+        let mut rom: Vec<u8> = synthetic_rom();
+        rom[6] = 0x70;
+        let cas: Cassette = Cassette::from_bytes("axrom", rom).unwrap();
+        let mut wram: Ram = Ram::new(WRAM_SIZE);
+        let mut vram: Ram = Ram::new(VRAM_SIZE);
+        let mut interrupts: Interrupts = Interrupts::new();
+        let mut apu: Apu = Apu::new();
+        let mut ppu: Ppu = Ppu::new(&cas, &mut vram);
+        let mut cpu: Cpu = Cpu::new(&cas, &mut wram);
+        // one byte at 0x2000 on each screen, all 4 name tables show it
+        for (bank, data) in [(0x00, 0x11), (0x10, 0x22)].iter() {
+            cpu.write(&mut ppu, &mut apu, &mut interrupts, 0x8000, *bank);
+            for (addr, data) in [(0x2006, 0x20), (0x2006, 0x00), (0x2007, *data)].iter() {
+                cpu.write(&mut ppu, &mut apu, &mut interrupts, *addr, *data);
+            }
+        }
+        for (bank, data) in [(0x00, 0x11), (0x10, 0x22)].iter() {
+            cpu.write(&mut ppu, &mut apu, &mut interrupts, 0x8000, *bank);
+            for table in 0..4 {
+                cpu.write(&mut ppu, &mut apu, &mut interrupts, 0x2006, 0x20 + 4 * table);
+                cpu.write(&mut ppu, &mut apu, &mut interrupts, 0x2006, 0x00);
+                cpu.read(&mut ppu, &mut apu, &mut interrupts, 0x2007);
+                assert_eq!(cpu.read(&mut ppu, &mut apu, &mut interrupts, 0x2007), *data);
+            }
+        }
+    }
+
+    #[test]
+    fn vs_inputs() {
+        let mut rom: Vec<u8> = synthetic_rom();
+        rom[7] = 0x01;
+        let cas: Cassette = Cassette::from_bytes("vs", rom).unwrap();
+        let mut wram: Ram = Ram::new(WRAM_SIZE);
+        let mut vram: Ram = Ram::new(VRAM_SIZE);
+        let mut interrupts: Interrupts = Interrupts::new();
+        let mut apu: Apu = Apu::new();
+        let mut ppu: Ppu = Ppu::new(&cas, &mut vram);
+        let mut cpu: Cpu = Cpu::new(&cas, &mut wram);
+        cpu.keypad1.a = true;
+        cpu.keypad2.b = true;
+        // A then B of both controllers after a strobe of $4016
+        let mut read_ab = |cpu: &mut Cpu| {
+            cpu.write(&mut ppu, &mut apu, &mut interrupts, 0x4016, 1);
+            cpu.write(&mut ppu, &mut apu, &mut interrupts, 0x4016, 0);
+            let mut values: Vec<u8> = Vec::new();
+            for _ in 0..2 {
+                values.push(cpu.read(&mut ppu, &mut apu, &mut interrupts, 0x4016));
+                values.push(cpu.read(&mut ppu, &mut apu, &mut interrupts, 0x4017));
+            }
+            values
+        };
+
+        // DIP switch 2 at $4016 bit 4, switch 3, 6 and 8 at $4017
+        cpu.set_vs_switches(0xA6, false);
+        assert_eq!(read_ab(&mut cpu), [0x11, 0xA4, 0x10, 0xA5]);
+
+        // service at bit 2, coin 1 and 2 at bit 5 and 6
+        let vs = cpu.vs.as_mut().unwrap();
+        vs.service = true;
+        vs.coin1 = true;
+        assert_eq!(read_ab(&mut cpu)[0], 0x35);
+        let vs = cpu.vs.as_mut().unwrap();
+        vs.coin1 = false;
+        vs.coin2 = true;
+        assert_eq!(read_ab(&mut cpu)[0], 0x55);
+
+        // player 1 at $4017, the switches stay where they are
+        let vs = cpu.vs.as_mut().unwrap();
+        vs.service = false;
+        vs.coin2 = false;
+        cpu.set_vs_switches(0xA6, true);
+        assert_eq!(read_ab(&mut cpu), [0x10, 0xA5, 0x11, 0xA4]);
+    }
+}
@@ -0,0 +1,272 @@
+/*
+    [Serial EEPROM] Bandai FCG boards, bit banged through $800D
+    | chip   | size | first byte after start          | bit order |
+    +--------+------+---------------------------------+-----------+
+    | 24C01  | 128  | word address 0-6, bit 7 R/W     | LSB first |
+    | 24C02  | 256  | device 1010xxx, bit 0 R/W       | MSB first |
+    24C02 writes take a word address byte after the device byte,
+    a read continues from the last address (random read is a write
+    of the address followed by a repeated start).
+
+    start  SDA falls while SCL is high
+    stop   SDA rises while SCL is high
+    data is sampled on the rising edge of SCL, the 9th clock of
+    every byte is the acknowledge (low) of the receiver.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EepromType {
+    C24C01,
+    C24C02,
+}
+
+impl EepromType {
+    pub fn size(&self) -> usize {
+        match self {
+            EepromType::C24C01 => 128,
+            EepromType::C24C02 => 256,
+        }
+    }
+    // bytes written before the address wraps inside the page
+    fn page_size(&self) -> u8 {
+        match self {
+            EepromType::C24C01 => 4,
+            EepromType::C24C02 => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Idle,
+    Device,
+    Address,
+    Write,
+    Read,
+}
+
+#[derive(Debug)]
+pub struct Eeprom {
+    kind: EepromType,
+    pub data: Vec<u8>,
+    mode: Mode,
+    addr: u8,
+    shift: u8,
+    // clocks of the current byte, 8 is the acknowledge clock, 9 after it
+    bit: u8,
+    scl: bool,
+    sda: bool,
+    out: bool,
+    // written since the last flush of the save file
+    pub is_dirty: bool,
+}
+
+impl Eeprom {
+    pub fn new(kind: EepromType) -> Eeprom {
+        Eeprom {
+            kind,
+            data: vec![0xFF; kind.size()],
+            mode: Mode::Idle,
+            addr: 0,
+            shift: 0,
+            bit: 0,
+            scl: false,
+            sda: true,
+            out: true,
+            is_dirty: false,
+        }
+    }
+
+    fn is_msb_first(&self) -> bool {
+        self.kind == EepromType::C24C02
+    }
+
+    fn is_input(&self) -> bool {
+        matches!(self.mode, Mode::Device | Mode::Address | Mode::Write)
+    }
+
+    // level driven by the chip, released (high) unless acknowledging or sending
+    pub fn read(&self) -> bool {
+        self.out
+    }
+
+    pub fn write(&mut self, scl: bool, sda: bool) {
+        if self.scl && scl && self.sda != sda {
+            if sda {
+                // stop
+                self.mode = Mode::Idle;
+            } else {
+                // start
+                self.mode = match self.kind {
+                    EepromType::C24C01 => Mode::Address,
+                    EepromType::C24C02 => Mode::Device,
+                };
+            }
+            self.bit = 0;
+            self.shift = 0;
+            self.out = true;
+        } else if !self.scl && scl {
+            self.rise(sda);
+        } else if self.scl && !scl {
+            self.fall();
+        }
+        self.scl = scl;
+        self.sda = sda;
+    }
+
+    fn rise(&mut self, sda: bool) {
+        if self.mode == Mode::Idle {
+            return;
+        }
+        if self.bit < 8 {
+            if self.is_input() {
+                if self.is_msb_first() {
+                    self.shift = (self.shift << 1) | sda as u8;
+                } else {
+                    self.shift |= (sda as u8) << self.bit;
+                }
+            }
+            self.bit += 1;
+        } else if self.bit == 8 {
+            // master does not acknowledge the last byte read,
+            // out is low when the chip acknowledged the device byte itself
+            if self.mode == Mode::Read && self.out && sda {
+                self.mode = Mode::Idle;
+            }
+            self.bit = 9;
+        }
+    }
+
+    fn fall(&mut self) {
+        match self.bit {
+            8 if self.is_input() => self.out = !self.receive(),
+            8 => self.out = true,
+            9 => {
+                self.bit = 0;
+                self.shift = 0;
+                self.out = true;
+                if self.mode == Mode::Read {
+                    self.shift = self.data[self.addr as usize];
+                    self.addr = ((self.addr as usize + 1) % self.data.len()) as u8;
+                    self.out = self.out_bit();
+                }
+            },
+            _ if self.mode == Mode::Read => self.out = self.out_bit(),
+            _ => (),
+        }
+    }
+
+    fn out_bit(&self) -> bool {
+        let i: u8 = if self.is_msb_first() { 7 - self.bit } else { self.bit };
+        (self.shift >> i) & 0x01 > 0
+    }
+
+    // handle a received byte, true to acknowledge it
+    fn receive(&mut self) -> bool {
+        let byte: u8 = self.shift;
+        match (self.kind, self.mode) {
+            (EepromType::C24C02, Mode::Device) => {
+                if byte & 0xF0 != 0xA0 {
+                    self.mode = Mode::Idle;
+                    return false;
+                }
+                self.mode = if byte & 0x01 > 0 { Mode::Read } else { Mode::Address };
+            },
+            (EepromType::C24C02, Mode::Address) => {
+                self.addr = byte;
+                self.mode = Mode::Write;
+            },
+            (EepromType::C24C01, Mode::Address) => {
+                self.addr = byte & 0x7F;
+                self.mode = if byte & 0x80 > 0 { Mode::Read } else { Mode::Write };
+            },
+            (_, Mode::Write) => {
+                self.data[self.addr as usize] = byte;
+                self.is_dirty = true;
+                let page: u8 = self.kind.page_size();
+                self.addr = (self.addr & !(page - 1)) | (self.addr.wrapping_add(1) & (page - 1));
+            },
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start(e: &mut Eeprom) {
+        e.write(false, true);
+        e.write(true, true);
+        e.write(true, false);
+        e.write(false, false);
+    }
+
+    fn stop(e: &mut Eeprom) {
+        e.write(false, false);
+        e.write(true, false);
+        e.write(true, true);
+    }
+
+    // returns the acknowledge
+    fn send(e: &mut Eeprom, byte: u8, is_msb_first: bool) -> bool {
+        for i in 0..8 {
+            let b = if is_msb_first { byte >> (7 - i) } else { byte >> i } & 0x01 > 0;
+            e.write(false, b);
+            e.write(true, b);
+            e.write(false, b);
+        }
+        e.write(false, true);
+        e.write(true, true);
+        let ack = !e.read();
+        e.write(false, true);
+        ack
+    }
+
+    fn receive(e: &mut Eeprom, is_msb_first: bool, is_ack: bool) -> u8 {
+        let mut byte: u8 = 0;
+        for i in 0..8 {
+            e.write(true, true);
+            let b = e.read() as u8;
+            byte |= if is_msb_first { b << (7 - i) } else { b << i };
+            e.write(false, true);
+        }
+        e.write(false, !is_ack);
+        e.write(true, !is_ack);
+        e.write(false, !is_ack);
+        byte
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let mut e = Eeprom::new(EepromType::C24C02);
+        start(&mut e);
+        assert!(send(&mut e, 0xA0, true));
+        assert!(send(&mut e, 0x10, true));
+        assert!(send(&mut e, 0x12, true));
+        assert!(send(&mut e, 0x34, true));
+        stop(&mut e);
+        assert_eq!((e.data[0x10], e.data[0x11], e.is_dirty), (0x12, 0x34, true));
+
+        // random read
+        start(&mut e);
+        send(&mut e, 0xA0, true);
+        send(&mut e, 0x10, true);
+        start(&mut e);
+        assert!(send(&mut e, 0xA1, true));
+        assert_eq!(receive(&mut e, true, true), 0x12);
+        assert_eq!(receive(&mut e, true, false), 0x34);
+        stop(&mut e);
+
+        let mut e = Eeprom::new(EepromType::C24C01);
+        start(&mut e);
+        assert!(send(&mut e, 0x05, false));
+        assert!(send(&mut e, 0x5A, false));
+        stop(&mut e);
+        start(&mut e);
+        assert!(send(&mut e, 0x85, false));
+        assert_eq!(receive(&mut e, false, false), 0x5A);
+        stop(&mut e);
+    }
+}
@@ -0,0 +1,578 @@
+use super::cassette::Mirroring;
+use super::fds_audio::FdsAudio;
+use super::Interrupts;
+
+/*
+    [Famicom Disk System] .fds image and RAM adapter
+    .fds is 65500 bytes per side, optionally after a 16 byte fwNES
+    header "FDS" 0x1A, side count. the BIOS (disksys.rom, 8KiB) is
+    mapped at $E000-$FFFF, RAM adapter RAM at $6000-$DFFF, 8KiB CHR RAM.
+
+    [Blocks] each is read after a gap and a 0x80 start mark,
+    followed by 2 CRC bytes
+    | code | size     | content                                 |
+    +------+----------+-----------------------------------------+
+    |  1   | 56       | "*NINTENDO-HVC*", disk info             |
+    |  2   | 2        | file count                              |
+    |  3   | 16       | file header, size at byte 13-14         |
+    |  4   | 1 + size | file data                               |
+
+    [Registers]
+    | addr  | description                                             |
+    +-------+---------------------------------------------------------+
+    | $4020 | w timer reload low                                      |
+    | $4021 | w timer reload high                                     |
+    | $4022 | w b0 timer repeat, b1 timer enable                      |
+    | $4023 | w b0 disk registers enable, b1 sound registers enable   |
+    | $4024 | w data to write                                         |
+    | $4025 | w b0 motor, b1 transfer reset, b2 read mode,            |
+    |       |   b3 horizontal mirroring, b4 CRC control,              |
+    |       |   b6 transfer start after the gap, b7 disk IRQ enable   |
+    | $4026 | w external connector                                    |
+    | $4030 | r b0 timer IRQ, b1 byte transferred, b6 end of disk     |
+    | $4031 | r data read                                             |
+    | $4032 | r b0 no disk, b1 not ready, b2 write protected          |
+    | $4033 | r external connector, b7 battery good                   |
+    a byte passes the head every 150 CPU cycles, the head returns to
+    the start of the disk 50000 cycles after the end.
+
+    writes are kept in memory and saved as an IPS patch of the image,
+    the original file is never modified. the rebuilt side keeps the bytes
+    after the last block, so an unwritten disk saves no patch.
+*/
+
+pub const SIDE_SIZE: usize = 65500;
+pub const FWNES_HSIZE: usize = 16;
+pub const BIOS_SIZE: usize = 0x2000;
+pub const RAM_SIZE: usize = 0x8000;
+const FIRST_GAP: usize = 28300 / 8;
+const BLOCK_GAP: usize = 976 / 8;
+const BYTE_CYCLES: u64 = 150;
+const REWIND_CYCLES: u64 = 50000;
+// about a second with no disk so the BIOS notices the change
+const SWAP_CYCLES: u64 = 1789773;
+
+pub fn is_fds(buf: &[u8]) -> bool {
+    buf.starts_with(b"FDS\x1A") || buf.starts_with(b"\x01*NINTENDO-HVC*")
+}
+
+// sides of the image without the fwNES header
+pub fn parse(buf: &[u8]) -> Result<Vec<u8>, String> {
+    let data: &[u8] = if buf.starts_with(b"FDS\x1A") { &buf[FWNES_HSIZE.min(buf.len())..] } else { buf };
+    if data.is_empty() || !data.len().is_multiple_of(SIDE_SIZE) {
+        return Err(format!("FDS image is {} bytes, not a multiple of {}", data.len(), SIDE_SIZE));
+    }
+    for (i, side) in data.chunks(SIDE_SIZE).enumerate() {
+        if !side.starts_with(b"\x01*NINTENDO-HVC*") {
+            return Err(format!("FDS side {} has no disk info block", i));
+        }
+    }
+    Ok(data.to_vec())
+}
+
+fn block_size(code: u8, file_size: usize) -> Option<usize> {
+    match code {
+        1 => Some(56),
+        2 => Some(2),
+        3 => Some(16),
+        4 => Some(1 + file_size),
+        _ => None,
+    }
+}
+
+// image side to the bytes passing the head, gaps, marks and CRC added
+fn to_drive(side: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = vec![0; FIRST_GAP];
+    let (mut p, mut file_size): (usize, usize) = (0, 0);
+    while p < side.len() {
+        let size: usize = match block_size(side[p], file_size) {
+            Some(size) if p + size <= side.len() => size,
+            _ => break,
+        };
+        let block: &[u8] = &side[p..p + size];
+        if block[0] == 3 {
+            file_size = u16::from_le_bytes([block[13], block[14]]) as usize;
+        }
+        out.push(0x80);
+        out.extend_from_slice(block);
+        // CRC is not checked, $4030 never reports an error
+        out.extend_from_slice(&[0x4D, 0x62]);
+        out.extend(vec![0; BLOCK_GAP]);
+        p += size;
+    }
+    let size: usize = out.len().max(SIDE_SIZE + FIRST_GAP);
+    out.resize(size, 0);
+    out
+}
+
+// the bytes passing the head back to an image side, the blocks are copied
+// over the original side so that the data after the last block is kept
+fn from_drive(data: &[u8], original: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = original.to_vec();
+    out.resize(SIDE_SIZE, 0);
+    let mut q: usize = 0;
+    let (mut p, mut file_size): (usize, usize) = (0, 0);
+    loop {
+        while p < data.len() && data[p] != 0x80 {
+            p += 1;
+        }
+        p += 1;
+        if p >= data.len() {
+            break;
+        }
+        let size: usize = match block_size(data[p], file_size) {
+            Some(size) if p + size <= data.len() => size,
+            _ => break,
+        };
+        let block: &[u8] = &data[p..p + size];
+        if block[0] == 3 {
+            file_size = u16::from_le_bytes([block[13], block[14]]) as usize;
+        }
+        if q + size > SIDE_SIZE {
+            break;
+        }
+        out[q..q + size].copy_from_slice(block);
+        q += size;
+        p += size + 2;
+    }
+    out
+}
+
+#[derive(Debug)]
+pub struct Fds {
+    sides: Vec<Vec<u8>>,
+    // image as loaded, the base of the rebuilt sides
+    original: Vec<u8>,
+    side: Option<usize>,
+    // inserted after swap_delay cycles without disk
+    next_side: Option<usize>,
+    swap_delay: u64,
+    pub audio: FdsAudio,
+    is_disk_reg_enabled: bool,
+    is_sound_reg_enabled: bool,
+    timer_reload: u16,
+    timer_counter: u16,
+    is_timer_repeat: bool,
+    is_timer_enabled: bool,
+    is_timer_irq: bool,
+    is_disk_irq: bool,
+    is_motor_on: bool,
+    is_reset_transfer: bool,
+    is_read_mode: bool,
+    is_crc_control: bool,
+    is_prev_crc_control: bool,
+    is_transfer_start: bool,
+    is_disk_irq_enabled: bool,
+    is_transfer_complete: bool,
+    is_end_of_head: bool,
+    is_scanning: bool,
+    is_gap_ended: bool,
+    position: usize,
+    delay: u64,
+    read_data: u8,
+    write_data: u8,
+    ext_output: u8,
+    // written since the last save
+    pub is_dirty: bool,
+}
+
+impl Fds {
+    pub fn new(image: &[u8]) -> Fds {
+        Fds {
+            sides: image.chunks(SIDE_SIZE).map(to_drive).collect(),
+            original: image.to_vec(),
+            side: Some(0),
+            next_side: None,
+            swap_delay: 0,
+            audio: FdsAudio::new(),
+            is_disk_reg_enabled: false,
+            is_sound_reg_enabled: false,
+            timer_reload: 0,
+            timer_counter: 0,
+            is_timer_repeat: false,
+            is_timer_enabled: false,
+            is_timer_irq: false,
+            is_disk_irq: false,
+            is_motor_on: false,
+            is_reset_transfer: false,
+            is_read_mode: true,
+            is_crc_control: false,
+            is_prev_crc_control: false,
+            is_transfer_start: false,
+            is_disk_irq_enabled: false,
+            is_transfer_complete: false,
+            is_end_of_head: true,
+            is_scanning: false,
+            is_gap_ended: false,
+            position: 0,
+            delay: 0,
+            read_data: 0,
+            write_data: 0,
+            ext_output: 0,
+            is_dirty: false,
+        }
+    }
+
+    pub fn side_count(&self) -> usize {
+        self.sides.len()
+    }
+
+    pub fn side(&self) -> Option<usize> {
+        self.side
+    }
+
+    // None ejects the disk
+    pub fn insert(&mut self, side: Option<usize>) {
+        self.side = side.filter(|s| *s < self.sides.len());
+        self.next_side = None;
+        self.is_end_of_head = true;
+        self.is_scanning = false;
+    }
+
+    // eject, then insert the next side after a while
+    pub fn switch_side(&mut self) {
+        let next: usize = self.side.map_or(0, |s| (s + 1) % self.sides.len());
+        self.insert(None);
+        self.next_side = Some(next);
+        self.swap_delay = SWAP_CYCLES;
+    }
+
+    // the image with the writes, same layout as the loaded one
+    pub fn image(&self) -> Vec<u8> {
+        self.sides.iter().zip(self.original.chunks(SIDE_SIZE))
+            .flat_map(|(side, original)| from_drive(side, original))
+            .collect()
+    }
+
+    // the IRQ line stays asserted until $4030/$4031 or a write acknowledges it
+    fn update_irq(&self, interrupts: &mut Interrupts) {
+        interrupts.set_fds_irq(self.is_timer_irq || self.is_disk_irq);
+    }
+
+    pub fn read(&mut self, interrupts: &mut Interrupts, addr: u16) -> u8 {
+        if self.is_sound_reg_enabled {
+            if let Some(data) = self.audio.read(addr) {
+                return data;
+            }
+        }
+        if !self.is_disk_reg_enabled {
+            return 0;
+        }
+        match addr {
+            0x4030 => {
+                let data: u8 = self.is_timer_irq as u8 |
+                    (self.is_transfer_complete as u8) << 1 |
+                    (self.is_end_of_head as u8) << 6;
+                self.is_timer_irq = false;
+                self.is_disk_irq = false;
+                self.is_transfer_complete = false;
+                self.update_irq(interrupts);
+                data
+            },
+            0x4031 => {
+                self.is_transfer_complete = false;
+                self.is_disk_irq = false;
+                self.update_irq(interrupts);
+                self.read_data
+            },
+            0x4032 => {
+                let is_ejected: bool = self.side.is_none();
+                is_ejected as u8 |
+                    ((is_ejected || !self.is_scanning) as u8) << 1 |
+                    (is_ejected as u8) << 2 |
+                    0x40
+            },
+            // battery good
+            0x4033 => 0x80 | (self.ext_output & 0x7F),
+            _ => 0,
+        }
+    }
+
+    // returns the mirroring selected by $4025
+    pub fn write(&mut self, interrupts: &mut Interrupts, addr: u16, data: u8) -> Option<Mirroring> {
+        if addr == 0x4023 {
+            self.is_disk_reg_enabled = data & 0x01 > 0;
+            self.is_sound_reg_enabled = data & 0x02 > 0;
+            if !self.is_disk_reg_enabled {
+                self.is_timer_enabled = false;
+                self.is_timer_irq = false;
+                self.update_irq(interrupts);
+            }
+            return None;
+        }
+        if (0x4040..=0x408A).contains(&addr) {
+            if self.is_sound_reg_enabled {
+                self.audio.write(addr, data);
+            }
+            return None;
+        }
+        if !self.is_disk_reg_enabled {
+            return None;
+        }
+        match addr {
+            0x4020 => self.timer_reload = (self.timer_reload & 0xFF00) | data as u16,
+            0x4021 => self.timer_reload = (self.timer_reload & 0x00FF) | (data as u16) << 8,
+            0x4022 => {
+                self.is_timer_repeat = data & 0x01 > 0;
+                self.is_timer_enabled = data & 0x02 > 0;
+                if self.is_timer_enabled {
+                    self.timer_counter = self.timer_reload;
+                } else {
+                    self.is_timer_irq = false;
+                    self.update_irq(interrupts);
+                }
+            },
+            0x4024 => {
+                self.write_data = data;
+                self.is_transfer_complete = false;
+                self.is_disk_irq = false;
+                self.update_irq(interrupts);
+            },
+            0x4025 => {
+                self.is_motor_on = data & 0x01 > 0;
+                self.is_reset_transfer = data & 0x02 > 0;
+                self.is_read_mode = data & 0x04 > 0;
+                self.is_crc_control = data & 0x10 > 0;
+                self.is_transfer_start = data & 0x40 > 0;
+                self.is_disk_irq_enabled = data & 0x80 > 0;
+                self.is_disk_irq = false;
+                self.update_irq(interrupts);
+                return Some(if data & 0x08 > 0 { Mirroring::Horizontal } else { Mirroring::Vertical });
+            },
+            0x4026 => self.ext_output = data,
+            _ => (),
+        }
+        None
+    }
+
+    pub fn run(&mut self, interrupts: &mut Interrupts, cycles: u64) {
+        for _ in 0..cycles {
+            self.clock_timer(interrupts);
+            self.clock_disk(interrupts);
+        }
+        if self.is_sound_reg_enabled {
+            self.audio.run(cycles);
+        }
+        if let Some(next) = self.next_side {
+            self.swap_delay = self.swap_delay.saturating_sub(cycles);
+            if self.swap_delay == 0 {
+                self.insert(Some(next));
+            }
+        }
+    }
+
+    fn clock_timer(&mut self, interrupts: &mut Interrupts) {
+        if !self.is_timer_enabled || !self.is_disk_reg_enabled {
+            return;
+        }
+        if self.timer_counter == 0 {
+            self.is_timer_irq = true;
+            self.update_irq(interrupts);
+            self.timer_counter = self.timer_reload;
+            if !self.is_timer_repeat {
+                self.is_timer_enabled = false;
+            }
+        } else {
+            self.timer_counter -= 1;
+        }
+    }
+
+    fn clock_disk(&mut self, interrupts: &mut Interrupts) {
+        let side: usize = match self.side {
+            Some(side) if self.is_motor_on => side,
+            _ => {
+                self.is_end_of_head = true;
+                self.is_scanning = false;
+                return;
+            },
+        };
+        if self.is_reset_transfer && !self.is_scanning {
+            return;
+        }
+        if self.is_end_of_head {
+            self.delay = REWIND_CYCLES;
+            self.is_end_of_head = false;
+            self.position = 0;
+            self.is_gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+        self.is_scanning = true;
+        let mut is_irq: bool = self.is_disk_irq_enabled;
+        if self.is_read_mode {
+            let data: u8 = self.sides[side][self.position];
+            if !self.is_transfer_start {
+                self.is_gap_ended = false;
+            } else if data > 0 && !self.is_gap_ended {
+                // start mark of a block
+                self.is_gap_ended = true;
+                is_irq = false;
+            }
+            if self.is_gap_ended {
+                self.is_transfer_complete = true;
+                self.read_data = data;
+                if is_irq {
+                    self.is_disk_irq = true;
+                    self.update_irq(interrupts);
+                }
+            }
+        } else {
+            let mut data: u8 = 0;
+            if !self.is_crc_control {
+                self.is_transfer_complete = true;
+                data = self.write_data;
+                if is_irq {
+                    self.is_disk_irq = true;
+                    self.update_irq(interrupts);
+                }
+            }
+            if !self.is_transfer_start {
+                data = 0;
+            }
+            if self.is_crc_control {
+                data = if self.is_prev_crc_control { 0x62 } else { 0x4D };
+            }
+            if self.sides[side][self.position] != data {
+                self.sides[side][self.position] = data;
+                self.is_dirty = true;
+            }
+            self.is_gap_ended = false;
+        }
+        self.is_prev_crc_control = self.is_crc_control;
+        self.position += 1;
+        if self.position >= self.sides[side].len() {
+            self.is_motor_on = false;
+        } else {
+            self.delay = BYTE_CYCLES;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_side() -> Vec<u8> {
+        let mut side: Vec<u8> = vec![0; SIDE_SIZE];
+        side[0..15].copy_from_slice(b"\x01*NINTENDO-HVC*");
+        side[56..58].copy_from_slice(&[0x02, 0x01]);
+        // file header of 3 bytes and its data
+        side[58] = 0x03;
+        side[58 + 13] = 3;
+        side[74..78].copy_from_slice(&[0x04, 0xAA, 0xBB, 0xCC]);
+        side
+    }
+
+    // cycles until the IRQ line goes up
+    fn wait_irq(fds: &mut Fds, interrupts: &mut Interrupts, limit: u64) -> Option<u64> {
+        (1..=limit).find(|_| {
+            fds.run(interrupts, 1);
+            interrupts.get_irq_assert()
+        })
+    }
+
+    #[test]
+    fn drive_roundtrip() {
+        let side: Vec<u8> = test_side();
+        let drive = to_drive(&side);
+        assert_eq!(drive[FIRST_GAP], 0x80);
+        assert_eq!(from_drive(&drive, &side), side);
+        assert_eq!(parse(&side).unwrap().len(), SIDE_SIZE);
+    }
+
+    #[test]
+    fn trailing_data_kept() {
+        let mut side: Vec<u8> = test_side();
+        side[0x8000..].iter_mut().enumerate().for_each(|(i, b)| *b = 0xA0 | (i & 0x0F) as u8);
+        let fds: Fds = Fds::new(&side);
+        assert_eq!(fds.image(), side);
+
+        // a write to the file data keeps the tail too
+        let mut drive: Vec<u8> = to_drive(&side);
+        let p: usize = drive.iter().position(|b| *b == 0xAA).unwrap();
+        drive[p] = 0x55;
+        let mut written: Vec<u8> = side.clone();
+        written[75] = 0x55;
+        assert_eq!(from_drive(&drive, &side), written);
+    }
+
+    #[test]
+    fn timer_irq() {
+        let mut fds: Fds = Fds::new(&test_side());
+        let mut interrupts: Interrupts = Interrupts::new();
+        fds.write(&mut interrupts, 0x4023, 0x01);
+        fds.write(&mut interrupts, 0x4020, 10);
+        fds.write(&mut interrupts, 0x4021, 0);
+        fds.write(&mut interrupts, 0x4022, 0x02);
+        assert_eq!(wait_irq(&mut fds, &mut interrupts, 100), Some(11));
+        // the CPU taking the IRQ does not acknowledge it
+        interrupts.deassert_irq();
+        assert!(interrupts.get_irq_assert());
+        assert_eq!(fds.read(&mut interrupts, 0x4030) & 0x01, 0x01);
+        assert!(!interrupts.get_irq_assert());
+        // one shot without the repeat bit
+        assert_eq!(wait_irq(&mut fds, &mut interrupts, 100), None);
+        fds.write(&mut interrupts, 0x4022, 0x03);
+        assert_eq!(wait_irq(&mut fds, &mut interrupts, 100), Some(11));
+        fds.read(&mut interrupts, 0x4030);
+        assert_eq!(wait_irq(&mut fds, &mut interrupts, 100), Some(11));
+        // acknowledging leaves an APU or mapper IRQ alone
+        interrupts.assert_irq();
+        fds.read(&mut interrupts, 0x4030);
+        assert!(interrupts.get_irq_assert());
+        interrupts.deassert_irq();
+        fds.write(&mut interrupts, 0x4022, 0x00);
+        assert!(!interrupts.get_irq_assert());
+    }
+
+    #[test]
+    fn disk_transfer() {
+        let side: Vec<u8> = test_side();
+        let mut fds: Fds = Fds::new(&side);
+        let mut interrupts: Interrupts = Interrupts::new();
+        fds.write(&mut interrupts, 0x4023, 0x01);
+        assert_eq!(fds.read(&mut interrupts, 0x4032) & 0x03, 0x02);
+        // motor on, read mode, transfer after the gap, disk IRQ
+        assert_eq!(fds.write(&mut interrupts, 0x4025, 0xC5), Some(Mirroring::Vertical));
+        // the start mark itself raises no IRQ
+        let gap: u64 = REWIND_CYCLES + BYTE_CYCLES * FIRST_GAP as u64;
+        let cycles: u64 = wait_irq(&mut fds, &mut interrupts, 2 * gap).unwrap();
+        assert!(cycles > gap);
+        assert_eq!(fds.read(&mut interrupts, 0x4032) & 0x03, 0x00);
+        assert_eq!(fds.read(&mut interrupts, 0x4030) & 0x02, 0x02);
+        assert!(!interrupts.get_irq_assert());
+        assert_eq!(fds.read(&mut interrupts, 0x4031), side[0]);
+        for data in side[1..15].iter() {
+            assert_eq!(wait_irq(&mut fds, &mut interrupts, 1000), Some(BYTE_CYCLES + 1));
+            assert_eq!(fds.read(&mut interrupts, 0x4031), *data);
+            assert!(!interrupts.get_irq_assert());
+        }
+        // no IRQ while disabled, the byte is still transferred
+        fds.write(&mut interrupts, 0x4025, 0x45);
+        assert_eq!(wait_irq(&mut fds, &mut interrupts, 1000), None);
+        assert_eq!(fds.read(&mut interrupts, 0x4030) & 0x02, 0x02);
+    }
+
+    #[test]
+    fn switch_side() {
+        let image: Vec<u8> = [test_side(), test_side()].concat();
+        let mut fds: Fds = Fds::new(&image);
+        let mut interrupts: Interrupts = Interrupts::new();
+        fds.write(&mut interrupts, 0x4023, 0x01);
+        assert_eq!(fds.side_count(), 2);
+        assert_eq!(fds.side(), Some(0));
+        for next in [1, 0].iter() {
+            fds.switch_side();
+            assert_eq!(fds.side(), None);
+            assert_eq!(fds.read(&mut interrupts, 0x4032) & 0x07, 0x07);
+            fds.run(&mut interrupts, SWAP_CYCLES - 1);
+            assert_eq!(fds.side(), None);
+            fds.run(&mut interrupts, 1);
+            assert_eq!(fds.side(), Some(*next));
+            assert_eq!(fds.read(&mut interrupts, 0x4032) & 0x05, 0x00);
+        }
+    }
+}
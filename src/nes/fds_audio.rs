@@ -0,0 +1,282 @@
+/*
+    [FDS audio] wavetable channel with frequency modulation
+    | addr        | description                                        |
+    +-------------+----------------------------------------------------+
+    | $4040-$407F | wavetable, 64 steps of 6 bit, writable by $4089 b7 |
+    | $4080       | volume envelope, b7 off (b0-5 gain), b6 increase   |
+    | $4082/$4083 | wave frequency 12 bit, $4083 b7 halt wave,         |
+    |             | b6 halt envelopes                                  |
+    | $4084       | mod envelope, same as $4080                        |
+    | $4085       | mod counter, 7 bit signed                          |
+    | $4086/$4087 | mod frequency 12 bit, $4087 b7 halt mod            |
+    | $4088       | mod table write while halted, 3 bit, 2 entries     |
+    | $4089       | b7 wavetable write, b0-1 master volume 2/2..2/5    |
+    | $408A       | envelope speed, 0 stops envelopes                  |
+    | $4090/$4092 | read volume gain / mod gain                        |
+    the envelopes tick every 8 * (speed + 1) * $408A CPU cycles.
+    there is no live audio output, the samples only go to the --record AVI.
+*/
+
+use super::region::Region;
+
+const SAMPLE_RATE: f64 = 44100.0;
+// mod counter change of the mod table values 0-7, 4 resets the counter
+const MOD_STEPS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+const MASTER_VOLUME: [u32; 4] = [30, 20, 15, 12];
+
+#[derive(Debug)]
+struct Envelope {
+    speed: u8,
+    gain: u8,
+    is_increase: bool,
+    is_disabled: bool,
+    counter: u32,
+}
+
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope { speed: 0, gain: 0, is_increase: false, is_disabled: true, counter: 0 }
+    }
+
+    fn write(&mut self, data: u8, master_speed: u8) {
+        self.speed = data & 0x3F;
+        self.is_increase = data & 0x40 > 0;
+        self.is_disabled = data & 0x80 > 0;
+        if self.is_disabled {
+            self.gain = data & 0x3F;
+        }
+        self.reset(master_speed);
+    }
+
+    fn reset(&mut self, master_speed: u8) {
+        self.counter = 8 * (self.speed as u32 + 1) * master_speed as u32;
+    }
+
+    fn clock(&mut self, master_speed: u8) {
+        if self.is_disabled || master_speed == 0 {
+            return;
+        }
+        if self.counter > 0 {
+            self.counter -= 1;
+            return;
+        }
+        self.reset(master_speed);
+        if self.is_increase && self.gain < 32 {
+            self.gain += 1;
+        } else if !self.is_increase && self.gain > 0 {
+            self.gain -= 1;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FdsAudio {
+    wave: [u8; 64],
+    wave_pos: usize,
+    wave_acc: u32,
+    wave_freq: u16,
+    is_wave_halted: bool,
+    is_wave_write: bool,
+    is_envelope_halted: bool,
+    volume: Envelope,
+    mod_env: Envelope,
+    mod_table: [u8; 64],
+    mod_pos: usize,
+    mod_acc: u32,
+    mod_freq: u16,
+    mod_counter: i8,
+    is_mod_halted: bool,
+    master_volume: usize,
+    master_speed: u8,
+    // output level averaged into samples
+    level_sum: f64,
+    level_count: u32,
+    sample_time: f64,
+    // cycles per second of the region, set by Cpu::set_region
+    cpu_clock: f64,
+    samples: Vec<i16>,
+}
+
+impl FdsAudio {
+    pub fn new() -> FdsAudio {
+        FdsAudio {
+            wave: [0; 64],
+            wave_pos: 0,
+            wave_acc: 0,
+            wave_freq: 0,
+            is_wave_halted: true,
+            is_wave_write: false,
+            is_envelope_halted: false,
+            volume: Envelope::new(),
+            mod_env: Envelope::new(),
+            mod_table: [0; 64],
+            mod_pos: 0,
+            mod_acc: 0,
+            mod_freq: 0,
+            mod_counter: 0,
+            is_mod_halted: true,
+            master_volume: 0,
+            master_speed: 0xE8,
+            level_sum: 0.0,
+            level_count: 0,
+            sample_time: 0.0,
+            cpu_clock: Region::Ntsc.cpu_clock(),
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4040..=0x407F => Some(self.wave[(addr - 0x4040) as usize] | 0x40),
+            0x4090 => Some(self.volume.gain | 0x40),
+            0x4092 => Some(self.mod_env.gain | 0x40),
+            _ => None,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4040..=0x407F if self.is_wave_write => self.wave[(addr - 0x4040) as usize] = data & 0x3F,
+            0x4080 => self.volume.write(data, self.master_speed),
+            0x4082 => self.wave_freq = (self.wave_freq & 0x0F00) | data as u16,
+            0x4083 => {
+                self.wave_freq = (self.wave_freq & 0x00FF) | ((data as u16 & 0x0F) << 8);
+                self.is_wave_halted = data & 0x80 > 0;
+                self.is_envelope_halted = data & 0x40 > 0;
+                if self.is_wave_halted {
+                    self.wave_pos = 0;
+                    self.wave_acc = 0;
+                }
+                if self.is_envelope_halted {
+                    self.volume.reset(self.master_speed);
+                    self.mod_env.reset(self.master_speed);
+                }
+            },
+            0x4084 => self.mod_env.write(data, self.master_speed),
+            0x4085 => self.mod_counter = (((data & 0x7F) << 1) as i8) >> 1,
+            0x4086 => self.mod_freq = (self.mod_freq & 0x0F00) | data as u16,
+            0x4087 => {
+                self.mod_freq = (self.mod_freq & 0x00FF) | ((data as u16 & 0x0F) << 8);
+                self.is_mod_halted = data & 0x80 > 0;
+                if self.is_mod_halted {
+                    self.mod_acc = 0;
+                }
+            },
+            0x4088 if self.is_mod_halted => {
+                self.mod_table[self.mod_pos] = data & 0x07;
+                self.mod_table[(self.mod_pos + 1) & 0x3F] = data & 0x07;
+                self.mod_pos = (self.mod_pos + 2) & 0x3F;
+            },
+            0x4089 => {
+                self.is_wave_write = data & 0x80 > 0;
+                self.master_volume = (data & 0x03) as usize;
+            },
+            0x408A => self.master_speed = data,
+            _ => (),
+        }
+    }
+
+    // wave frequency bent by the mod counter and gain
+    fn pitch(&self) -> u32 {
+        let mut temp: i32 = self.mod_counter as i32 * self.mod_env.gain as i32;
+        let remainder: i32 = temp & 0x0F;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if self.mod_counter < 0 { -1 } else { 2 };
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+        temp *= self.wave_freq as i32;
+        let remainder: i32 = temp & 0x3F;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        (self.wave_freq as i32 + temp).max(0) as u32
+    }
+
+    fn clock(&mut self) {
+        if !self.is_envelope_halted && !self.is_wave_halted {
+            self.volume.clock(self.master_speed);
+            self.mod_env.clock(self.master_speed);
+        }
+        if !self.is_mod_halted && self.mod_freq > 0 {
+            self.mod_acc += self.mod_freq as u32;
+            if self.mod_acc >= 0x10000 {
+                self.mod_acc -= 0x10000;
+                let step: u8 = self.mod_table[self.mod_pos];
+                self.mod_pos = (self.mod_pos + 1) & 0x3F;
+                self.mod_counter = if step == 4 {
+                    0
+                } else {
+                    // 7 bit signed wrap
+                    (self.mod_counter.wrapping_add(MOD_STEPS[step as usize]) << 1) >> 1
+                };
+            }
+        }
+        if !self.is_wave_halted && !self.is_wave_write {
+            self.wave_acc += self.pitch();
+            while self.wave_acc >= 0x10000 {
+                self.wave_acc -= 0x10000;
+                self.wave_pos = (self.wave_pos + 1) & 0x3F;
+            }
+        }
+    }
+
+    // 0-63 * gain 0-32, scaled by the master volume
+    fn level(&self) -> u32 {
+        let gain: u32 = self.volume.gain.min(32) as u32;
+        self.wave[self.wave_pos] as u32 * gain * MASTER_VOLUME[self.master_volume] / 30
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.cpu_clock = region.cpu_clock();
+    }
+
+    pub fn run(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.clock();
+            self.level_sum += self.level() as f64;
+            self.level_count += 1;
+            self.sample_time += SAMPLE_RATE;
+            if self.sample_time >= self.cpu_clock {
+                self.sample_time -= self.cpu_clock;
+                let level: f64 = self.level_sum / self.level_count as f64;
+                // 63 * 32 is the loudest
+                self.samples.push((level * 12.0) as i16);
+                self.level_sum = 0.0;
+                self.level_count = 0;
+            }
+        }
+    }
+
+    // 44100Hz mono samples since the last call
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        std::mem::take(&mut self.samples)
+    }
+}
+
+impl Default for FdsAudio {
+    fn default() -> FdsAudio {
+        FdsAudio::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one second of CPU cycles of each region is one second of samples
+    #[test]
+    fn region_sample_rate() {
+        for region in [Region::Ntsc, Region::Pal, Region::Dendy].iter() {
+            let mut audio: FdsAudio = FdsAudio::new();
+            audio.set_region(*region);
+            audio.run(region.cpu_clock().ceil() as u64);
+            assert_eq!(audio.take_samples().len(), SAMPLE_RATE as usize);
+        }
+    }
+}
@@ -2,18 +2,19 @@
 
 use super::cpu::*;
 use super::ppu::*;
+use super::render::FrameBuffer;
 
 extern crate sdl2;
 use sdl2::*;
 use sdl2::rect::Rect;
 use sdl2::video::*;
-use sdl2::pixels::Color;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::render::{Texture, TextureCreator};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::gfx::framerate::FPSManager;
 
 pub const SCALE: u32 = 2;
-pub const FPS: u32 = 60;
 pub const PAD_DELAY: usize = 10;
 pub const PAD_INTERVAL: usize = 10;
 
@@ -22,11 +23,25 @@ pub struct Game {
     canvas: sdl2::render::Canvas<Window>,
     sdl_context: Sdl,
     fps_manager: FPSManager,
+    texture_creator: TextureCreator<WindowContext>,
+    // streaming textures of Render::data, dbg_bg_data and dbg_pattern_data
+    // with unsafe_textures a Texture has no lifetime and no Drop, SDL frees
+    // it with the renderer when canvas and texture_creator are dropped.
+    // a replaced texture is destroyed while both are still alive
+    screen_texture: Texture,
+    name_table_texture: Texture,
+    pattern_table_texture: Texture,
+    // size of the game screen in the window
+    screen_size: (u32, u32),
+    // hold Tab to run without waiting for the frame rate
+    is_fast_forward: bool,
 }
 
 #[derive(Debug, PartialEq)]
 pub enum GameStatus {
     Exit,
+    Screenshot,
+    SwitchDiskSide,
     Ok
 }
 
@@ -39,13 +54,17 @@ pub enum UpdateMode {
 
 
 impl Game {
-    pub fn new(is_debug: bool) -> Result<Game, Box<dyn std::error::Error>> {
+    pub fn new(
+        is_debug: bool, screen_size: (u32, u32), fps: u32
+    ) -> Result<Game, Box<dyn std::error::Error>> {
         let sdl_context: Sdl = sdl2::init()?;
         let video_subsystem = sdl_context.video()?;
+        // name table on the right, pattern table below the screen
         let screen = if is_debug {
-            (3 * SCALE * H_SIZE as u32, 2 * SCALE * V_SIZE as u32)
+            (screen_size.0 + 2 * SCALE * H_SIZE as u32,
+                (screen_size.1 + SCALE * V_SIZE as u32).max(2 * SCALE * V_SIZE as u32))
         } else {
-            (SCALE * H_SIZE as u32, SCALE * V_SIZE as u32)
+            screen_size
         };
         let window = video_subsystem
             .window(
@@ -58,11 +77,17 @@ impl Game {
             .map_err(|e| e.to_string())?;
         let mut canvas = window
             .into_canvas()
-            .software()
             .build()
             .map_err(|e| e.to_string())?;
+        let texture_creator = canvas.texture_creator();
+        let screen_texture = texture_creator.create_texture_streaming(
+            PixelFormatEnum::ARGB8888, H_SIZE as u32, V_SIZE as u32)?;
+        let name_table_texture = texture_creator.create_texture_streaming(
+            PixelFormatEnum::ARGB8888, 2 * H_SIZE as u32, 2 * V_SIZE as u32)?;
+        let pattern_table_texture = texture_creator.create_texture_streaming(
+            PixelFormatEnum::ARGB8888, H_SIZE as u32, V_SIZE as u32)?;
         let mut fps_manager = FPSManager::new();
-        _ = fps_manager.set_framerate(FPS);
+        _ = fps_manager.set_framerate(fps);
 
         canvas.set_draw_color(Color::RGB(0, 0, 0));
         canvas.clear();
@@ -71,8 +96,14 @@ impl Game {
         Ok(Game {
             canvas: canvas,
             sdl_context: sdl_context,
-            fps_manager: fps_manager
-        })  
+            fps_manager,
+            texture_creator,
+            screen_texture,
+            name_table_texture,
+            pattern_table_texture,
+            screen_size,
+            is_fast_forward: false,
+        })
     }
 
     pub fn check_key(
@@ -83,6 +114,49 @@ impl Game {
                 Event::Quit { .. } | Event::KeyDown {
                     keycode: Option::Some(Keycode::Escape), ..
                 } => return Ok(GameStatus::Exit),
+                Event::KeyDown {keycode: Option::Some(Keycode::F12), ..} => {
+                    return Ok(GameStatus::Screenshot);
+                },
+                Event::KeyDown {keycode: Option::Some(Keycode::F8), ..} => {
+                    return Ok(GameStatus::SwitchDiskSide);
+                },
+                // Vs. System coin 1, coin 2 and service
+                Event::KeyDown {keycode: Option::Some(Keycode::Num5), ..} => {
+                    if let Some(vs) = &mut cpu.vs {
+                        vs.coin1 = true;
+                    }
+                },
+                Event::KeyUp {keycode: Option::Some(Keycode::Num5), ..} => {
+                    if let Some(vs) = &mut cpu.vs {
+                        vs.coin1 = false;
+                    }
+                },
+                Event::KeyDown {keycode: Option::Some(Keycode::Num6), ..} => {
+                    if let Some(vs) = &mut cpu.vs {
+                        vs.coin2 = true;
+                    }
+                },
+                Event::KeyUp {keycode: Option::Some(Keycode::Num6), ..} => {
+                    if let Some(vs) = &mut cpu.vs {
+                        vs.coin2 = false;
+                    }
+                },
+                Event::KeyDown {keycode: Option::Some(Keycode::Num9), ..} => {
+                    if let Some(vs) = &mut cpu.vs {
+                        vs.service = true;
+                    }
+                },
+                Event::KeyUp {keycode: Option::Some(Keycode::Num9), ..} => {
+                    if let Some(vs) = &mut cpu.vs {
+                        vs.service = false;
+                    }
+                },
+                Event::KeyDown {keycode: Option::Some(Keycode::Tab), ..} => {
+                    self.is_fast_forward = true;
+                },
+                Event::KeyUp {keycode: Option::Some(Keycode::Tab), ..} => {
+                    self.is_fast_forward = false;
+                },
                 Event::KeyDown {keycode: Option::Some(Keycode::A), ..} => {
                     cpu.keypad1.a = true;
                 },
@@ -137,36 +211,49 @@ impl Game {
         Ok(GameStatus::Ok)
     }
 
+    // copy the frame into its texture and draw it scaled, shown by present()
     pub fn update(
-        &mut self, data: &Vec<Vec<u64>>, mode: UpdateMode
+        &mut self, data: &FrameBuffer, mode: UpdateMode
     ) -> Result<GameStatus, Box<dyn std::error::Error>> {
-
-        let base = match mode {
-            UpdateMode::Game => (0,0),
-            UpdateMode::NameTable => (SCALE as usize * H_SIZE, 0usize),
-            UpdateMode::PatternTable => (0usize, SCALE as usize * V_SIZE),
-            _ => panic!("invalid mode {:?}", mode),
+        // the game screen keeps its size when a filter changes the width
+        let query = self.screen_texture.query();
+        if mode == UpdateMode::Game &&
+                (query.width as usize != data.width || query.height as usize != data.height) {
+            let texture: Texture = self.texture_creator.create_texture_streaming(
+                PixelFormatEnum::ARGB8888, data.width as u32, data.height as u32)?;
+            let old: Texture = std::mem::replace(&mut self.screen_texture, texture);
+            // the canvas owning it is alive as long as self
+            unsafe { old.destroy(); }
+        }
+        let (texture, base, size) = match mode {
+            UpdateMode::Game => (&mut self.screen_texture, (0, 0), self.screen_size),
+            UpdateMode::NameTable =>
+                (&mut self.name_table_texture, (self.screen_size.0 as usize, 0usize),
+                (SCALE * data.width as u32, SCALE * data.height as u32)),
+            UpdateMode::PatternTable =>
+                (&mut self.pattern_table_texture, (0usize, self.screen_size.1 as usize),
+                (SCALE * data.width as u32, SCALE * data.height as u32)),
         };
-
-        for i in 0..data.len(){
-            for j in 0..data[0].len(){
-                // print!("{}", if *b > 0x050505 {"#"} else {" "})
-                let r: u8 = ((data[i][j] & 0xFF0000) >> 16) as u8;
-                let g: u8 = ((data[i][j] & 0x00FF00) >> 8) as u8;
-                let b: u8 = (data[i][j] & 0x0000FF) as u8;
-                let a: u8 = 0xFF;
-                self.canvas.set_draw_color(Color::RGBA(r, g, b, a));
-                _ = self.canvas.fill_rect(Rect::new(
-                    (base.0 + SCALE as usize * j) as i32,
-                    (base.1 + SCALE as usize * i) as i32,
-                    SCALE,
-                    SCALE));
+        texture.with_lock(None, |buf: &mut [u8], pitch: usize| {
+            for y in 0..data.height {
+                let line = &mut buf[y * pitch..y * pitch + 4 * data.width];
+                for (dst, pixel) in line.chunks_exact_mut(4).zip(data.row(y).iter()) {
+                    dst.copy_from_slice(&pixel.to_ne_bytes());
+                }
             }
-        }
-        self.canvas.present();
-        self.fps_manager.delay();
-        // dbg!(self.fps_manager.get_framerate());
-        
+        })?;
+        self.canvas.copy(texture, None, Rect::new(
+            base.0 as i32,
+            base.1 as i32,
+            size.0,
+            size.1))?;
         Ok(GameStatus::Ok)
     }
-}
\ No newline at end of file
+
+    pub fn present(&mut self) {
+        self.canvas.present();
+        if !self.is_fast_forward {
+            self.fps_manager.delay();
+        }
+    }
+}
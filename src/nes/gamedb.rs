@@ -0,0 +1,243 @@
+use std::fs;
+
+use super::cassette::{Header, Mirroring, Timing};
+
+/*
+    [Rom database] gamedb.txt bundled, then ./gamedb.txt if present
+    one game per line, '#' starts a comment
+
+    | column     | value                                          |
+    +------------+------------------------------------------------+
+    | crc32      | hex, PRG ROM then CHR ROM without header       |
+    | sha1       | hex of the same data, - when unknown           |
+    | mapper.sub | mapper and submapper                           |
+    | mirroring  | H, V, 4 four screen, M selected by the mapper  |
+    | battery    | 0 or 1                                         |
+    | prg-ram    | volatile PRG RAM bytes                         |
+    | prg-nvram  | battery backed PRG RAM bytes                   |
+    | chr-ram    | CHR RAM bytes                                  |
+    | region     | NTSC, PAL, MULTI or DENDY                      |
+    | board      | board name such as NES-SNROM                   |
+    | title      | rest of the line                               |
+
+    a sha1 given in the entry must match too, otherwise the crc32 is enough.
+    a matching entry overrides the header fields, see Cassette::apply_database.
+
+    the bundled file is only a seed, nes20db is not redistributed here.
+    --import-nes20db <nes20db.xml> gamedb.txt converts the NES 2.0 XML
+    database into ./gamedb.txt, which is loaded after the bundled one.
+*/
+
+
+const BUNDLED: &str = include_str!("gamedb.txt");
+pub const USER_DB: &str = "gamedb.txt";
+
+#[derive(Debug, Clone)]
+pub struct GameInfo {
+    pub crc32: u32,
+    pub sha1: Option<String>,
+    pub mapper: u16,
+    pub submapper: u8,
+    // None when the mapper selects it
+    pub mirroring: Option<Mirroring>,
+    pub has_battery: bool,
+    pub prog_ram_size: usize,
+    pub prog_nvram_size: usize,
+    pub char_ram_size: usize,
+    pub timing: Timing,
+    pub board: String,
+    pub title: String,
+}
+
+impl GameInfo {
+    // writes the fields into the header, returns the names of changed fields
+    pub fn apply(&self, header: &mut Header) -> Vec<String> {
+        let mut changed: Vec<String> = Vec::new();
+        if (header.mapper, header.submapper) != (self.mapper, self.submapper) {
+            changed.push(format!("mapper {}.{} -> {}.{}",
+                header.mapper, header.submapper, self.mapper, self.submapper));
+            header.mapper = self.mapper;
+            header.submapper = self.submapper;
+        }
+        if let Some(mirroring) = self.mirroring {
+            if header.mirroring != mirroring {
+                changed.push(format!("mirroring {:?} -> {:?}", header.mirroring, mirroring));
+                header.mirroring = mirroring;
+            }
+        }
+        if header.has_battery != self.has_battery {
+            changed.push(format!("battery {} -> {}", header.has_battery, self.has_battery));
+            header.has_battery = self.has_battery;
+        }
+        let ram = (self.prog_ram_size, self.prog_nvram_size, self.char_ram_size);
+        if (header.prog_ram_size, header.prog_nvram_size, header.char_ram_size) != ram {
+            changed.push(format!("prg ram {:#X}/{:#X} -> {:#X}/{:#X}, chr ram {:#X} -> {:#X}",
+                header.prog_ram_size, header.prog_nvram_size, ram.0, ram.1,
+                header.char_ram_size, ram.2));
+            header.prog_ram_size = ram.0;
+            header.prog_nvram_size = ram.1;
+            header.char_ram_size = ram.2;
+        }
+        if header.timing != self.timing {
+            changed.push(format!("timing {:?} -> {:?}", header.timing, self.timing));
+            header.timing = self.timing;
+        }
+        changed
+    }
+}
+
+fn parse_line(line: &str) -> Result<GameInfo, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    if words.len() < 11 {
+        return Err(format!("{} columns, 11 expected", words.len()));
+    }
+    let crc32 = u32::from_str_radix(words[0], 16).map_err(|_| format!("invalid crc32 {}", words[0]))?;
+    let sha1 = match words[1] {
+        "-" => None,
+        s if s.len() == 40 && s.chars().all(|c| c.is_ascii_hexdigit()) => Some(s.to_uppercase()),
+        s => return Err(format!("invalid sha1 {}", s)),
+    };
+    let mut mapper_words = words[2].splitn(2, '.');
+    let mapper: u16 = mapper_words.next().unwrap_or("").parse()
+        .map_err(|_| format!("invalid mapper {}", words[2]))?;
+    let submapper: u8 = mapper_words.next().unwrap_or("0").parse()
+        .map_err(|_| format!("invalid mapper {}", words[2]))?;
+    let mirroring = match words[3] {
+        "H" => Some(Mirroring::Horizontal),
+        "V" => Some(Mirroring::Vertical),
+        "4" => Some(Mirroring::FourScreen),
+        "M" => None,
+        s => return Err(format!("invalid mirroring {}", s)),
+    };
+    let size = |s: &str| s.parse::<usize>().map_err(|_| format!("invalid size {}", s));
+    let timing = match words[8] {
+        "NTSC" => Timing::Ntsc,
+        "PAL" => Timing::Pal,
+        "MULTI" => Timing::MultiRegion,
+        "DENDY" => Timing::Dendy,
+        s => return Err(format!("invalid region {}", s)),
+    };
+    Ok(GameInfo {
+        crc32,
+        sha1,
+        mapper,
+        submapper,
+        mirroring,
+        has_battery: words[4] == "1",
+        prog_ram_size: size(words[5])?,
+        prog_nvram_size: size(words[6])?,
+        char_ram_size: size(words[7])?,
+        timing,
+        board: words[9].to_string(),
+        title: words[10..].join(" "),
+    })
+}
+
+pub fn parse(text: &str) -> Result<Vec<GameInfo>, String> {
+    let mut games: Vec<GameInfo> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        games.push(parse_line(line).map_err(|e| format!("line {}: {}", i + 1, e))?);
+    }
+    Ok(games)
+}
+
+// the bundled entries then ./gamedb.txt
+pub fn load() -> Vec<GameInfo> {
+    let mut games: Vec<GameInfo> = parse(BUNDLED).unwrap_or_else(|e| panic!("bundled gamedb.txt: {}", e));
+    if let Ok(text) = fs::read_to_string(USER_DB) {
+        match parse(&text) {
+            Ok(mut user) => games.append(&mut user),
+            Err(e) => println!("{}: {}", USER_DB, e),
+        }
+    }
+    games
+}
+
+// value of attr in the first <tag ...> of xml
+fn xml_attr<'a>(xml: &'a str, tag: &str, attr: &str) -> Option<&'a str> {
+    let start: usize = xml.find(&format!("<{} ", tag))?;
+    let element: &str = &xml[start..start + xml[start..].find('>')?];
+    let key: String = format!(" {}=\"", attr);
+    let value: &str = &element[element.find(&key)? + key.len()..];
+    Some(&value[..value.find('"')?])
+}
+
+// nes20db.xml to gamedb.txt lines, the comment before each <game> is the title
+pub fn import_nes20db(xml: &str) -> Result<String, String> {
+    let mut out: String = String::from("# generated from nes20db by --import-nes20db\n");
+    let mut count: usize = 0;
+    // each part with the one before it, which holds the title comment
+    let parts = xml.split("<game>").zip(xml.split("<game>").skip(1));
+    for (i, (before, part)) in parts.enumerate().map(|(i, p)| (i + 1, p)) {
+        let game: &str = part.split("</game>").next().unwrap_or("");
+        let title: &str = before.rfind("<!--")
+            .map_or("-", |p| before[p + 4..].split("-->").next().unwrap_or("-").trim());
+        let title: &str = title.rsplit_once('.').map_or(title, |(name, _)| name);
+        let get = |tag: &str, attr: &str| xml_attr(game, tag, attr);
+        let crc32: &str = get("rom", "crc32").ok_or(format!("game {} has no rom crc32", i))?;
+        let size = |tag: &str| get(tag, "size").unwrap_or("0");
+        let region: &str = match get("console", "region").unwrap_or("0") {
+            "1" => "PAL",
+            "2" => "MULTI",
+            "3" => "DENDY",
+            _ => "NTSC",
+        };
+        let mirroring: &str = match get("pcb", "mirroring").unwrap_or("H") {
+            m @ ("H" | "V" | "4") => m,
+            _ => "M",
+        };
+        out += &format!("{} {} {}.{} {} {} {} {} {} {} - {}\n",
+            crc32, get("rom", "sha1").unwrap_or("-"),
+            get("pcb", "mapper").unwrap_or("0"), get("pcb", "submapper").unwrap_or("0"),
+            mirroring, get("pcb", "battery").unwrap_or("0"),
+            size("prgram"), size("prgnvram"), size("chrram"), region, title.replace('#', ""));
+        count += 1;
+    }
+    // the output must load
+    parse(&out)?;
+    println!("gamedb: {} games", count);
+    Ok(out)
+}
+
+// sha1 is hex of PRG ROM then CHR ROM, the last match wins so ./gamedb.txt can override
+pub fn find<'a>(games: &'a [GameInfo], crc32: u32, sha1: &str) -> Option<&'a GameInfo> {
+    games.iter().rev().find(|g| {
+        g.crc32 == crc32 && g.sha1.as_ref().is_none_or(|s| s == sha1)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_database_parses() {
+        let games = parse(BUNDLED).unwrap();
+        let smb = find(&games, 0x3337EC46, "").unwrap();
+        assert_eq!((smb.mapper, smb.mirroring), (0, Some(Mirroring::Vertical)));
+        assert_eq!(smb.title, "Super Mario Bros.");
+    }
+
+    #[test]
+    fn import_xml() {
+        let xml = "<database>\n<!-- Game (USA).nes -->\n<game>\n\
+            <rom size=\"40960\" crc32=\"12345678\"/>\n\
+            <pcb mapper=\"1\" submapper=\"0\" mirroring=\"H\" battery=\"1\"/>\n\
+            <prgnvram size=\"8192\"/>\n<console type=\"0\" region=\"1\"/>\n\
+            </game>\n<!-- Other (Japan).nes -->\n<game>\n\
+            <rom size=\"262144\" crc32=\"9ABCDEF0\"/>\n\
+            <pcb mapper=\"4\" submapper=\"0\" mirroring=\"V\" battery=\"0\"/>\n\
+            </game>\n</database>";
+        let games = parse(&import_nes20db(xml).unwrap()).unwrap();
+        let game = find(&games, 0x12345678, "").unwrap();
+        assert_eq!((game.mapper, game.has_battery, game.prog_nvram_size), (1, true, 8192));
+        assert_eq!((game.timing, game.title.as_str()), (Timing::Pal, "Game (USA)"));
+        // titles come from the comment right before each game
+        let game = find(&games, 0x9ABCDEF0, "").unwrap();
+        assert_eq!((game.mapper, game.title.as_str()), (4, "Other (Japan)"));
+    }
+}
@@ -0,0 +1,212 @@
+/*
+    [Deflate] RFC 1951 decoder for zip and gzip roms
+    | BTYPE | block                                            |
+    +-------+--------------------------------------------------+
+    |   0   | stored, LEN and NLEN then LEN bytes              |
+    |   1   | fixed huffman codes                              |
+    |   2   | dynamic huffman codes, code lengths in the block |
+    bits are read from the lsb of each byte, huffman codes msb first.
+*/
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// order of the code length code lengths
+const CLEN_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+const MAX_BITS: usize = 15;
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+    bits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader { data, pos: 0, bit: 0, bits: 0 }
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32, String> {
+        while self.bits < n {
+            let byte = *self.data.get(self.pos).ok_or("deflate stream is truncated")?;
+            self.bit |= (byte as u32) << self.bits;
+            self.pos += 1;
+            self.bits += 8;
+        }
+        let v: u32 = self.bit & ((1u32 << n) - 1);
+        self.bit = if n == 32 { 0 } else { self.bit >> n };
+        self.bits -= n;
+        Ok(v)
+    }
+
+    fn align(&mut self) {
+        self.bit = 0;
+        self.bits = 0;
+    }
+}
+
+// canonical huffman code, symbols sorted by code
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman, String> {
+        let mut counts: [u16; MAX_BITS + 1] = [0; MAX_BITS + 1];
+        for l in lengths.iter() {
+            counts[*l as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets: [u16; MAX_BITS + 2] = [0; MAX_BITS + 2];
+        for i in 1..=MAX_BITS {
+            offsets[i + 1] = offsets[i] + counts[i];
+        }
+        let mut symbols: Vec<u16> = vec![0; offsets[MAX_BITS + 1] as usize];
+        for (s, l) in lengths.iter().enumerate() {
+            if *l > 0 {
+                symbols[offsets[*l as usize] as usize] = s as u16;
+                offsets[*l as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        let (mut code, mut first, mut index): (i32, i32, i32) = (0, 0, 0);
+        for len in 1..=MAX_BITS {
+            code |= reader.bits(1)? as i32;
+            let count: i32 = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid huffman code".to_string())
+    }
+}
+
+fn fixed_codes() -> Result<(Huffman, Huffman), String> {
+    let mut lengths: [u8; 288] = [8; 288];
+    lengths[144..256].iter_mut().for_each(|l| *l = 9);
+    lengths[256..280].iter_mut().for_each(|l| *l = 7);
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let hlit: usize = reader.bits(5)? as usize + 257;
+    let hdist: usize = reader.bits(5)? as usize + 1;
+    let hclen: usize = reader.bits(4)? as usize + 4;
+    let mut clen: [u8; 19] = [0; 19];
+    for i in 0..hclen {
+        clen[CLEN_ORDER[i]] = reader.bits(3)? as u8;
+    }
+    let clen_code = Huffman::new(&clen)?;
+    let mut lengths: Vec<u8> = Vec::with_capacity(hlit + hdist);
+    while lengths.len() < hlit + hdist {
+        let (value, repeat): (u8, u32) = match clen_code.decode(reader)? {
+            sym @ 0..=15 => (sym as u8, 1),
+            16 => {
+                let prev = *lengths.last().ok_or("repeat without previous length")?;
+                (prev, 3 + reader.bits(2)?)
+            },
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() > hlit + hdist {
+        return Err("code lengths overflow".to_string());
+    }
+    Ok((Huffman::new(&lengths[..hlit])?, Huffman::new(&lengths[hlit..])?))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    lit: &Huffman,
+    dist: &Huffman
+) -> Result<(), String> {
+    loop {
+        let sym: usize = lit.decode(reader)? as usize;
+        if sym < 256 {
+            out.push(sym as u8);
+            continue;
+        }
+        if sym == 256 {
+            return Ok(());
+        }
+        let i: usize = sym - 257;
+        if i >= LENGTH_BASE.len() {
+            return Err(format!("invalid length symbol {}", sym));
+        }
+        let length: usize = LENGTH_BASE[i] as usize + reader.bits(LENGTH_EXTRA[i] as u32)? as usize;
+        let d: usize = dist.decode(reader)? as usize;
+        if d >= DIST_BASE.len() {
+            return Err(format!("invalid distance symbol {}", d));
+        }
+        let distance: usize = DIST_BASE[d] as usize + reader.bits(DIST_EXTRA[d] as u32)? as usize;
+        if distance > out.len() {
+            return Err(format!("distance {} before the start of data", distance));
+        }
+        let start: usize = out.len() - distance;
+        for k in 0..length {
+            let b = out[start + k];
+            out.push(b);
+        }
+    }
+}
+
+// raw deflate stream, returns the data and the bytes consumed
+pub fn inflate(data: &[u8]) -> Result<(Vec<u8>, usize), String> {
+    let mut reader = BitReader::new(data);
+    let mut out: Vec<u8> = Vec::new();
+    loop {
+        let is_final: bool = reader.bits(1)? > 0;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let p: usize = reader.pos;
+                if p + 4 > data.len() {
+                    return Err("deflate stream is truncated".to_string());
+                }
+                let len: usize = u16::from_le_bytes([data[p], data[p + 1]]) as usize;
+                let nlen: usize = u16::from_le_bytes([data[p + 2], data[p + 3]]) as usize;
+                if len != !nlen & 0xFFFF {
+                    return Err("stored block length mismatch".to_string());
+                }
+                if p + 4 + len > data.len() {
+                    return Err("deflate stream is truncated".to_string());
+                }
+                out.extend_from_slice(&data[p + 4..p + 4 + len]);
+                reader.pos = p + 4 + len;
+            },
+            1 => {
+                let (lit, dist) = fixed_codes()?;
+                inflate_block(&mut reader, &mut out, &lit, &dist)?;
+            },
+            2 => {
+                let (lit, dist) = dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &mut out, &lit, &dist)?;
+            },
+            _ => return Err("invalid deflate block type".to_string()),
+        }
+        if is_final {
+            return Ok((out, reader.pos));
+        }
+    }
+}
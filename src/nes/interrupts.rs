@@ -3,6 +3,8 @@
 #[derive(Debug)]
 pub struct Interrupts {
     irq: bool,
+    // level of the FDS timer and disk IRQs, acknowledged by the FDS only
+    fds_irq: bool,
     nmi: bool
 }
 
@@ -10,11 +12,12 @@ impl Interrupts {
     pub fn new() -> Interrupts {
         Interrupts {
             irq: false,
+            fds_irq: false,
             nmi: false,
         }
     }
     pub fn get_irq_assert(&self) -> bool {
-        self.irq
+        self.irq || self.fds_irq
     }
     pub fn get_nmi_assert(&self) -> bool {
         self.nmi
@@ -25,6 +28,9 @@ impl Interrupts {
     pub fn deassert_irq(&mut self) {
         self.irq = false;
     }
+    pub fn set_fds_irq(&mut self, is_asserted: bool) {
+        self.fds_irq = is_asserted;
+    }
     pub fn assert_nmi(&mut self) {
         self.nmi = true;
     }
@@ -0,0 +1,130 @@
+use super::colors::{self, NtscParams, TABLE_SIZE};
+use super::ppu::{Image, H_SIZE, V_SIZE};
+use super::render::FrameBuffer;
+
+/*
+    [NTSC filter]
+    every PPU pixel is 8 samples of the composite signal and the
+    color carrier is 12 samples long. a line starts 4 samples after
+    the line above and each frame starts 4 samples after the last
+    one, which makes the dot crawl. luma is averaged over one
+    carrier cycle and chroma over two, so colors bleed into the
+    neighbouring pixels.
+
+    | samples | description                                 |
+    +---------+---------------------------------------------+
+    |    8    | one PPU pixel                               |
+    |    4    | one output pixel, NTSC_WIDTH = 2 * H_SIZE   |
+    |   12    | luma window                                 |
+    |   24    | chroma window                               |
+*/
+
+pub const SAMPLES_PER_PIXEL: usize = 8;
+pub const SAMPLES_PER_OUTPUT: usize = 4;
+pub const NTSC_WIDTH: usize = H_SIZE * SAMPLES_PER_PIXEL / SAMPLES_PER_OUTPUT;
+const PHASE_NUM: usize = 12;
+const LINE_PHASE_STEP: usize = 4;
+const FRAME_PHASE_STEP: usize = 4;
+const LUMA_WINDOW: usize = 12;
+const CHROMA_WINDOW: usize = 24;
+// samples outside the line for the chroma window
+const PADDING: usize = CHROMA_WINDOW / 2;
+const LINE_SAMPLES: usize = H_SIZE * SAMPLES_PER_PIXEL + 2 * PADDING;
+
+#[derive(Debug)]
+pub struct NtscFilter {
+    params: NtscParams,
+    // normalized signal of every screen pixel at each phase
+    levels: Vec<[f64; PHASE_NUM]>,
+    // cos and sin of the carrier at each phase
+    carrier: [(f64, f64); PHASE_NUM],
+    frame_phase: usize,
+    // prefix sums of the signal and the signal demodulated into I and Q
+    sum_y: Vec<f64>,
+    sum_i: Vec<f64>,
+    sum_q: Vec<f64>,
+}
+
+impl NtscFilter {
+    pub fn new(params: NtscParams) -> NtscFilter {
+        let mut levels: Vec<[f64; PHASE_NUM]> = vec![[0.0; PHASE_NUM]; TABLE_SIZE];
+        for (pixel, level) in levels.iter_mut().enumerate() {
+            for (phase, v) in level.iter_mut().enumerate() {
+                let emphasis: usize = if params.is_pal { colors::swap_red_green(pixel >> 6) } else { pixel >> 6 };
+                *v = colors::normalized_signal(pixel & 0x3F, emphasis, phase);
+            }
+        }
+        let mut carrier: [(f64, f64); PHASE_NUM] = [(0.0, 0.0); PHASE_NUM];
+        for (phase, c) in carrier.iter_mut().enumerate() {
+            let angle: f64 = colors::carrier_angle(phase, &params);
+            *c = (angle.cos(), angle.sin());
+        }
+        NtscFilter {
+            params,
+            levels,
+            carrier,
+            frame_phase: 0,
+            sum_y: vec![0.0; LINE_SAMPLES + 1],
+            sum_i: vec![0.0; LINE_SAMPLES + 1],
+            sum_q: vec![0.0; LINE_SAMPLES + 1],
+        }
+    }
+
+    // encode one line into samples, the edge pixels are repeated into the padding
+    fn encode_line(&mut self, line: &[u16], line_phase: usize) {
+        for s in 0..LINE_SAMPLES {
+            let x: usize = (s.saturating_sub(PADDING) / SAMPLES_PER_PIXEL).min(H_SIZE - 1);
+            let phase: usize = (line_phase + s + PHASE_NUM - PADDING % PHASE_NUM) % PHASE_NUM;
+            let v: f64 = self.levels[line[x] as usize][phase];
+            let (cos, sin) = self.carrier[phase];
+            self.sum_y[s + 1] = self.sum_y[s] + v;
+            self.sum_i[s + 1] = self.sum_i[s] + v * cos;
+            self.sum_q[s + 1] = self.sum_q[s] + v * sin;
+        }
+    }
+
+    // out must be NTSC_WIDTH x V_SIZE
+    pub fn apply(&mut self, image: &Image, out: &mut FrameBuffer) {
+        for (y, line) in image.screen.iter().enumerate().take(V_SIZE) {
+            let line_phase: usize = (self.frame_phase + y * LINE_PHASE_STEP) % PHASE_NUM;
+            self.encode_line(line, line_phase);
+            for x in 0..NTSC_WIDTH {
+                let center: usize = PADDING + x * SAMPLES_PER_OUTPUT + SAMPLES_PER_OUTPUT / 2;
+                let (l0, l1) = (center - LUMA_WINDOW / 2, center + LUMA_WINDOW / 2);
+                let (c0, c1) = (center - CHROMA_WINDOW / 2, center + CHROMA_WINDOW / 2);
+                let luma: f64 = (self.sum_y[l1] - self.sum_y[l0]) / LUMA_WINDOW as f64;
+                let i: f64 = (self.sum_i[c1] - self.sum_i[c0]) * 2.0 / CHROMA_WINDOW as f64;
+                let q: f64 = (self.sum_q[c1] - self.sum_q[c0]) * 2.0 / CHROMA_WINDOW as f64;
+                out.set(x, y, colors::yiq_to_rgb(luma, i, q, &self.params));
+            }
+        }
+        self.frame_phase = (self.frame_phase + FRAME_PHASE_STEP) % PHASE_NUM;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // away from edges a solid color decodes to the generated palette
+    #[test]
+    fn solid_color_matches_palette() {
+        let params = NtscParams::new();
+        let table = colors::generate_ntsc(&params);
+        let mut filter = NtscFilter::new(params);
+        let mut image = Image::new();
+        let mut out = FrameBuffer::new(NTSC_WIDTH, V_SIZE);
+        for color in [0x00u16, 0x16, 0x2A, 0x0F, 0x30 | 0x40] {
+            for line in image.screen.iter_mut() {
+                line.iter_mut().for_each(|p| *p = color);
+            }
+            filter.apply(&image, &mut out);
+            let rgb: u64 = (out.get(NTSC_WIDTH / 2, 10) & 0xFFFFFF) as u64;
+            for shift in [16, 8, 0] {
+                let a = (rgb >> shift) & 0xFF;
+                let b = (table[color as usize] >> shift) & 0xFF;
+                assert!(a.abs_diff(b) <= 2, "{:02X}: {:06X} {:06X}", color, rgb, table[color as usize]);
+            }
+        }
+    }
+}
@@ -0,0 +1,428 @@
+use std::fs;
+use std::path::Path;
+
+use super::checksum::crc32;
+
+/*
+    [Soft patch] applied to the whole rom file, header included
+    | format | magic | checksums                       |
+    +--------+-------+---------------------------------+
+    | IPS    | PATCH | none                            |
+    | UPS    | UPS1  | crc32 of source, target, patch  |
+    | BPS    | BPS1  | crc32 of source, target, patch  |
+
+    IPS  records of 3 byte offset, 2 byte size and data, size 0 is
+         RLE with 2 byte count and a value. "EOF" ends the records,
+         a 3 byte size after it truncates the output.
+    UPS  source size, target size, then hunks of relative offset and
+         xor bytes ended by 0.
+    BPS  source size, target size, metadata, then actions of
+         (length - 1) << 2 | command
+         0 SourceRead  1 TargetRead  2 SourceCopy  3 TargetCopy
+    sizes and offsets of UPS and BPS are variable length numbers.
+    the patch and source crc32 are checked before decoding, the target
+    size is limited to TARGET_SIZE_MAX.
+
+    without --patch, <rom>.ips, <rom>.ups and <rom>.bps next to the rom
+    are applied in this order.
+*/
+
+pub const PATCH_EXTENSIONS: [&str; 3] = ["ips", "ups", "bps"];
+const IPS_EOF: usize = 0x454F46;
+const IPS_RECORD_MAX: usize = 0xFFFF;
+// far above any cartridge, keeps a bad size from allocating gigabytes
+const TARGET_SIZE_MAX: usize = 0x4000000;
+
+fn read_number(patch: &[u8], p: &mut usize) -> Result<usize, String> {
+    let (mut data, mut shift): (usize, usize) = (0, 1);
+    let overflow = || "patch number is too large".to_string();
+    loop {
+        let x = *patch.get(*p).ok_or("patch is truncated")?;
+        *p += 1;
+        data = ((x & 0x7F) as usize).checked_mul(shift)
+            .and_then(|d| data.checked_add(d)).ok_or_else(overflow)?;
+        if x & 0x80 > 0 {
+            return Ok(data);
+        }
+        shift = shift.checked_mul(0x80).ok_or_else(overflow)?;
+        data = data.checked_add(shift).ok_or_else(overflow)?;
+    }
+}
+
+fn write_number(out: &mut Vec<u8>, mut data: usize) {
+    loop {
+        let x: u8 = (data & 0x7F) as u8;
+        data >>= 7;
+        if data == 0 {
+            out.push(0x80 | x);
+            return;
+        }
+        out.push(x);
+        data -= 1;
+    }
+}
+
+fn u32le(data: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]])
+}
+
+// patch and source crc32 at the end of UPS and BPS, before decoding
+fn check_patch(source: &[u8], patch: &[u8]) -> Result<(), String> {
+    let footer: usize = patch.len() - 12;
+    if crc32(&patch[..patch.len() - 4]) != u32le(patch, footer + 8) {
+        return Err("patch crc32 mismatch, the patch is corrupted".to_string());
+    }
+    if crc32(source) != u32le(patch, footer) {
+        return Err(format!("rom crc32 {:08X}, the patch expects {:08X}",
+            crc32(source), u32le(patch, footer)));
+    }
+    Ok(())
+}
+
+fn check_target(target: &[u8], patch: &[u8]) -> Result<(), String> {
+    if crc32(target) != u32le(patch, patch.len() - 8) {
+        return Err("patched rom crc32 mismatch".to_string());
+    }
+    Ok(())
+}
+
+fn check_target_size(size: usize) -> Result<(), String> {
+    if size > TARGET_SIZE_MAX {
+        return Err(format!("patched rom would be {} bytes, the limit is {}", size, TARGET_SIZE_MAX));
+    }
+    Ok(())
+}
+
+fn push_footer(out: &mut Vec<u8>, source: &[u8], target: &[u8]) {
+    out.extend_from_slice(&crc32(source).to_le_bytes());
+    out.extend_from_slice(&crc32(target).to_le_bytes());
+    let c: u32 = crc32(out);
+    out.extend_from_slice(&c.to_le_bytes());
+}
+
+pub fn apply_ips(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out: Vec<u8> = source.to_vec();
+    let mut p: usize = 5;
+    let byte = |p: usize| patch.get(p).copied().ok_or("IPS patch is truncated".to_string());
+    loop {
+        let offset: usize = (byte(p)? as usize) << 16 | (byte(p + 1)? as usize) << 8 | byte(p + 2)? as usize;
+        if offset == IPS_EOF {
+            p += 3;
+            break;
+        }
+        let size: usize = (byte(p + 3)? as usize) << 8 | byte(p + 4)? as usize;
+        p += 5;
+        let data: Vec<u8> = if size == 0 {
+            let count: usize = (byte(p)? as usize) << 8 | byte(p + 1)? as usize;
+            let value = byte(p + 2)?;
+            p += 3;
+            vec![value; count]
+        } else {
+            let data = patch.get(p..p + size).ok_or("IPS patch is truncated")?.to_vec();
+            p += size;
+            data
+        };
+        if out.len() < offset + data.len() {
+            out.resize(offset + data.len(), 0);
+        }
+        out[offset..offset + data.len()].copy_from_slice(&data);
+    }
+    if p + 3 <= patch.len() {
+        let size: usize = (patch[p] as usize) << 16 | (patch[p + 1] as usize) << 8 | patch[p + 2] as usize;
+        out.truncate(size);
+    }
+    Ok(out)
+}
+
+pub fn apply_ups(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.len() < 16 {
+        return Err("UPS patch is truncated".to_string());
+    }
+    check_patch(source, patch)?;
+    let mut p: usize = 4;
+    let source_size: usize = read_number(patch, &mut p)?;
+    let target_size: usize = read_number(patch, &mut p)?;
+    if source.len() != source_size {
+        return Err(format!("rom is {} bytes, the patch expects {}", source.len(), source_size));
+    }
+    check_target_size(target_size)?;
+    let mut out: Vec<u8> = source.to_vec();
+    out.resize(target_size, 0);
+    let end: usize = patch.len() - 12;
+    let mut ptr: usize = 0;
+    while p < end {
+        ptr = ptr.checked_add(read_number(patch, &mut p)?)
+            .filter(|ptr| *ptr <= source_size.max(target_size))
+            .ok_or("UPS hunk out of range")?;
+        while p < end {
+            let x: u8 = patch[p];
+            p += 1;
+            if x == 0 {
+                break;
+            }
+            if ptr < out.len() {
+                out[ptr] ^= x;
+            }
+            ptr += 1;
+        }
+        ptr += 1;
+    }
+    check_target(&out, patch)?;
+    Ok(out)
+}
+
+pub fn apply_bps(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.len() < 16 {
+        return Err("BPS patch is truncated".to_string());
+    }
+    check_patch(source, patch)?;
+    let mut p: usize = 4;
+    let source_size: usize = read_number(patch, &mut p)?;
+    let target_size: usize = read_number(patch, &mut p)?;
+    let metadata_size: usize = read_number(patch, &mut p)?;
+    p = p.checked_add(metadata_size).ok_or("BPS metadata is too large")?;
+    if source.len() != source_size {
+        return Err(format!("rom is {} bytes, the patch expects {}", source.len(), source_size));
+    }
+    check_target_size(target_size)?;
+    let mut out: Vec<u8> = Vec::with_capacity(target_size);
+    let (mut source_offset, mut target_offset): (isize, isize) = (0, 0);
+    let end: usize = patch.len() - 12;
+    let invalid = || "BPS action out of range".to_string();
+    while p < end {
+        let data: usize = read_number(patch, &mut p)?;
+        let length: usize = (data >> 2) + 1;
+        if length > target_size - out.len() {
+            return Err(invalid());
+        }
+        match data & 0x03 {
+            0 => {
+                let pos: usize = out.len();
+                out.extend_from_slice(source.get(pos..pos + length).ok_or_else(invalid)?);
+            },
+            1 => {
+                let next: usize = p.checked_add(length).ok_or_else(invalid)?;
+                out.extend_from_slice(patch.get(p..next).ok_or_else(invalid)?);
+                p = next;
+            },
+            command => {
+                let d: usize = read_number(patch, &mut p)?;
+                let delta: isize = if d & 1 > 0 { -((d >> 1) as isize) } else { (d >> 1) as isize };
+                if command == 2 {
+                    source_offset = source_offset.checked_add(delta).ok_or_else(invalid)?;
+                    let s: usize = source_offset as usize;
+                    if source_offset < 0 || length > source.len().saturating_sub(s) {
+                        return Err(invalid());
+                    }
+                    out.extend_from_slice(&source[s..s + length]);
+                    source_offset += length as isize;
+                } else {
+                    target_offset = target_offset.checked_add(delta).ok_or_else(invalid)?;
+                    if target_offset < 0 || target_offset as usize >= out.len() {
+                        return Err(invalid());
+                    }
+                    // may overlap the bytes being written
+                    for _ in 0..length {
+                        let b: u8 = out[target_offset as usize];
+                        out.push(b);
+                        target_offset += 1;
+                    }
+                }
+            },
+        }
+    }
+    if out.len() != target_size {
+        return Err(format!("patched rom is {} bytes, the patch declares {}", out.len(), target_size));
+    }
+    check_target(&out, patch)?;
+    Ok(out)
+}
+
+// format detected by the magic
+pub fn apply(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.starts_with(b"PATCH") {
+        apply_ips(source, patch)
+    } else if patch.starts_with(b"UPS1") {
+        apply_ups(source, patch)
+    } else if patch.starts_with(b"BPS1") {
+        apply_bps(source, patch)
+    } else {
+        Err("unknown patch format".to_string())
+    }
+}
+
+pub fn create_ips(source: &[u8], target: &[u8]) -> Result<Vec<u8>, String> {
+    if target.len() > 0xFFFFFF {
+        return Err("IPS is limited to 16MiB".to_string());
+    }
+    let mut out: Vec<u8> = b"PATCH".to_vec();
+    let mut i: usize = 0;
+    while i < target.len() {
+        if i < source.len() && source[i] == target[i] {
+            i += 1;
+            continue;
+        }
+        // the offset "EOF" would end the records
+        let start: usize = if i == IPS_EOF { i - 1 } else { i };
+        let mut end: usize = i;
+        while end < target.len() && end - start < IPS_RECORD_MAX &&
+                !(end < source.len() && source[end] == target[end]) {
+            end += 1;
+        }
+        out.extend_from_slice(&(start as u32).to_be_bytes()[1..]);
+        out.extend_from_slice(&((end - start) as u16).to_be_bytes());
+        out.extend_from_slice(&target[start..end]);
+        i = end;
+    }
+    out.extend_from_slice(b"EOF");
+    if target.len() < source.len() {
+        out.extend_from_slice(&(target.len() as u32).to_be_bytes()[1..]);
+    }
+    Ok(out)
+}
+
+pub fn create_ups(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = b"UPS1".to_vec();
+    write_number(&mut out, source.len());
+    write_number(&mut out, target.len());
+    let at = |data: &[u8], i: usize| data.get(i).copied().unwrap_or(0);
+    let size: usize = source.len().max(target.len());
+    let (mut i, mut ptr): (usize, usize) = (0, 0);
+    while i < size {
+        if at(source, i) == at(target, i) {
+            i += 1;
+            continue;
+        }
+        write_number(&mut out, i - ptr);
+        while i < size && at(source, i) != at(target, i) {
+            out.push(at(source, i) ^ at(target, i));
+            i += 1;
+        }
+        out.push(0);
+        i += 1;
+        ptr = i;
+    }
+    push_footer(&mut out, source, target);
+    out
+}
+
+pub fn create_bps(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = b"BPS1".to_vec();
+    write_number(&mut out, source.len());
+    write_number(&mut out, target.len());
+    write_number(&mut out, 0);
+    let is_same = |i: usize| i < source.len() && source[i] == target[i];
+    let mut i: usize = 0;
+    while i < target.len() {
+        let same: bool = is_same(i);
+        let mut end: usize = i + 1;
+        while end < target.len() && is_same(end) == same {
+            end += 1;
+        }
+        write_number(&mut out, (end - i - 1) << 2 | if same { 0 } else { 1 });
+        if !same {
+            out.extend_from_slice(&target[i..end]);
+        }
+        i = end;
+    }
+    push_footer(&mut out, source, target);
+    out
+}
+
+// format by the extension of path
+pub fn create(source: &[u8], target: &[u8], path: &str) -> Result<Vec<u8>, String> {
+    let ext = Path::new(path).extension().map_or(String::new(), |e| e.to_string_lossy().to_lowercase());
+    match ext.as_str() {
+        "ips" => create_ips(source, target),
+        "ups" => Ok(create_ups(source, target)),
+        "bps" => Ok(create_bps(source, target)),
+        _ => Err(format!("{}: patch extension must be .ips, .ups or .bps", path)),
+    }
+}
+
+// <rom>.ips, <rom>.ups, <rom>.bps which exist next to the rom
+pub fn find_patches(rom: &str) -> Vec<String> {
+    PATCH_EXTENSIONS.iter()
+        .map(|ext| Path::new(rom).with_extension(ext).to_string_lossy().to_string())
+        .filter(|path| Path::new(path).is_file())
+        .collect()
+}
+
+pub fn apply_files(rom: &[u8], patches: &[String]) -> Result<Vec<u8>, String> {
+    let mut data: Vec<u8> = rom.to_vec();
+    for path in patches.iter() {
+        let patch: Vec<u8> = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        data = apply(&data, &patch).map_err(|e| format!("{}: {}", path, e))?;
+        println!("patch: applied {}", path);
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_and_apply() {
+        let source: Vec<u8> = (0..0x3000).map(|i| (i * 7 % 251) as u8).collect();
+        let mut target: Vec<u8> = source.clone();
+        target[0x10..0x20].iter_mut().for_each(|b| *b = 0xEA);
+        target[0x2FFF] ^= 0xFF;
+        target.extend_from_slice(b"appended");
+        for path in ["a.ips", "a.ups", "a.bps"].iter() {
+            let patch = create(&source, &target, path).unwrap();
+            assert_eq!(apply(&source, &patch).unwrap(), target, "{}", path);
+        }
+        // shrinking
+        let short: Vec<u8> = source[..0x1000].to_vec();
+        assert_eq!(apply(&source, &create_ips(&source, &short).unwrap()).unwrap(), short);
+        // checksum of the source is checked
+        let mut other = source.clone();
+        other[0] ^= 1;
+        assert!(apply(&other, &create_bps(&source, &target)).is_err());
+    }
+
+    // magic, the numbers and a valid footer for the source
+    fn craft(magic: &[u8], numbers: &[usize], body: &[u8], source: &[u8]) -> Vec<u8> {
+        let mut out: Vec<u8> = magic.to_vec();
+        for n in numbers.iter() {
+            write_number(&mut out, *n);
+        }
+        out.extend_from_slice(body);
+        push_footer(&mut out, source, &[]);
+        out
+    }
+
+    #[test]
+    fn corrupt_patches() {
+        let source: Vec<u8> = vec![0x55; 0x100];
+        let target: Vec<u8> = vec![0xAA; 0x100];
+        // the patch crc32 is checked first
+        for mut patch in [create_ups(&source, &target), create_bps(&source, &target)].iter().cloned() {
+            patch[6] ^= 0x01;
+            assert_eq!(apply(&source, &patch), Err("patch crc32 mismatch, the patch is corrupted".to_string()));
+        }
+        // target sizes over the limit are not allocated
+        let huge: usize = TARGET_SIZE_MAX + 1;
+        assert!(apply(&source, &craft(b"UPS1", &[0x100, huge], &[], &source)).unwrap_err().contains("limit"));
+        assert!(apply(&source, &craft(b"BPS1", &[0x100, huge, 0], &[], &source)).unwrap_err().contains("limit"));
+        // numbers that overflow usize
+        let long: Vec<u8> = [vec![0x7F; 12], vec![0xFF]].concat();
+        let mut patch: Vec<u8> = b"UPS1".to_vec();
+        patch.extend_from_slice(&long);
+        patch.extend_from_slice(&long);
+        push_footer(&mut patch, &source, &[]);
+        assert_eq!(apply(&source, &patch), Err("patch number is too large".to_string()));
+        // TargetRead with a length past the end of the patch or the target
+        let patch: Vec<u8> = craft(b"BPS1", &[0x100, 0x100, 0, (usize::MAX >> 3) << 2 | 1], &[], &source);
+        assert_eq!(apply(&source, &patch), Err("BPS action out of range".to_string()));
+        let patch: Vec<u8> = craft(b"BPS1", &[0x100, 0x200, 0, 0xFF << 2 | 1], &[], &source);
+        assert_eq!(apply(&source, &patch), Err("BPS action out of range".to_string()));
+        // SourceCopy far outside the source
+        let patch: Vec<u8> = craft(b"BPS1", &[0x100, 0x100, 0, 0x0F << 2 | 2, usize::MAX - 1], &[], &source);
+        assert_eq!(apply(&source, &patch), Err("BPS action out of range".to_string()));
+        // UPS hunk offset past both sizes
+        let patch: Vec<u8> = craft(b"UPS1", &[0x100, 0x100, usize::MAX], &[0x01, 0x00], &source);
+        assert_eq!(apply(&source, &patch), Err("UPS hunk out of range".to_string()));
+    }
+}
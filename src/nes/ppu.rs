@@ -4,9 +4,11 @@ use core::panic;
 use crate::nes::VRAM_SIZE;
 
 use super::Cassette;
+use super::cassette::Mirroring;
 use super::Interrupts;
 use super::Ram;
-use super::interrupts;
+use super::region::Region;
+use super::vs_system::VsPpu;
 
 /*
     [Control Register1 0x2000]
@@ -26,16 +28,21 @@ use super::interrupts;
     [Control Register2 0x2001]
     | bit  | description                                 |
     +------+---------------------------------------------+
-    |  7-5 | Background color  0x00: Black               |
-    |      |                   0x01: Green               |
-    |      |                   0x02: Blue                |
-    |      |                   0x04: Red                 |
+    |  7   | Emphasize blue                              |
+    |  6   | Emphasize green                             |
+    |  5   | Emphasize red                               |
     |  4   | Enable sprite                               |
     |  3   | Enable background                           |
     |  2   | Sprite mask       render left end           |
     |  1   | Background mask   render left end           |
     |  0   | Display type      0: color, 1: mono         |
 
+    [Screen pixel]
+    | bit  | description                                 |
+    +------+---------------------------------------------+
+    |  8-6 | Emphasis bits 7-5 of Control Register2      |
+    |  5-0 | Color index, 0x30 mask in mono              |
+
     [PPU MEMORY MAP]
     | addr           |  description               |
     +----------------+----------------------------+
@@ -53,6 +60,24 @@ use super::interrupts;
     | 0x3F00-0x3F0F  |  background Palette        |
     | 0x3F10-0x3F1F  |  sprite Palette            |
     | 0x3F20-0x3FFF  |  mirror of 0x3F00-0x3F1F   |
+
+    [Internal registers (loopy)]
+    v, t: yyy NN YYYYY XXXXX
+          ||| || ||||| +++++-- coarse X scroll
+          ||| || +++++-------- coarse Y scroll
+          ||| ++-------------- name table select
+          +++----------------- fine Y scroll
+    x:    fine X scroll (3bit)
+    w:    first or second write toggle of 0x2005/0x2006
+
+    [Frame timing]
+    | line    | description                                 |
+    +---------+---------------------------------------------+
+    | 0-239   | visible, one pixel per dot at dot 1-256     |
+    | 240     | post-render, idle                           |
+    | 241-260 | vblank, set at line 241 dot 1               |
+    | 261     | pre-render, refills v from t at dot 280-304 |
+    NTSC lines, PAL and Dendy have 312 lines, see region.rs
 */
 
 pub const H_SIZE: usize = 256;
@@ -61,9 +86,13 @@ pub const PALETTE_SIZE: usize = 0x20;
 pub const H_SPRITE_NUM: usize = 32;
 pub const V_SPRITE_NUM: usize = 30;
 pub const SPRITE_RAM_SIZE: usize = 0x0100;
+const SPRITE_NUM: u16 = 64;
+const LINE_SPRITE_NUM: usize = 8;
+const CART_VRAM_SIZE: usize = 0x0800;
+const EX_RAM_SIZE: usize = 0x0400;
+// the I/O latch fades in about 600ms
+const IO_LATCH_DECAY_FRAMES: u8 = 36;
 // const VRAM_SIZE: usize = 0x0800;
-const TILE_SIZE: usize = 8;
-const V_SIZE_WITH_VBLANK: usize = 262;
 const CYCLE_PER_LINE: usize = 341;
 
 #[derive(Debug, Clone)]
@@ -87,28 +116,16 @@ impl Sprite {
 
 #[derive(Debug, Clone)]
 pub struct Tile {
-    pub scroll_x: u8,
-    pub scroll_y: u8,
-    pub scroll_xs: [u8; 8],
-    pub scroll_ys: [u8; 8],
     pub sprite_id: u16,
     pub palette_id: u16,
-    pub is_need_update: bool,
-    pub is_background_enable: bool,
     pub sprite: Sprite,
 }
 
 impl Tile {
     fn new() -> Tile {
         Tile {
-            scroll_x: 0,
-            scroll_y: 0,
-            scroll_xs: [0; 8],
-            scroll_ys: [0; 8],
             sprite_id: 0,
             palette_id: 0,
-            is_need_update: true,
-            is_background_enable: true,
             sprite: Sprite::new(),
         }
     }
@@ -116,20 +133,19 @@ impl Tile {
 
 #[derive(Debug)]
 pub struct Image {
-    pub sprite: Vec<Sprite>,
-    pub background: Vec<Vec<Tile>>,
+    // palette color index of every pixel, written one per dot
+    pub screen: Vec<Vec<u16>>,
     pub dbg_bg: Vec<Vec<Tile>>,
     pub dbg_pattern: Vec<Sprite>,
     pub palette: [u8; PALETTE_SIZE],
-    pub current_x: u8,
-    pub current_y: u8,
+    pub current_x: u16,
+    pub current_y: u16,
 }
 
 impl Image {
     pub fn new() -> Image {
         Image {
-            sprite: Vec::new(),
-            background: vec![vec![Tile::new(); H_SPRITE_NUM]; V_SPRITE_NUM],
+            screen: vec![vec![0; H_SIZE]; V_SIZE],
             dbg_bg: vec![vec![Tile::new(); H_SPRITE_NUM*2]; V_SPRITE_NUM*2],
             dbg_pattern: vec![Sprite::new(); 512],
             palette: [0; PALETTE_SIZE],
@@ -139,6 +155,29 @@ impl Image {
     }
 }
 
+// memory behind each 1KiB name table slot
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NameTable {
+    // 1KiB page of the 2KiB VRAM
+    Vram(u8),
+    // 1KiB page of the four screen VRAM on the cartridge
+    CartVram(u8),
+    // MMC5 extended RAM
+    ExRam,
+    // MMC5 fill mode, every tile is fill_tile
+    Fill,
+}
+
+// sprite fetched for the next line
+#[derive(Debug, Clone, Copy)]
+struct LineSprite {
+    is_sprite_0: bool,
+    x: u8,
+    attr: u8,
+    pattern_lo: u8,
+    pattern_hi: u8,
+}
+
 #[derive(Debug)]
 struct Palette {
     ram: Ram
@@ -163,9 +202,13 @@ impl Palette {
         }
         palette
     }
+    fn read_at(&self, addr: u16) -> u8 {
+        self.ram.read(self.get_palette_addr(addr))
+    }
     fn write(&mut self, addr: u16, data: u8) {
         let addr_: u16 = self.get_palette_addr(addr);
-        self.ram.write(addr_, data);
+        // palette entries are 6bit
+        self.ram.write(addr_, data & 0x3F);
     }
     fn is_background_mirror(&self, addr: u16) -> bool{
         match addr {
@@ -198,27 +241,50 @@ impl Palette {
 pub struct Ppu<'a> {
     pub cycle: u64,
     pub line: u16,
-    background_index: u8,
+    v: u16,
+    t: u16,
+    fine_x: u8,
+    w: bool,
     vram_buf: u8,
-    vram_addr: u16,
-    vram_offset: u16,
-    sprite_ram_addr: u16,
-    scroll_x: u8,
-    scroll_y: u8,
-    is_horizontal_mirror: bool,
-    is_horizontal_scroll: bool,
-    is_lower_vram_addr: bool,
+    sprite_ram_addr: u8,
+    name_tables: [NameTable; 4],
+    fill_tile: u8,
+    fill_attr: u8,
+    is_odd_frame: bool,
+    is_sprite_limit: bool,
+    region: Region,
+    // RGB PPU of Vs. System and PlayChoice-10
+    vs_ppu: Option<VsPpu>,
+    // fraction of a dot left over by the last run, PAL is 3.2 dots per cycle
+    dot_remainder: u64,
+    is_nmi_requested: bool,
+    io_latch: u8,
+    io_latch_decay: [u8; 8],
     creg1: u8,
     creg2: u8,
     sreg: u8,
     is_char_rom: bool,
-    sprite_0_hit_switch: bool,
-    already_sprite_0_hit: bool,
+    // background fetch latches and shift registers
+    name_table_latch: u8,
+    attribute_latch: u8,
+    pattern_lo_latch: u8,
+    pattern_hi_latch: u8,
+    pattern_lo_shift: u16,
+    pattern_hi_shift: u16,
+    attribute_lo_shift: u16,
+    attribute_hi_shift: u16,
+    line_sprites: Vec<LineSprite>,
     palette: Palette,
     sprite_ram: Ram,
     // char_rom: Ram,
     char_ram: Ram,
+    // offset in char_ram of each 1KiB of the pattern tables
+    char_banks: [usize; 8],
     vram: &'a mut Ram,
+    // four screen VRAM on the cartridge
+    cart_vram: Ram,
+    // MMC5 extended RAM
+    ex_ram: Ram,
 }
 
 impl<'a> Ppu<'a> {
@@ -229,49 +295,71 @@ impl<'a> Ppu<'a> {
             0x2000
         };
         let mut char_ram = Ram::new(size);
-        for i in 0..cas.char_size {
-            char_ram.write(i as u16, cas.char_rom[i]);
-        }
-        Ppu {
+        char_ram.data[..cas.char_size].copy_from_slice(&cas.char_rom);
+        let mut ppu = Ppu {
             cycle: 0,
             line: 0,
-            background_index: 0,
+            v: 0,
+            t: 0,
+            fine_x: 0,
+            w: false, // first write
             vram_buf: 0,
-            vram_addr: 0,
-            vram_offset: 0,
             sprite_ram_addr: 0,
-            scroll_x: 0,
-            scroll_y: 0,
-            is_horizontal_mirror: cas.is_horizontal_mirror,
-            is_horizontal_scroll: false, // vertical scroll is first
-            is_lower_vram_addr: false, // higher is first
+            name_tables: [NameTable::Vram(0); 4],
+            fill_tile: 0,
+            fill_attr: 0,
+            is_odd_frame: false,
+            is_sprite_limit: true,
+            region: Region::from_header(&cas.header),
+            vs_ppu: VsPpu::from_header(&cas.header),
+            dot_remainder: 0,
+            is_nmi_requested: false,
+            io_latch: 0,
+            io_latch_decay: [0; 8],
             creg1: 0,
             creg2: 0,
             sreg: 0,
             is_char_rom: cas.char_size > 0,
-            sprite_0_hit_switch: false,
-            already_sprite_0_hit: false,
+            name_table_latch: 0,
+            attribute_latch: 0,
+            pattern_lo_latch: 0,
+            pattern_hi_latch: 0,
+            pattern_lo_shift: 0,
+            pattern_hi_shift: 0,
+            attribute_lo_shift: 0,
+            attribute_hi_shift: 0,
+            line_sprites: Vec::new(),
             palette: Palette::new(PALETTE_SIZE),
             sprite_ram: Ram::new(SPRITE_RAM_SIZE),
             // char_rom: char_rom,
             char_ram: char_ram,
+            char_banks: [0, 0x400, 0x800, 0xC00, 0x1000, 0x1400, 0x1800, 0x1C00],
             vram: vram,
-        }
+            cart_vram: Ram::new(
+                if cas.mirroring == Mirroring::FourScreen {CART_VRAM_SIZE} else {0}),
+            ex_ram: Ram::new(EX_RAM_SIZE),
+        };
+        ppu.set_mirroring(cas.mirroring);
+        ppu
+    }
+    // more than 8 sprites on a line are drawn when disabled, removes flicker
+    pub fn set_sprite_limit(&mut self, is_sprite_limit: bool) {
+        self.is_sprite_limit = is_sprite_limit;
     }
-    // Control Register 1, Main Screen assignment by name table
-    fn get_name_table_id(&mut self) -> u8{
-        self.creg1 & 0x03
+    // lines, vblank and dots per CPU cycle, the header region by default
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
     }
     // Control Register 1, PPU memory increment
-    fn get_vram_offset(&mut self) -> u8{
+    fn get_vram_offset(&self) -> u8{
         if self.creg1 & 0x04 > 0 {32} else {1}
     }
     // Control Register 1, get sprite pattern table
-    fn get_sprite_table_offset(&mut self) -> u16 {
+    fn get_sprite_table_offset(&self) -> u16 {
         if self.creg1 & 0x08 > 0 {0x1000} else {0x0000}
     }
     // Control Register 1, get background pattern table
-    fn get_background_table_offset(&mut self) -> u16{
+    fn get_background_table_offset(&self) -> u16{
         if self.creg1 & 0x10 > 0 {0x1000} else {0x0000}
     }
     // Control Register 1, Sprite Size
@@ -282,34 +370,35 @@ impl<'a> Ppu<'a> {
     fn has_vblank_irq_enabled(&mut self) -> bool{
         self.creg1 & 0x80 > 0
     }
-    // Control Register 2, Enable sprite
-    fn get_is_background_enable(&mut self) -> bool {
+    // Control Register 2, Enable background
+    fn get_is_background_enable(&self) -> bool {
         self.creg2 & 0x08 > 0
     }
     // Control Register 2, Enable sprite
-    fn get_is_sprite_enable(&mut self) -> bool {
+    fn get_is_sprite_enable(&self) -> bool {
         self.creg2 & 0x10 > 0
     }
+    fn is_rendering_enabled(&self) -> bool {
+        self.get_is_background_enable() || self.get_is_sprite_enable()
+    }
+    fn is_rendering_line(&self) -> bool {
+        self.line < V_SIZE as u16 || self.line == self.region.pre_render_line()
+    }
     // PPU status register
     fn set_sprite_0_hit(&mut self) {
-        let sreg = self.sreg;
         self.sreg |= 0x40;
-        if sreg != self.sreg {
-            // println!("set_sprite_0_hit   {:08b} {:08b} {:08b} x:{:3} y:{:3} line:{:3} cyc:{:3}",
-            //     self.sreg, self.creg1, self.creg2,
-            //     self.scroll_x, self.scroll_y, self.line, self.cycle);
-        }
     }
     // PPU status register
-    fn clear_sprite_0_hit(&mut self, line: usize) {
-        let sreg = self.sreg;
+    fn clear_sprite_0_hit(&mut self) {
         self.sreg &= 0xBF;
-        self.already_sprite_0_hit = false;
-        if sreg != self.sreg {
-            // println!("clear_sprite_0_hit {:08b} {:08b} {:08b} x:{:3} y:{:3} line:{:3} cyc:{:3}",
-            //     self.sreg, self.creg1, self.creg2,
-            //     self.scroll_x, self.scroll_y, line, self.cycle);
-        }
+    }
+    // PPU status register
+    fn set_sprite_overflow(&mut self) {
+        self.sreg |= 0x20;
+    }
+    // PPU status register
+    fn clear_sprite_overflow(&mut self) {
+        self.sreg &= 0xDF;
     }
     // PPU status register
     fn set_vblank(&mut self) {
@@ -323,118 +412,175 @@ impl<'a> Ppu<'a> {
     fn clear_vblank(&mut self) {
         self.sreg &= 0x7F;
     }
-    fn is_sprite_0_hit(&mut self) -> bool {
-        if self.already_sprite_0_hit {
-            return false;
+    fn get_block_id(&mut self, x: u16, y: u16) -> u8{
+        ((x % 4) / 2 + ((y % 4) / 2) * 2) as u8
+    }
+    // name table, attr table [0x2000:0x2FFF], [0x3000:0x3EFF] is copy of it
+    fn read_name_table(&self, addr: u16) -> u8 {
+        let offset: u16 = addr & 0x03FF;
+        match self.name_tables[((addr >> 10) & 0x03) as usize] {
+            NameTable::Vram(page) => self.vram.read(page as u16 * 0x0400 + offset),
+            NameTable::CartVram(page) => self.cart_vram.read(page as u16 * 0x0400 + offset),
+            NameTable::ExRam => self.ex_ram.read(offset),
+            NameTable::Fill => if offset < 0x03C0 {
+                self.fill_tile
+            } else {
+                (self.fill_attr & 0x03) * 0x55
+            },
         }
-        let y: u8 = self.sprite_ram.read(0);
-        let sprite_id: u16 = self.sprite_ram.read(1) as u16;
-        let x = self.sprite_ram.read(3);
-        let mut is_hit = false;
-        if (y as u16) <= self.line && self.line < (y as u16 + 8) {
-            let mut sprite = Sprite::new();
-            self.build_sprite_data(
-                false,
-                sprite_id,
-                0,
-                &mut sprite,
-            );
-            let base_y = self.line as u8 - y;
-            let mut is_not_transparent_line = false;
-            for i in 0..8 {
-                if sprite.data[base_y as usize][i as usize] > 0 {
-                    is_not_transparent_line = true;
-                    break
-                }
-            }
-
-            if !is_not_transparent_line {
-                return false;
-            }
-
-            is_hit = (x as u64) <= self.cycle &&
-                is_not_transparent_line;
-                self.get_is_sprite_enable();
-            
-            self.already_sprite_0_hit = is_hit;
-            self.sprite_0_hit_switch = is_hit;
+    }
+    fn write_name_table(&mut self, addr: u16, data: u8) {
+        let offset: u16 = addr & 0x03FF;
+        match self.name_tables[((addr >> 10) & 0x03) as usize] {
+            NameTable::Vram(page) => self.vram.write(page as u16 * 0x0400 + offset, data),
+            NameTable::CartVram(page) => self.cart_vram.write(page as u16 * 0x0400 + offset, data),
+            NameTable::ExRam => self.ex_ram.write(offset, data),
+            NameTable::Fill => (),
         }
-        is_hit
     }
-    fn get_scroll_tile_x(&mut self) -> u8 {
-        ((self.scroll_x as u16 +
-            (self.get_name_table_id() % 2) as u16 * H_SIZE as u16) / 8) as u8
+    // 1KiB char bank for 0x0000-0x03FF .. 0x1C00-0x1FFF, selected by mapper
+    pub fn set_char_bank(&mut self, slot: usize, bank: usize) {
+        self.char_banks[slot] = (bank * 0x400) % self.char_ram.data.len();
     }
-    fn get_scroll_tile_y(&mut self) -> u8 { 
-        ((self.scroll_y as u16 + self.line +
-            (self.get_name_table_id() / 2) as u16 * V_SIZE as u16) / 8 - 1) as u8 
+    fn read_char(&self, addr: u16) -> u8 {
+        let offset: usize = self.char_banks[(addr as usize >> 10) & 0x07] + (addr as usize & 0x03FF);
+        self.char_ram.data[offset % self.char_ram.data.len()]
     }
-    fn get_block_id(&mut self, x: u16, y: u16) -> u8{
-        ((x % 4) / 2 + ((y % 4) / 2) * 2) as u8
+    // mirroring changed by mapper
+    pub fn set_mirroring(&mut self, mirroring: Mirroring) {
+        self.name_tables = match mirroring {
+            Mirroring::Horizontal => [
+                NameTable::Vram(0), NameTable::Vram(0),
+                NameTable::Vram(1), NameTable::Vram(1)],
+            Mirroring::Vertical => [
+                NameTable::Vram(0), NameTable::Vram(1),
+                NameTable::Vram(0), NameTable::Vram(1)],
+            Mirroring::SingleScreenA => [NameTable::Vram(0); 4],
+            Mirroring::SingleScreenB => [NameTable::Vram(1); 4],
+            Mirroring::FourScreen => [
+                NameTable::Vram(0), NameTable::Vram(1),
+                NameTable::CartVram(0), NameTable::CartVram(1)],
+        };
     }
-    fn get_vram_addr(&mut self, sprite_addr: u16) -> u16 {
-        if self.is_horizontal_mirror {
-            match sprite_addr {
-                0x0000..=0x03FF => sprite_addr,
-                0x0400..=0x07FF => sprite_addr - 0x0400,
-                0x0800..=0x0BFF => sprite_addr,
-                0x0C00..=0x0FFF => sprite_addr - 0x0400,
-                _ => panic!("invalid sprite_addr {}", sprite_addr),
-            }
-        } else {
-            match sprite_addr {
-                0x0000..=0x03FF => sprite_addr,
-                0x0400..=0x07FF => sprite_addr,
-                0x0800..=0x0BFF => sprite_addr - 0x0800,
-                0x0C00..=0x0FFF => sprite_addr - 0x0800,
-                _ => panic!("invalid sprite_addr {}", sprite_addr),
-            }
-        }
+    // mapper provided name table for one of 0x2000/0x2400/0x2800/0x2C00
+    pub fn set_name_table(&mut self, index: usize, name_table: NameTable) {
+        self.name_tables[index] = name_table;
+    }
+    // tile and palette of NameTable::Fill
+    pub fn set_fill_mode(&mut self, tile: u8, attr: u8) {
+        self.fill_tile = tile;
+        self.fill_attr = attr;
+    }
+    pub fn read_ex_ram(&self, addr: u16) -> u8 {
+        self.ex_ram.read(addr & 0x03FF)
+    }
+    pub fn write_ex_ram(&mut self, addr: u16, data: u8) {
+        self.ex_ram.write(addr & 0x03FF, data);
     }
     // read from name_table
     fn get_sprite_id(&mut self, x: u16, y: u16, offset: u16) -> u8{
         let tile_num: u16 =  x as u16 + y as u16 * 32;
-        let sprite_addr: u16 =
-            self.get_vram_addr(tile_num + offset);
-        self.vram.read(sprite_addr)
+        self.read_name_table(tile_num + offset)
     }
     fn get_attribute(&mut self, x: u16, y: u16, offset: u16) -> u8{
         let addr: u16 = x as u16 / 4 +
             (y as u16/ 4) * 8 +
             0x03C0 + offset;
-        let sprite_addr: u16 = self.get_vram_addr(addr);
-            // if x == 0 && y == 0 {
-            //     println!("{} {} {:b}", offset, sprite_addr, self.vram.read(sprite_addr));
-        // }
-        self.vram.read(sprite_addr)
+        self.read_name_table(addr)
     }
     fn get_palette(&mut self, image: &mut Image) {
         image.palette = self.palette.read();
     }
+    // coarse X increment, wraps into the next horizontal name table
+    fn increment_x(&mut self) {
+        if self.v & 0x001F == 31 {
+            self.v &= !0x001F;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+    // fine Y increment, wraps into the next vertical name table at row 29
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let mut y: u16 = (self.v & 0x03E0) >> 5;
+        if y == 29 {
+            y = 0;
+            self.v ^= 0x0800;
+        } else if y == 31 {
+            y = 0;
+        } else {
+            y += 1;
+        }
+        self.v = (self.v & !0x03E0) | (y << 5);
+    }
+    fn copy_x(&mut self) {
+        self.v = (self.v & !0x041F) | (self.t & 0x041F);
+    }
+    fn copy_y(&mut self) {
+        self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+    }
+    // 0x2007 access moves v, during rendering it glitches both coarse X and Y
+    fn increment_vram_addr(&mut self) {
+        if self.is_rendering_enabled() && self.is_rendering_line() {
+            self.increment_x();
+            self.increment_y();
+        } else {
+            self.v = (self.v + self.get_vram_offset() as u16) & 0x7FFF;
+        }
+    }
     // read by cpu
     fn vram_read(&mut self) -> u8{
         let mut vram_buf: u8 = self.vram_buf;
-        self.vram_addr %= 0x4000;
-        match self.vram_addr {
+        let addr: u16 = self.v & 0x3FFF;
+        match addr {
             // pattern table from charactor rom
             0x0000..=0x1FFF => {
-                self.vram_buf = self.char_ram.read(self.vram_addr);
+                self.vram_buf = self.read_char(addr);
+                self.refresh_io_latch(vram_buf, 0xFF);
             },
             // name table, attr table
             0x2000..=0x3EFF => {
-                self.vram_buf = self.vram.read(self.vram_addr % 0x1000);
+                self.vram_buf = self.read_name_table(addr);
+                self.refresh_io_latch(vram_buf, 0xFF);
             },
-            // pallette
-            0x3F00..=0x4000 => {
-                // vram_buf = self.vram.read(self.vram_addr - 0x3F00);
-                let addr = (self.vram_addr - 0x3F00) as usize;
-                vram_buf = self.palette.read()[addr];
+            // pallette, returned directly with open bus upper 2bit
+            // the name table byte underneath goes to the buffer
+            0x3F00..=0x3FFF => {
+                vram_buf = self.palette.read_at(addr - 0x3F00) |
+                    (self.io_latch & 0xC0);
+                self.vram_buf = self.read_name_table(addr - 0x1000);
+                self.refresh_io_latch(vram_buf, 0x3F);
             },
-            _ => panic!("invalid addr: {}", self.vram_addr),
+            _ => panic!("invalid addr: {}", addr),
         }
-        self.vram_addr += self.get_vram_offset() as u16;
+        self.increment_vram_addr();
         vram_buf as u8
     }
+    // bits driven by the last access, each one decays to 0 when not refreshed
+    fn refresh_io_latch(&mut self, data: u8, mask: u8) {
+        self.io_latch = (self.io_latch & !mask) | (data & mask);
+        for i in 0..8 {
+            if mask & (1 << i) > 0 {
+                self.io_latch_decay[i] = IO_LATCH_DECAY_FRAMES;
+            }
+        }
+    }
+    fn decay_io_latch(&mut self) {
+        for i in 0..8 {
+            if self.io_latch_decay[i] == 0 {
+                continue;
+            }
+            self.io_latch_decay[i] -= 1;
+            if self.io_latch_decay[i] == 0 {
+                self.io_latch &= !(1 << i);
+            }
+        }
+    }
     pub fn read(&mut self, addr: u16) -> u8 {
         // println!(" ppu read {:#X}", addr);
         match addr {
@@ -448,110 +594,127 @@ impl<'a> Ppu<'a> {
             |      | bit4 VRAM write flag [0: success, 1: fail]  |
             */
             0x0002 => {
-                // PPUSTATUS
-                let status: u8 = self.sreg;
+                // PPUSTATUS, lower 5bit is open bus
+                let status: u8 = match self.vs_ppu.and_then(|p| p.status_id()) {
+                    // RC2C05 returns its id instead
+                    Some(id) => (self.sreg & 0xC0) | id,
+                    None => (self.sreg & 0xE0) | (self.io_latch & 0x1F),
+                };
                 self.clear_vblank();
-                let line = self.line;
-                // self.clear_sprite_0_hit(line as usize);
-                self.is_lower_vram_addr = false;
-                self.is_horizontal_scroll = true;
-                return status;
+                self.w = false;
+                self.refresh_io_latch(status, 0xE0);
+                status
             },
             0x0004 => {
-                // OAMADDR
-                return self.sprite_ram.read(self.sprite_ram_addr);
+                // OAMDATA
+                let data: u8 = self.read_sprite_ram_data();
+                self.refresh_io_latch(data, 0xFF);
+                data
             },
             0x0007 => {
                 // PPUDATA
-                return self.vram_read();
+                self.vram_read()
             },
-            _ => 0,
-            // _ => panic!("invalid addr {:#X}", addr)
+            // write only registers
+            _ => self.io_latch,
         }
     }
+    fn read_sprite_ram_data(&self) -> u8 {
+        // secondary OAM is being cleared to 0xFF at dot 1-64
+        if self.is_rendering_enabled() && self.line < V_SIZE as u16 &&
+                (1..=64).contains(&self.cycle) {
+            return 0xFF;
+        }
+        self.sprite_ram.read(self.sprite_ram_addr as u16)
+    }
     pub fn write_sprite_ram_addr(&mut self, data: u8) {
-        self.sprite_ram_addr = data as u16;
+        self.sprite_ram_addr = data;
     }
     pub fn write_sprite_ram_data(&mut self, data: u8) {
-        self.sprite_ram.write(self.sprite_ram_addr, data);
-        self.sprite_ram_addr += 1;
+        // no write during rendering, only the upper 6bit of OAMADDR increments
+        if self.is_rendering_enabled() && self.is_rendering_line() {
+            self.sprite_ram_addr = self.sprite_ram_addr.wrapping_add(4);
+            return;
+        }
+        // bit 2-4 of attribute are not implemented
+        let data: u8 = if self.sprite_ram_addr & 0x03 == 0x02 {data & 0xE3} else {data};
+        self.sprite_ram.write(self.sprite_ram_addr as u16, data);
+        self.sprite_ram_addr = self.sprite_ram_addr.wrapping_add(1);
+    }
+    fn write_control(&mut self, data: u8) {
+        // enabling NMI during vblank asserts it immediately
+        if self.creg1 & 0x80 == 0 && data & 0x80 > 0 && self.sreg & 0x80 > 0 {
+            self.is_nmi_requested = true;
+        }
+        self.creg1 = data;
+        // name table select goes to t
+        self.t = (self.t & 0xF3FF) | ((data as u16 & 0x03) << 10);
     }
     fn write_scroll_data(&mut self, data: u8) {
-        let (x, y) = (self.scroll_x, self.scroll_y);
-        if self.is_horizontal_scroll {
-            self.is_horizontal_scroll = false;
-            self.scroll_x = data;
+        if !self.w {
+            // coarse X, fine X
+            self.t = (self.t & 0xFFE0) | (data as u16 >> 3);
+            self.fine_x = data & 0x07;
         } else {
-            self.scroll_y = data;
-            self.is_horizontal_scroll = true;
-        }
-        if x != self.scroll_x || y != self.scroll_y {
-            let is_sprite_0_hit = self.already_sprite_0_hit;
-            // println!("write scroll from game x:{}, y:{}, already_0hit:{} scroll_val:{} line:{}",
-            //     self.scroll_x,
-            //     self.scroll_y,
-            //     is_sprite_0_hit,
-            //     data,
-            //     self.line);
+            // coarse Y, fine Y
+            self.t = (self.t & 0x8C1F) |
+                ((data as u16 & 0x07) << 12) |
+                ((data as u16 & 0xF8) << 2);
         }
+        self.w = !self.w;
     }
     // write by cpu
     fn write_vram_addr(&mut self, data: u8) {
-        // println!("{:X} {:X} {}", self.vram_addr, data, self.is_lower_vram_addr);
-        if self.is_lower_vram_addr {
-            self.vram_addr += data as u16;
-            self.is_lower_vram_addr = false;
+        if !self.w {
+            // higher 6bit, bit14 is cleared
+            self.t = (self.t & 0x80FF) | ((data as u16 & 0x3F) << 8);
         } else {
-            self.vram_addr = (data as u16) << 8;
-            self.is_lower_vram_addr = true;
+            self.t = (self.t & 0xFF00) | data as u16;
+            self.v = self.t;
         }
+        self.w = !self.w;
     }
     // write by cpu
     fn write_vram_data(&mut self, data: u8) {
-        // println!("write_vram_data {:#06X} {:#04X}", self.vram_addr, data);
-        self.vram_addr %= 0x4000;
-        match self.vram_addr {
+        // println!("write_vram_data {:#06X} {:#04X}", self.v, data);
+        let addr: u16 = self.v & 0x3FFF;
+        match addr {
             // pattern table from charactor rom
             0x0000..=0x1FFF => {
-                // println!("write_vram_data {:#06X} {:#04X}", self.vram_addr, data);
-                if self.is_char_rom {
-                    return;
-                } else {
-                    // println!("char ram write addr:{} data:{}", self.vram_addr, data);
-                    self.char_ram.write(self.vram_addr, data);
+                if !self.is_char_rom {
+                    let offset: usize = self.char_banks[addr as usize >> 10] + (addr as usize & 0x03FF);
+                    self.char_ram.data[offset] = data;
                 }
             },
             // name table, attr table [0x2000:0x2FFF]
             // name table, attr table [0x3000:0x3EFF] => copy of [0x2000:0x2EFF] 
             0x2000..=0x3EFF => {
-                let addr: u16 = self.vram_addr % 0x1000;
-                // println!("write_vram_data {:#06X} {:#06X} {:#04X}",
-                    // self.vram_addr, addr, data);
-                self.vram.write(addr, data);
+                self.write_name_table(addr, data);
             },
-            0x3F00..=0x4000 => {
+            0x3F00..=0x3FFF => {
                 // pallette
-                let addr: u16 = (self.vram_addr - 0x3F00);
-                self.palette.write(addr, data);
+                self.palette.write(addr - 0x3F00, data);
             },
-            _ => panic!("invalid addr: {}", self.vram_addr),
+            _ => panic!("invalid addr: {}", addr),
         }
-        self.vram_addr += self.get_vram_offset() as u16;
+        self.increment_vram_addr();
     }
     pub fn write(&mut self, addr: u16, data: u8) {
         // println!(" ppu write {:#X} {:#X}:{:08b}", addr, data, data);
+        self.refresh_io_latch(data, 0xFF);
+        let is_swapped: bool = self.vs_ppu.is_some_and(|p| p.is_control_swapped());
+        let addr: u16 = if is_swapped && addr < 2 { addr ^ 1 } else { addr };
         match addr {
-            0x0000 => self.creg1 = data,
+            0x0000 => self.write_control(data),
             0x0001 => self.creg2 = data,
+            // PPUSTATUS is read only
+            0x0002 => (),
             // set sprite ram write addr
             0x0003 => self.write_sprite_ram_addr(data),
             // sprite ram write
             0x0004 => self.write_sprite_ram_data(data),
             // set scroll setting
-            0x0005 => {
-                // println!("{} {} {}", addr, data, self.line);
-                self.write_scroll_data(data);
-            },
+            0x0005 => self.write_scroll_data(data),
             // set vram write addr (first: high 8bit, second: low 8bit)
             0x0006 => self.write_vram_addr(data),
             // sprite ram write
@@ -589,19 +752,14 @@ impl<'a> Ppu<'a> {
             }
         }
         let h = if self.is_large_sprite() && !is_tile {2} else {1};
-        let mut disp = false;
         for k in 0..h {
             for i in 0..16 {
                 let addr: u16 = ((sprite_id + k) * 16 + i + offset) as u16;
-                if addr as usize >= self.char_ram.data.len() {
+                if addr >= 0x2000 {
                     continue
                 }
                 // read from pattern table
-                let ram: u8 = self.char_ram.read(addr);
-                if is_tile && sprite_id == 4 {
-                    // println!("{:X} {:X} {:X}", ram, addr, offset);
-                    disp = true;
-                }
+                let ram: u8 = self.read_char(addr);
                 for j in 0..8 {
                     if ram & (0x80 >> j) > 0 {
                         sprite.data[(k * 8 + (i % 8)) as usize][j] +=
@@ -610,116 +768,200 @@ impl<'a> Ppu<'a> {
                 }
             }
         }
-        if disp {
-            // println!("{:X} {:X}", sprite_id, offset);
-            // for a in &sprite.data{
-            //     for b in a.iter(){
-            //         let val = if *b > 0 {"#"} else {" "};
-            //         print!("{}", val);
-            //     }
-            //     print!(";\n");
-            // }
-            // print!(";\n");
-            // panic!();
-        }
-            // sprite
-    }
-    fn build_sprites(&mut self, image: &mut Image) {
-        // see https:#wiki.nesdev.com/w/index.php/PPU_OAM
-        for i in 0..self.sprite_ram_addr/4 {
-            let j: u16 = 4 * i as u16;
-
-            let y: u8 = self.sprite_ram.read(j);
-            let sprite_id: u16 = self.sprite_ram.read(j + 1) as u16;
-            let attr = self.sprite_ram.read(j + 2);
-            let x = self.sprite_ram.read(j + 3);
-
-            let mut sprite: Sprite = Sprite::new();
-            sprite.y = y;
-            sprite.attr = attr;
-            sprite.x = x;
-            // println!("{}", sprite_id);
-            let (sprite_id, offset) = if self.is_large_sprite() {
-                let offset: u16 = 0x1000 * (sprite_id & 0x01);
-                sprite.data = (0..16).into_iter().map(|_| vec![0; 16]).collect();
-                (sprite_id & 0xFE, offset)
+    }
+    fn load_background_shift(&mut self) {
+        self.pattern_lo_shift =
+            (self.pattern_lo_shift & 0xFF00) | self.pattern_lo_latch as u16;
+        self.pattern_hi_shift =
+            (self.pattern_hi_shift & 0xFF00) | self.pattern_hi_latch as u16;
+        let attr_lo: u16 = if self.attribute_latch & 0x01 > 0 {0xFF} else {0x00};
+        let attr_hi: u16 = if self.attribute_latch & 0x02 > 0 {0xFF} else {0x00};
+        self.attribute_lo_shift = (self.attribute_lo_shift & 0xFF00) | attr_lo;
+        self.attribute_hi_shift = (self.attribute_hi_shift & 0xFF00) | attr_hi;
+    }
+    fn shift_background(&mut self) {
+        self.pattern_lo_shift <<= 1;
+        self.pattern_hi_shift <<= 1;
+        self.attribute_lo_shift <<= 1;
+        self.attribute_hi_shift <<= 1;
+    }
+    fn fetch_name_table(&mut self) {
+        self.name_table_latch = self.read_name_table(0x2000 | (self.v & 0x0FFF));
+    }
+    fn fetch_attribute(&mut self) {
+        let v: u16 = self.v;
+        let addr: u16 = 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+        let shift: u16 = ((v >> 4) & 0x04) | (v & 0x02);
+        self.attribute_latch = (self.read_name_table(addr) >> shift) & 0x03;
+    }
+    fn fetch_pattern(&mut self, plane: u16) -> u8 {
+        let fine_y: u16 = (self.v >> 12) & 0x07;
+        let addr: u16 = self.get_background_table_offset() +
+            self.name_table_latch as u16 * 16 + fine_y + plane;
+        self.read_char(addr)
+    }
+    // background pipeline, see https://www.nesdev.org/wiki/PPU_rendering
+    fn fetch_background(&mut self) {
+        let dot: u64 = self.cycle;
+        if (2..=257).contains(&dot) || (321..=337).contains(&dot) {
+            self.shift_background();
+            match (dot - 1) % 8 {
+                0 => {
+                    self.load_background_shift();
+                    self.fetch_name_table();
+                },
+                2 => self.fetch_attribute(),
+                4 => self.pattern_lo_latch = self.fetch_pattern(0),
+                6 => self.pattern_hi_latch = self.fetch_pattern(8),
+                7 => self.increment_x(),
+                _ => (),
+            }
+        }
+        if dot == 256 {
+            self.increment_y();
+        }
+        if dot == 257 {
+            self.load_background_shift();
+            self.copy_x();
+        }
+        if self.line == self.region.pre_render_line() && (280..=304).contains(&dot) {
+            self.copy_y();
+        }
+    }
+    // OAMADDR >= 8 when rendering starts copies its row over the first 8 bytes
+    fn corrupt_sprite_ram(&mut self) {
+        if self.sprite_ram_addr < 8 {
+            return;
+        }
+        let base: u16 = (self.sprite_ram_addr & 0xF8) as u16;
+        for i in 0..8 {
+            let data: u8 = self.sprite_ram.read(base + i);
+            self.sprite_ram.write(i, data);
+        }
+    }
+    fn is_sprite_in_line(&self, y: u8) -> bool {
+        let h: u16 = if self.is_large_sprite() {16} else {8};
+        self.line >= y as u16 && self.line < y as u16 + h
+    }
+    // secondary OAM evaluation, see https://www.nesdev.org/wiki/PPU_sprite_evaluation
+    fn evaluate_sprites(&mut self) {
+        let mut indexes: Vec<u16> = Vec::new();
+        let mut n: u16 = 0;
+        while n < SPRITE_NUM && indexes.len() < LINE_SPRITE_NUM {
+            if self.is_sprite_in_line(self.sprite_ram.read(4 * n)) {
+                indexes.push(n);
+            }
+            n += 1;
+        }
+        // overflow search, the hardware increments m together with n
+        // and reads tile/attr/x bytes as Y (diagonal evaluation bug)
+        let mut m: u16 = 0;
+        let mut overflow_n: u16 = n;
+        while overflow_n < SPRITE_NUM {
+            if self.is_sprite_in_line(self.sprite_ram.read(4 * overflow_n + m)) {
+                self.set_sprite_overflow();
+                break;
+            }
+            overflow_n += 1;
+            m = (m + 1) & 0x03;
+        }
+        if !self.is_sprite_limit {
+            for i in n..SPRITE_NUM {
+                if self.is_sprite_in_line(self.sprite_ram.read(4 * i)) {
+                    indexes.push(i);
+                }
+            }
+        }
+        self.line_sprites.clear();
+        for i in indexes {
+            self.fetch_sprite(i);
+        }
+    }
+    fn fetch_sprite(&mut self, i: u16) {
+        let j: u16 = 4 * i;
+        let h: u16 = if self.is_large_sprite() {16} else {8};
+        let y: u16 = self.sprite_ram.read(j) as u16;
+        let sprite_id: u16 = self.sprite_ram.read(j + 1) as u16;
+        let attr: u8 = self.sprite_ram.read(j + 2);
+        let x: u8 = self.sprite_ram.read(j + 3);
+        let is_vertical_reverse = attr & 0x80 > 0;
+        let is_horizontal_reverse = attr & 0x40 > 0;
+        let mut row: u16 = self.line - y;
+        if is_vertical_reverse {
+            row = h - 1 - row;
+        }
+        let addr: u16 = if self.is_large_sprite() {
+            0x1000 * (sprite_id & 0x01) +
+                ((sprite_id & 0xFE) + row / 8) * 16 + row % 8
+        } else {
+            self.get_sprite_table_offset() + sprite_id * 16 + row
+        };
+        let mut pattern_lo: u8 = self.read_char(addr);
+        let mut pattern_hi: u8 = self.read_char(addr + 8);
+        if is_horizontal_reverse {
+            pattern_lo = pattern_lo.reverse_bits();
+            pattern_hi = pattern_hi.reverse_bits();
+        }
+        self.line_sprites.push(LineSprite {
+            is_sprite_0: i == 0,
+            x,
+            attr,
+            pattern_lo,
+            pattern_hi,
+        });
+    }
+    fn render_pixel(&mut self, image: &mut Image) {
+        let x: usize = self.cycle as usize - 1;
+        let (mut bg_pixel, mut bg_palette) = (0u8, 0u8);
+        if self.get_is_background_enable() && (x >= 8 || self.creg2 & 0x02 > 0) {
+            let mux: u16 = 0x8000 >> self.fine_x;
+            bg_pixel = ((self.pattern_hi_shift & mux > 0) as u8) << 1 |
+                (self.pattern_lo_shift & mux > 0) as u8;
+            bg_palette = ((self.attribute_hi_shift & mux > 0) as u8) << 1 |
+                (self.attribute_lo_shift & mux > 0) as u8;
+        }
+        let (mut sp_pixel, mut sp_palette, mut is_low_priority) = (0u8, 0u8, false);
+        let mut is_sprite_0_pixel = false;
+        if self.get_is_sprite_enable() && (x >= 8 || self.creg2 & 0x04 > 0) {
+            for sprite in self.line_sprites.iter() {
+                let offset: usize = x.wrapping_sub(sprite.x as usize);
+                if offset >= 8 {
+                    continue;
+                }
+                let bit: usize = 7 - offset;
+                let pixel: u8 = ((sprite.pattern_hi >> bit) & 0x01) << 1 |
+                    ((sprite.pattern_lo >> bit) & 0x01);
+                if pixel > 0 {
+                    sp_pixel = pixel;
+                    sp_palette = sprite.attr & 0x03;
+                    is_low_priority = sprite.attr & 0x20 > 0;
+                    // sprite 0 is always first in line, so it is opaque here
+                    is_sprite_0_pixel = sprite.is_sprite_0;
+                    break;
+                }
+            }
+        }
+        // opaque sprite 0 over opaque background, never at x=255
+        // (masked left 8 pixels are already transparent)
+        if is_sprite_0_pixel && bg_pixel > 0 && x != 255 {
+            self.set_sprite_0_hit();
+        }
+        let addr: u8 = match (bg_pixel, sp_pixel) {
+            (0, 0) => 0,
+            (0, _) => 0x10 + sp_palette * 4 + sp_pixel,
+            (_, 0) => bg_palette * 4 + bg_pixel,
+            _ => if is_low_priority {
+                bg_palette * 4 + bg_pixel
             } else {
-                (sprite_id, self.get_sprite_table_offset())
-            };
-            // dbg!(sprite_id, offset);
-            self.build_sprite_data(
-                false,
-                sprite_id,
-                offset,
-                &mut sprite
-            );
-            // if sprite_id > 0 {
-            //     dbg!(i, j+1, sprite_id, self.sprite_ram_addr);
-            //     for a in &sprite.data{
-            //         for b in a.iter(){
-            //             // let val = if *b > 0 {"#"} else {" "};
-            //             print!("{}", *b);
-            //         }
-            //         print!(";\n");
-            //     }
-            //     print!(";\n");
-            // }
-            image.sprite.push(sprite);
-        }
-    }
-    // the element of background
-    fn build_tile(&mut self, image: &mut Image, x: u8, y: u8, offset: u16, i: u8, j: u8) {
-        let block_id: u8 = self.get_block_id(x as u16, y as u16);
-        let sprite_id: u16 = self.get_sprite_id(x as u16, y as u16, offset) as u16;
-        let attr: u16 = self.get_attribute(x as u16, y as u16, offset) as u16;
-        let palette_id: u16 = (attr >> (block_id * 2)) as u16 & 0x03;
-        let offset: u16 = self.get_background_table_offset();
-        let tile = &mut image.background[i as usize][j as usize];
-        // let is_no_update =
-        //     tile.sprite_id == sprite_id &&
-        //     tile.palette_id == palette_id &&
-        //     tile.scroll_x == self.scroll_x &&
-        //     tile.scroll_y == self.scroll_y;
-        // if is_no_update {
-        //     tile.is_need_update = false;
-        //     return;
-        // } else {
-        //     tile.is_need_update = true;
-        // }
-        tile.sprite_id = sprite_id;
-        tile.palette_id = palette_id;
-        // dbg!(sprite_id, offset);
-        self.build_sprite_data(
-            true,
-            sprite_id,
-            offset,
-            &mut tile.sprite
-        );
-        tile.scroll_x = self.scroll_x;
-        tile.scroll_y = self.scroll_y;
-        tile.is_background_enable = true;
-        tile.is_background_enable = self.get_is_background_enable();
-    }
-    // draw every 8 line
-    fn build_background(&mut self, image: &mut Image) {
-        let i : u8 = self.background_index;
-        let tile_y: u8 = self.get_scroll_tile_y() % V_SPRITE_NUM as u8;
-        let y_offset: u8 =
-            2 * ((self.get_scroll_tile_y() / V_SPRITE_NUM as u8) % 2);
-        for j in 0..H_SPRITE_NUM as u8 {
-            let x: u8 = (j + self.get_scroll_tile_x()) as u8;
-            let tile_x: u8 = x % H_SPRITE_NUM as u8;
-            let bg_id: u8 = (x / H_SPRITE_NUM as u8) % 2 + y_offset;
-            let offset: u16 = bg_id as u16 * 0x0400;
-            self.build_tile(
-                image,
-                tile_x,
-                tile_y,
-                offset,
-                i, j
-            );
-        }
-        self.background_index += 1;
+                0x10 + sp_palette * 4 + sp_pixel
+            },
+        };
+        let mut color: u8 = self.palette.read_at(addr as u16) & 0x3F;
+        if self.creg2 & 0x01 > 0 {
+            color &= 0x30;
+        }
+        let emphasis: u16 = (self.creg2 as u16 & 0xE0) << 1;
+        image.screen[self.line as usize][x] = emphasis | color as u16;
     }
 
     fn build_dbg_patterns(&mut self, image: &mut Image) {
@@ -789,55 +1031,325 @@ impl<'a> Ppu<'a> {
         }
     }
 
-    pub fn run(&mut self, cycle: u64, image: &mut Image, interrupts: &mut Interrupts) -> bool{
-        self.cycle += 3 * cycle;
-
-        if self.cycle >= CYCLE_PER_LINE as u64 {
-            if self.line == 0 {
-                image.sprite.resize(0, Sprite::new());
+    // one dot, returns true when the frame is done
+    fn step(&mut self, image: &mut Image, interrupts: &mut Interrupts) -> bool {
+        if self.is_rendering_enabled() && self.is_rendering_line() {
+            if self.line == self.region.pre_render_line() && self.cycle == 1 {
+                self.corrupt_sprite_ram();
             }
-
-            if self.is_sprite_0_hit() {
-                // WA
-                // self.set_sprite_0_hit();
+            self.fetch_background();
+            if (257..=320).contains(&self.cycle) {
+                self.sprite_ram_addr = 0;
             }
-
-            self.cycle -= CYCLE_PER_LINE as u64;
-            self.line += 1;
-
-            if self.line <= V_SIZE as u16 &&
-                    self.scroll_y <= V_SIZE as u8 &&
-                    self.line % TILE_SIZE as u16 == 0 {
-
-                self.build_background(image);
-                // WA
-                if self.already_sprite_0_hit {
-                    self.set_sprite_0_hit();
+            if self.cycle == 257 {
+                // sprites on line 0 are never fetched on the pre-render line
+                if self.line == self.region.pre_render_line() {
+                    self.line_sprites.clear();
+                } else {
+                    self.evaluate_sprites();
                 }
             }
-            if self.line == (V_SIZE as u16 + 1) {
+        }
+        if self.line < V_SIZE as u16 && (1..=H_SIZE as u64).contains(&self.cycle) {
+            self.render_pixel(image);
+        }
+        if self.cycle == 1 {
+            if self.line == self.region.vblank_line() {
                 self.set_vblank();
-                self.clear_sprite_0_hit(self.line as usize);
                 interrupts.deassert_nmi();
                 if self.has_vblank_irq_enabled() {
                     interrupts.assert_nmi();
                 }
             }
-            if self.line >= V_SIZE_WITH_VBLANK as u16 {                
-                self.clear_sprite_0_hit(self.line as usize);
+            if self.line == self.region.pre_render_line() {
                 self.clear_vblank();
+                self.clear_sprite_0_hit();
+                self.clear_sprite_overflow();
                 interrupts.deassert_nmi();
-                self.line = 0;
-                self.background_index = 0;
-                self.get_palette(image);
-                self.build_sprites(image);
-                self.build_dbg_bg(image);
-                self.build_dbg_patterns(image);
-                image.current_x = self.scroll_x;
-                image.current_y = self.scroll_y;
-                return true;
             }
         }
-        return false;
+
+        self.cycle += 1;
+        // the pre-render line is one dot shorter on odd frames
+        let is_skip_dot = self.line == self.region.pre_render_line() && self.cycle == 340 &&
+            self.is_odd_frame && self.is_rendering_enabled() && self.region.has_odd_frame_skip();
+        if self.cycle < CYCLE_PER_LINE as u64 && !is_skip_dot {
+            return false;
+        }
+        self.cycle = 0;
+        self.line += 1;
+        if self.line < self.region.frame_lines() {
+            return false;
+        }
+        self.line = 0;
+        self.is_odd_frame = !self.is_odd_frame;
+        self.decay_io_latch();
+        self.get_palette(image);
+        self.build_dbg_bg(image);
+        self.build_dbg_patterns(image);
+        image.current_x = ((self.t >> 10) & 0x01) * H_SIZE as u16 +
+            (self.t & 0x1F) * 8 + self.fine_x as u16;
+        image.current_y = ((self.t >> 11) & 0x01) * V_SIZE as u16 +
+            ((self.t >> 5) & 0x1F) * 8 + ((self.t >> 12) & 0x07);
+        true
+    }
+
+    pub fn run(&mut self, cycle: u64, image: &mut Image, interrupts: &mut Interrupts) -> bool{
+        let mut is_frame_ready = false;
+        if self.is_nmi_requested {
+            self.is_nmi_requested = false;
+            interrupts.assert_nmi();
+        }
+        let (num, den) = self.region.dots_per_cycle();
+        let dots: u64 = num * cycle + self.dot_remainder;
+        self.dot_remainder = dots % den;
+        for _ in 0..dots / den {
+            is_frame_ready |= self.step(image, interrupts);
+        }
+        is_frame_ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::bench::synthetic_cassette;
+
+    fn set_vram_addr(ppu: &mut Ppu, addr: u16) {
+        ppu.write(0x0006, (addr >> 8) as u8);
+        ppu.write(0x0006, addr as u8);
+    }
+
+    fn run_frame(ppu: &mut Ppu, image: &mut Image, interrupts: &mut Interrupts) {
+        while !ppu.run(1, image, interrupts) {}
+    }
+
+    // tile 1 solid, tile 2 clear, tile 3 solid, tile 4 left column only
+    // name tables all tile 1, returns the line and dot of the first hit
+    fn sprite_0_hit(creg1: u8, creg2: u8, sprite: [u8; 4]) -> Option<(u16, u64)> {
+        let cas = synthetic_cassette();
+        let mut vram: Ram = Ram::new(VRAM_SIZE);
+        let mut ppu: Ppu = Ppu::new(&cas, &mut vram);
+        let mut image: Image = Image::new();
+        let mut interrupts: Interrupts = Interrupts::new();
+        ppu.char_ram.data.fill(0);
+        ppu.char_ram.data[0x10..0x18].fill(0xFF);
+        ppu.char_ram.data[0x30..0x38].fill(0xFF);
+        ppu.char_ram.data[0x40..0x48].fill(0x80);
+        ppu.vram.data.fill(0x01);
+        ppu.sprite_ram.data.fill(0xFF);
+        ppu.sprite_ram.data[..4].copy_from_slice(&sprite);
+        ppu.creg1 = creg1;
+        ppu.creg2 = creg2;
+        loop {
+            let (line, cycle) = (ppu.line, ppu.cycle);
+            let is_frame_done: bool = ppu.step(&mut image, &mut interrupts);
+            if ppu.sreg & 0x40 > 0 {
+                return Some((line, cycle));
+            }
+            if is_frame_done {
+                return None;
+            }
+        }
+    }
+
+    #[test]
+    fn mapper_name_tables() {
+        let cas = synthetic_cassette();
+        let mut vram: Ram = Ram::new(VRAM_SIZE);
+        let mut ppu: Ppu = Ppu::new(&cas, &mut vram);
+        ppu.set_mirroring(Mirroring::Vertical);
+        set_vram_addr(&mut ppu, 0x2005);
+        ppu.write(0x0007, 0x11);
+
+        // ExRAM at 0x2400, shared with the mapper registers
+        ppu.set_name_table(1, NameTable::ExRam);
+        ppu.write_ex_ram(0x5C05, 0x77);
+        assert_eq!(ppu.read_name_table(0x2405), 0x77);
+        set_vram_addr(&mut ppu, 0x2406);
+        ppu.write(0x0007, 0x66);
+        assert_eq!(ppu.read_ex_ram(0x5C06), 0x66);
+
+        // fill mode at 0x2800, writes are ignored
+        ppu.set_name_table(2, NameTable::Fill);
+        ppu.set_fill_mode(0x42, 0x02);
+        set_vram_addr(&mut ppu, 0x2800);
+        ppu.write(0x0007, 0x99);
+        assert_eq!(ppu.read_name_table(0x2800), 0x42);
+        assert_eq!(ppu.read_name_table(0x2BFF), 0xAA);
+
+        // the other slots keep the VRAM pages
+        assert_eq!(ppu.read_name_table(0x2005), 0x11);
+        assert_eq!(ppu.read_name_table(0x2C05), 0x00);
+        assert_eq!(ppu.vram.read(0x0405), 0x00);
+    }
+
+    #[test]
+    fn sprite_0_hit_timing() {
+        // sprites are drawn one line below their Y, pixel x at dot x + 1
+        assert_eq!(sprite_0_hit(0x00, 0x1E, [20, 1, 0x00, 100]), Some((21, 101)));
+        // no hit at x=255
+        assert_eq!(sprite_0_hit(0x00, 0x1E, [20, 1, 0x00, 255]), None);
+        assert_eq!(sprite_0_hit(0x00, 0x1E, [20, 1, 0x00, 248]), Some((21, 249)));
+        // left column clipping of either layer
+        assert_eq!(sprite_0_hit(0x00, 0x1E, [20, 1, 0x00, 0]), Some((21, 1)));
+        assert_eq!(sprite_0_hit(0x00, 0x18, [20, 1, 0x00, 0]), None);
+        assert_eq!(sprite_0_hit(0x00, 0x1A, [20, 1, 0x00, 4]), Some((21, 9)));
+        assert_eq!(sprite_0_hit(0x00, 0x1C, [20, 1, 0x00, 4]), Some((21, 9)));
+        // transparent background or sprite
+        assert_eq!(sprite_0_hit(0x00, 0x1E, [20, 2, 0x00, 100]), None);
+        assert_eq!(sprite_0_hit(0x00, 0x14, [20, 1, 0x00, 100]), None);
+        // horizontal flip moves the left column to the right
+        assert_eq!(sprite_0_hit(0x00, 0x1E, [20, 4, 0x00, 100]), Some((21, 101)));
+        assert_eq!(sprite_0_hit(0x00, 0x1E, [20, 4, 0x40, 100]), Some((21, 108)));
+        // 8x16, tile 2 on top of tile 3, vertical flip swaps them
+        assert_eq!(sprite_0_hit(0x20, 0x1E, [20, 2, 0x00, 100]), Some((29, 101)));
+        assert_eq!(sprite_0_hit(0x20, 0x1E, [20, 2, 0x80, 100]), Some((21, 101)));
+    }
+
+    // sprites 0-7 on line 50, the rest off screen, then `extra` at 4 * 8
+    fn evaluate(extra: &[u8], is_sprite_limit: bool) -> (usize, bool) {
+        let cas = synthetic_cassette();
+        let mut vram: Ram = Ram::new(VRAM_SIZE);
+        let mut ppu: Ppu = Ppu::new(&cas, &mut vram);
+        ppu.sprite_ram.data.fill(0xFF);
+        for i in 0..LINE_SPRITE_NUM {
+            ppu.sprite_ram.data[4 * i..4 * i + 4].copy_from_slice(&[48, 0, 0, 8 * i as u8]);
+        }
+        ppu.sprite_ram.data[4 * LINE_SPRITE_NUM..4 * LINE_SPRITE_NUM + extra.len()]
+            .copy_from_slice(extra);
+        ppu.set_sprite_limit(is_sprite_limit);
+        ppu.line = 50;
+        ppu.evaluate_sprites();
+        (ppu.line_sprites.len(), ppu.sreg & 0x20 > 0)
+    }
+
+    #[test]
+    fn sprite_overflow() {
+        assert_eq!(evaluate(&[], true), (8, false));
+        // a 9th sprite right after the first 8
+        assert_eq!(evaluate(&[49, 0, 0, 0], true), (8, true));
+        // the flag is set even when the limit is off
+        assert_eq!(evaluate(&[49, 0, 0, 0], false), (9, true));
+        // sprite 9 is on the line but its tile byte is read as Y
+        assert_eq!(evaluate(&[0xFF, 0xFF, 0xFF, 0xFF, 49, 0xFF, 0xFF, 0xFF], true), (8, false));
+        // sprite 9 is off the line but its tile byte is in range
+        assert_eq!(evaluate(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 49, 0xFF, 0xFF], true), (8, true));
+        // m wraps after the X byte, sprite 12 is read at its Y again
+        let mut extra: Vec<u8> = vec![0xFF; 20];
+        extra[16] = 49;
+        assert_eq!(evaluate(&extra, true), (8, true));
+        extra[16] = 0xFF;
+        extra[15] = 49;
+        assert_eq!(evaluate(&extra, true), (8, true));
+        extra[15] = 0xFF;
+        extra[14] = 49;
+        assert_eq!(evaluate(&extra, true), (8, false));
+    }
+
+    #[test]
+    fn region_frames() {
+        let cas = synthetic_cassette();
+        let regions = [(Region::Ntsc, 262, 241), (Region::Pal, 312, 241), (Region::Dendy, 312, 291)];
+        for (region, frame_lines, vblank) in regions.iter() {
+            let mut vram: Ram = Ram::new(VRAM_SIZE);
+            let mut ppu: Ppu = Ppu::new(&cas, &mut vram);
+            let mut image: Image = Image::new();
+            let mut interrupts: Interrupts = Interrupts::new();
+            ppu.set_region(*region);
+            ppu.creg2 = 0x18;
+            for frame in 0..2 {
+                let (mut dots, mut lines, mut vblank_line) = (0u64, 0u16, None);
+                loop {
+                    lines = lines.max(ppu.line + 1);
+                    dots += 1;
+                    let is_frame_done: bool = ppu.step(&mut image, &mut interrupts);
+                    if vblank_line.is_none() && ppu.sreg & 0x80 > 0 {
+                        vblank_line = Some(ppu.line);
+                    }
+                    if is_frame_done {
+                        break;
+                    }
+                }
+                let is_skipped: bool = frame == 1 && *region == Region::Ntsc;
+                assert_eq!(dots, 341 * *frame_lines as u64 - is_skipped as u64, "{:?}", region);
+                assert_eq!(lines, *frame_lines);
+                assert_eq!(vblank_line, Some(*vblank));
+            }
+            // 3.2 dots per CPU cycle on PAL, the fraction is carried over
+            let mut counts: Vec<u64> = Vec::new();
+            for _ in 0..5 {
+                let before: u64 = ppu.line as u64 * 341 + ppu.cycle;
+                ppu.run(1, &mut image, &mut interrupts);
+                counts.push(ppu.line as u64 * 341 + ppu.cycle - before);
+            }
+            let expected: Vec<u64> = if *region == Region::Pal { vec![3, 3, 3, 3, 4] } else { vec![3; 5] };
+            assert_eq!(counts, expected);
+        }
+    }
+
+    #[test]
+    fn register_reads() {
+        let cas = synthetic_cassette();
+        let mut vram: Ram = Ram::new(VRAM_SIZE);
+        let mut ppu: Ppu = Ppu::new(&cas, &mut vram);
+
+        // PPUSTATUS lower 5bit is the last value on the bus
+        ppu.write(0x0002, 0x15);
+        ppu.sreg = 0x80;
+        assert_eq!(ppu.read(0x0002), 0x95);
+        assert_eq!(ppu.read(0x0002), 0x15);
+
+        // name table reads return the previous buffer
+        set_vram_addr(&mut ppu, 0x2000);
+        ppu.write(0x0007, 0xAB);
+        ppu.write(0x0007, 0xCD);
+        set_vram_addr(&mut ppu, 0x2000);
+        assert_eq!(ppu.read(0x0007), 0x00);
+        assert_eq!(ppu.read(0x0007), 0xAB);
+        assert_eq!(ppu.read(0x0007), 0xCD);
+
+        // palette reads are direct and fill the buffer from addr - 0x1000
+        set_vram_addr(&mut ppu, 0x2F00);
+        ppu.write(0x0007, 0x77);
+        set_vram_addr(&mut ppu, 0x3F00);
+        ppu.write(0x0007, 0x2A);
+        set_vram_addr(&mut ppu, 0x3F00);
+        assert_eq!(ppu.read(0x0007), 0x2A);
+        set_vram_addr(&mut ppu, 0x2000);
+        assert_eq!(ppu.read(0x0007), 0x77);
+
+        // attribute bytes have no bit 2-4
+        ppu.write(0x0003, 0x01);
+        ppu.write(0x0004, 0xFF);
+        ppu.write(0x0004, 0xFF);
+        ppu.write(0x0003, 0x01);
+        assert_eq!(ppu.read(0x0004), 0xFF);
+        ppu.write(0x0003, 0x02);
+        assert_eq!(ppu.read(0x0004), 0xE3);
+    }
+
+    #[test]
+    fn io_latch_decay() {
+        let cas = synthetic_cassette();
+        let mut vram: Ram = Ram::new(VRAM_SIZE);
+        let mut ppu: Ppu = Ppu::new(&cas, &mut vram);
+        let mut image: Image = Image::new();
+        let mut interrupts: Interrupts = Interrupts::new();
+
+        ppu.write(0x0002, 0xFF);
+        for _ in 0..IO_LATCH_DECAY_FRAMES - 1 {
+            run_frame(&mut ppu, &mut image, &mut interrupts);
+        }
+        assert_eq!(ppu.read(0x0005), 0xFF);
+        // PPUSTATUS refreshes bit 5-7 only
+        ppu.sreg = 0xE0;
+        ppu.read(0x0002);
+        run_frame(&mut ppu, &mut image, &mut interrupts);
+        assert_eq!(ppu.read(0x0005), 0xE0);
+        for _ in 0..IO_LATCH_DECAY_FRAMES {
+            run_frame(&mut ppu, &mut image, &mut interrupts);
+        }
+        assert_eq!(ppu.read(0x0005), 0x00);
     }
 }
@@ -0,0 +1,144 @@
+use super::cassette::{Header, Timing};
+
+/*
+    [Region] timing of the CPU, PPU and APU
+    | region | CPU clock Hz | lines | vblank | dots/cycle | frame Hz |
+    +--------+--------------+-------+--------+------------+----------+
+    | NTSC   | 1789772.5    | 262   | 241    | 3          | 60.0988  |
+    | PAL    | 1662607.0    | 312   | 241    | 3.2        | 50.0070  |
+    | Dendy  | 1773447.5    | 312   | 291    | 3          | 50.0070  |
+    the pre-render line is the last line, only NTSC skips a dot on
+    odd frames. PAL (2C07) and Dendy swap the red and green emphasis
+    bits of PPUMASK. the APU frame counter and the noise and DMC period
+    tables follow the region, PAL has its own tables, Dendy uses NTSC's.
+    the FDS audio is resampled at the CPU clock. Dendy vblank starts 50 lines late
+    so NTSC games keep their NMI timing.
+
+    chosen by --region, then the NES 2.0 header or the rom database,
+    multi-region games run as NTSC.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl Region {
+    pub fn parse(name: &str) -> Result<Region, String> {
+        match name.to_lowercase().as_str() {
+            "ntsc" => Ok(Region::Ntsc),
+            "pal" => Ok(Region::Pal),
+            "dendy" => Ok(Region::Dendy),
+            _ => Err(format!("unknown region {}, expected ntsc, pal or dendy", name)),
+        }
+    }
+
+    pub fn from_header(header: &Header) -> Region {
+        match header.timing {
+            Timing::Pal => Region::Pal,
+            Timing::Dendy => Region::Dendy,
+            Timing::Ntsc | Timing::MultiRegion => Region::Ntsc,
+        }
+    }
+
+    // command line over the header
+    pub fn select(option: Option<Region>, header: &Header) -> Region {
+        option.unwrap_or_else(|| Region::from_header(header))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Region::Ntsc => "NTSC",
+            Region::Pal => "PAL",
+            Region::Dendy => "Dendy",
+        }
+    }
+
+    pub fn cpu_clock(&self) -> f64 {
+        match self {
+            Region::Ntsc => 1789772.5,
+            Region::Pal => 1662607.0,
+            Region::Dendy => 1773447.5,
+        }
+    }
+
+    // lines per frame including vblank and the pre-render line
+    pub fn frame_lines(&self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    pub fn vblank_line(&self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    pub fn pre_render_line(&self) -> u16 {
+        self.frame_lines() - 1
+    }
+
+    // PPU dots per CPU cycle as numerator, denominator
+    pub fn dots_per_cycle(&self) -> (u64, u64) {
+        match self {
+            Region::Ntsc | Region::Dendy => (3, 1),
+            Region::Pal => (16, 5),
+        }
+    }
+
+    pub fn has_odd_frame_skip(&self) -> bool {
+        *self == Region::Ntsc
+    }
+
+    pub fn is_emphasis_swapped(&self) -> bool {
+        *self != Region::Ntsc
+    }
+
+    // frames per second as numerator, denominator
+    pub fn frame_rate(&self) -> (u32, u32) {
+        match self {
+            Region::Ntsc => (39375000, 655171),
+            Region::Pal | Region::Dendy => (10640685, 212784),
+        }
+    }
+
+    // rounded for the window frame limiter
+    pub fn fps(&self) -> u32 {
+        match self {
+            Region::Ntsc => 60,
+            Region::Pal | Region::Dendy => 50,
+        }
+    }
+
+    // CPU cycles between the quarter frames of the frame counter
+    pub fn frame_counter_period(&self) -> u64 {
+        match self {
+            Region::Ntsc | Region::Dendy => 7457,
+            Region::Pal => 8313,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a frame is the same number of dots and CPU cycles in each region
+    #[test]
+    fn frame_timing() {
+        for region in [Region::Ntsc, Region::Pal, Region::Dendy].iter() {
+            let dots: f64 = 341.0 * region.frame_lines() as f64;
+            let (num, den) = region.dots_per_cycle();
+            let cycles: f64 = dots * den as f64 / num as f64;
+            let (rate_num, rate_den) = region.frame_rate();
+            let rate: f64 = rate_num as f64 / rate_den as f64;
+            assert!((region.cpu_clock() / cycles - rate).abs() < 0.01, "{:?}", region);
+        }
+        assert_eq!(Region::parse("PAL"), Ok(Region::Pal));
+    }
+}
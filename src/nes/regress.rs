@@ -0,0 +1,187 @@
+use std::fs;
+use std::path::Path;
+
+use super::cassette::Cassette;
+use super::config::Config;
+use super::render::{FrameBuffer, Render};
+use super::script::InputScript;
+
+/*
+    [Screenshot regression]
+    boot each rom in the manifest, feed the input script, run to
+    the given frame and compare the hash of Render::data with the
+    golden file regress/golden/<name>.txt. a missing rom or golden
+    is a failure, roms must be checked in under regress/roms.
+
+    # name     rom                            frames  script
+    synthetic  regress/roms/synthetic.nes     120     regress/scripts/synthetic.txt
+*/
+
+pub const MANIFEST: &str = "regress/cases.txt";
+pub const GOLDEN_DIR: &str = "regress/golden";
+
+#[derive(Debug, Clone)]
+pub struct Case {
+    pub name: String,
+    pub rom: String,
+    pub frames: u32,
+    pub script: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Pass,
+    Fail { expected: u64, actual: u64 },
+    NoGolden(u64),
+    Updated(u64),
+    NoRom,
+}
+
+pub fn load_cases(path: &str) -> Result<Vec<Case>, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("{}: {}", path, e))?;
+    let mut cases = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() < 3 {
+            return Err(format!("{}:{}: expected name rom frames [script]", path, i + 1));
+        }
+        let frames: u32 = words[2].parse()
+            .map_err(|_| format!("{}:{}: invalid frames {}", path, i + 1, words[2]))?;
+        if frames == 0 {
+            return Err(format!("{}:{}: frames must be at least 1", path, i + 1));
+        }
+        cases.push(Case {
+            name: words[0].to_string(),
+            rom: words[1].to_string(),
+            frames,
+            script: words.get(3).map(|s| s.to_string()),
+        });
+    }
+    Ok(cases)
+}
+
+// FNV-1a over the RGB bytes of every pixel
+pub fn hash_frame(data: &FrameBuffer) -> u64 {
+    let mut hash: u64 = 0xCBF29CE484222325;
+    for pixel in data.pixels.iter() {
+        for shift in [16, 8, 0] {
+            hash ^= ((pixel >> shift) & 0xFF) as u64;
+            hash = hash.wrapping_mul(0x100000001B3);
+        }
+    }
+    hash
+}
+
+pub fn golden_path(case: &Case) -> String {
+    format!("{}/{}.txt", GOLDEN_DIR, case.name)
+}
+
+fn read_golden(case: &Case) -> Option<u64> {
+    let text = fs::read_to_string(golden_path(case)).ok()?;
+    u64::from_str_radix(text.trim(), 16).ok()
+}
+
+fn write_golden(case: &Case, hash: u64) -> Result<(), String> {
+    fs::create_dir_all(GOLDEN_DIR).map_err(|e| e.to_string())?;
+    fs::write(golden_path(case), format!("{:016X}\n", hash))
+        .map_err(|e| e.to_string())
+}
+
+// hash of the last frame of the case
+pub fn run_case(case: &Case) -> Result<u64, String> {
+    let script = match &case.script {
+        Some(path) => InputScript::load(path)?,
+        None => InputScript::new(),
+    };
+    let cas: Cassette = Cassette::new(&case.rom)?;
+    let mut render: Render = Render::new();
+    let mut hash: u64 = 0;
+    super::run_headless(&cas, &Config::new(), case.frames, &script, |frame, image, _| {
+        if frame + 1 == case.frames {
+            render.render(image);
+            hash = hash_frame(&render.data);
+        }
+    });
+    Ok(hash)
+}
+
+pub fn check_case(case: &Case, update: bool) -> Result<Outcome, String> {
+    if !Path::new(&case.rom).exists() {
+        return Ok(Outcome::NoRom);
+    }
+    let actual = run_case(case)?;
+    if update {
+        write_golden(case, actual)?;
+        return Ok(Outcome::Updated(actual));
+    }
+    Ok(match read_golden(case) {
+        Some(expected) if expected == actual => Outcome::Pass,
+        Some(expected) => Outcome::Fail { expected, actual },
+        None => Outcome::NoGolden(actual),
+    })
+}
+
+// returns true when no case failed
+pub fn run_all(manifest: &str, update: bool) -> Result<bool, String> {
+    let mut ok = true;
+    for case in load_cases(manifest)?.iter() {
+        let outcome = check_case(case, update)?;
+        match outcome {
+            Outcome::Pass => println!("[PASS] {}", case.name),
+            Outcome::Fail { expected, actual } => {
+                println!("[FAIL] {} expected:{:016X} actual:{:016X}",
+                    case.name, expected, actual);
+                ok = false;
+            },
+            Outcome::NoGolden(actual) => {
+                println!("[FAIL] {} no golden {} (actual:{:016X}), run with --update-golden",
+                    case.name, golden_path(case), actual);
+                ok = false;
+            },
+            Outcome::Updated(actual) => {
+                println!("[UPDATE] {} {:016X}", case.name, actual);
+            },
+            Outcome::NoRom => {
+                println!("[FAIL] {} {} not found", case.name, case.rom);
+                ok = false;
+            },
+        }
+    }
+    Ok(ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn screenshot_regression() {
+        let update = std::env::var("RUSTNES_UPDATE_GOLDEN").is_ok();
+        assert!(run_all(MANIFEST, update).unwrap());
+    }
+
+    // the checked in rom is the one of bench.rs
+    #[test]
+    fn synthetic_rom_is_current() {
+        let rom = fs::read("regress/roms/synthetic.nes").unwrap();
+        assert!(rom == super::super::bench::synthetic_rom());
+    }
+
+    #[test]
+    fn missing_inputs_fail() {
+        let case = Case {
+            name: "missing".to_string(),
+            rom: "regress/roms/missing.nes".to_string(),
+            frames: 1,
+            script: None,
+        };
+        assert_eq!(check_case(&case, false), Ok(Outcome::NoRom));
+        let case = Case { name: "no_golden".to_string(), rom: "regress/roms/synthetic.nes".to_string(), ..case };
+        assert!(matches!(check_case(&case, false), Ok(Outcome::NoGolden(_))));
+    }
+}
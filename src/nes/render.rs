@@ -1,32 +1,53 @@
 #![allow(unused_variables)]
 
+use super::colors;
+use super::ntsc::*;
+use super::scale::{self, ScaleOptions};
 use super::ppu::*;
 
-const COLORS: [u64; 64] = [
-    0x808080, 0x003DA6, 0x0012B0, 0x440096,
-    0xA1005E, 0xC70028, 0xBA0600, 0x8C1700,
-    0x5C2F00, 0x104500, 0x054A00, 0x00472E,
-    0x004166, 0x000000, 0x050505, 0x050505,
-    0xC7C7C7, 0x0077FF, 0x2155FF, 0x8237FA,
-    0xEB2FB5, 0xFF2950, 0xFF2200, 0xD63200,
-    0xC46200, 0x358000, 0x058F00, 0x008A55,
-    0x0099CC, 0x212121, 0x090909, 0x090909,
-    0xFFFFFF, 0x0FD7FF, 0x69A2FF, 0xD480FF,
-    0xFF45F3, 0xFF618B, 0xFF8833, 0xFF9C12,
-    0xFABC20, 0x9FE30E, 0x2BF035, 0x0CF0A4,
-    0x05FBFF, 0x5E5E5E, 0x0D0D0D, 0x0D0D0D,
-    0xFFFFFF, 0xA6FCFF, 0xB3ECFF, 0xDAABEB,
-    0xFFA8F9, 0xFFABB3, 0xFFD2B0, 0xFFEFA6,
-    0xFFF79C, 0xD7E895, 0xA6EDAF, 0xA2F2DA,
-    0x99FFFC, 0xDDDDDD, 0x111111, 0x111111
-];
+// row major 0xAARRGGBB pixels, uploaded to SDL as ARGB8888
+#[derive(Debug, Clone)]
+pub struct FrameBuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+impl FrameBuffer {
+    pub fn new(width: usize, height: usize) -> FrameBuffer {
+        FrameBuffer {
+            width,
+            height,
+            pixels: vec![0xFF000000; width * height],
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> u32 {
+        self.pixels[y * self.width + x]
+    }
+
+    // alpha is always opaque
+    pub fn set(&mut self, x: usize, y: usize, rgb: u64) {
+        self.pixels[y * self.width + x] = 0xFF000000 | rgb as u32;
+    }
+
+    pub fn row(&self, y: usize) -> &[u32] {
+        &self.pixels[y * self.width..(y + 1) * self.width]
+    }
+}
 
 #[derive(Debug)]
 pub struct Render {
     is_pattern_rendered: bool,
-    pub data: Vec<Vec<u64>>,
-    pub dbg_bg_data: Vec<Vec<u64>>,
-    pub dbg_pattern_data: Vec<Vec<u64>>,
+    colors: Vec<u64>,
+    ntsc: Option<NtscFilter>,
+    scale: ScaleOptions,
+    // screen before scaling
+    screen: FrameBuffer,
+    // screen after scaling, for the frontends
+    pub data: FrameBuffer,
+    pub dbg_bg_data: FrameBuffer,
+    pub dbg_pattern_data: FrameBuffer,
     pub temp: u16,
 }
 
@@ -34,49 +55,78 @@ impl Render {
     pub fn new() -> Render {
         Render {
             is_pattern_rendered: false,
-            data: vec![vec![0; H_SIZE]; V_SIZE],
-            dbg_bg_data: vec![vec![0; 2*H_SIZE]; 2*V_SIZE],
-            dbg_pattern_data: vec![vec![0; H_SIZE]; V_SIZE],
+            colors: colors::default_table(),
+            ntsc: None,
+            scale: ScaleOptions::new(),
+            screen: FrameBuffer::new(H_SIZE, V_SIZE),
+            data: FrameBuffer::new(H_SIZE, V_SIZE),
+            dbg_bg_data: FrameBuffer::new(2*H_SIZE, 2*V_SIZE),
+            dbg_pattern_data: FrameBuffer::new(H_SIZE, V_SIZE),
             temp: 0,
         }
     }
 
+    // 512 colors from colors::load
+    pub fn set_colors(&mut self, colors: Vec<u64>) {
+        self.colors = colors;
+    }
+
+    // the screen becomes NTSC_WIDTH wide while the filter is set
+    pub fn set_ntsc_filter(&mut self, filter: Option<NtscFilter>) {
+        self.screen = match filter {
+            Some(_) => FrameBuffer::new(NTSC_WIDTH, V_SIZE),
+            None => FrameBuffer::new(H_SIZE, V_SIZE),
+        };
+        self.ntsc = filter;
+    }
+
+    pub fn set_scale(&mut self, options: ScaleOptions) {
+        self.scale = options;
+    }
+
     pub fn render(&mut self, image: &Image) {
-        self.render_background(image);
+        self.render_screen(image);
         self.render_dbg_background(image);
-        self.render_sprite(image);
         // if !self.is_pattern_rendered {
             self.render_pattern(image);
         // }
     }
 
-    fn should_pixel_hide(&self, image: &Image, x: u8, y: u8) -> bool{
-        let tile_x: u8 = (x / 8) % H_SPRITE_NUM as u8;
-        let tile_y: u8 = (y / 8) % V_SPRITE_NUM as u8;
-        // let tile_x: u8 = (x / 8);
-        // let tile_y: u8 = (y / 8);
-        (image.background[tile_y as usize][tile_x as usize]
-            .sprite.data[(y % 8) as usize][(x % 8) as usize] % 4) > 0
+    // PPU output without filter and scaling
+    pub fn render_raw(&self, image: &Image) -> FrameBuffer {
+        let mut fb: FrameBuffer = FrameBuffer::new(H_SIZE, V_SIZE);
+        for (i, line) in image.screen.iter().enumerate() {
+            for (j, color_id) in line.iter().enumerate() {
+                fb.set(j, i, self.colors[*color_id as usize]);
+            }
+        }
+        fb
     }
 
     fn render_dbg_background(&mut self, image: &Image) {
         for i in 0..2*V_SPRITE_NUM {
             for j in 0..2*H_SPRITE_NUM {
-                // if image.dbg_bg[j][i].is_background_enable {
-                    self.render_dbg_tile(image, j as u16, i as u16);
-                // }
+                self.render_dbg_tile(image, j as u16, i as u16);
             }
         }
     }
 
-    fn render_background(&mut self, image: &Image) {
-        for i in 0..V_SPRITE_NUM {
-            for j in 0..H_SPRITE_NUM {
-                if image.background[i][j].is_background_enable {
-                    self.render_tile(image, j as u8, i as u8);
+    fn render_screen(&mut self, image: &Image) {
+        if let Some(filter) = self.ntsc.as_mut() {
+            filter.apply(image, &mut self.screen);
+        } else {
+            for (i, line) in image.screen.iter().enumerate() {
+                let row = &mut self.screen.pixels[i * H_SIZE..(i + 1) * H_SIZE];
+                for (pixel, color_id) in row.iter_mut().zip(line.iter()) {
+                    *pixel = 0xFF000000 | self.colors[*color_id as usize] as u32;
                 }
             }
         }
+        if self.scale.is_identity() {
+            self.data.clone_from(&self.screen);
+        } else {
+            self.data = scale::apply(&self.screen, &self.scale);
+        }
     }
 
     fn render_dbg_tile(
@@ -86,8 +136,8 @@ impl Render {
         tile_y: u16
     ) {
         let tile:&Tile = &image.dbg_bg[tile_y as usize][tile_x as usize];
-        let current_x: u16 = (image.current_x as u16) as u16;
-        let current_y: u16 = (image.current_y as u16) as u16;
+        let current_x: u16 = image.current_x;
+        let current_y: u16 = image.current_y;
         let palette_id: u16 = tile.palette_id;
         for j in 0..8 {
             for i in 0..8 {
@@ -96,48 +146,23 @@ impl Render {
                 if x < 2*H_SIZE as u16 && y < 2*V_SIZE as u16 {
                     let color_id: u8 = image.palette[(palette_id * 4 +
                         tile.sprite.data[j as usize][i as usize] as u16) as usize];
-                    self.dbg_bg_data[(y % (2*V_SIZE) as u16) as usize][(x % (2*H_SIZE) as u16) as usize] =
-                        if i == 0 && j == 0 ||
+                    let color: u64 = if i == 0 && j == 0 ||
                                 x == 0 || y == 0 ||
                                 x == (256 - 1) || y == (240 - 1) ||
                                 x == (2 * 256 - 1) || y == (2 * 240 - 1) {
                             0xFF00FF
-                        } else if x == current_x || x == current_x + H_SIZE as u16 ||
-                                y == current_y || y == current_y + V_SIZE as u16 {
+                        } else if x == current_x ||
+                                x == (current_x + H_SIZE as u16) % (2*H_SIZE) as u16 ||
+                                y == current_y ||
+                                y == (current_y + V_SIZE as u16) % (2*V_SIZE) as u16 {
                             0
                         } else {
-                            COLORS[color_id as usize]
-                        };
-                }
-            }
-        }
-    }
-
-    fn render_tile(
-        &mut self, 
-        image: &Image,
-        tile_x: u8,
-        tile_y: u8
-    ) {
-        let tile:&Tile = &image.background[tile_y as usize][tile_x as usize];
-        let palette_id: u16 = tile.palette_id;
-        let off_x: i16 = (tile.scroll_x % 8) as i16;
-        let off_y: i16 = (tile.scroll_y % 8) as i16;
-        for j in 0..8 {
-            for i in 0..8 {
-                let x: i16 = 8 * tile_x as i16 + i as i16 - off_x;
-                let y: i16 = 8 * tile_y as i16 + j as i16 - off_y;
-                if 0 <= x && x < H_SIZE as i16 && 0 <= y && y < V_SIZE as i16 {
-                    let color_id: u8 = image.palette[(palette_id * 4 +
-                        tile.sprite.data[j as usize][i as usize] as u16) as usize];
-                    self.data[((y as u8) % 240) as usize][(x % 256) as usize] =
-                        if i == 0 && j == 0 ||
-                                x == 0 || y == 0 ||
-                                x == 255 || y == 239 {
-                            0x00FF00
-                        } else {
-                            COLORS[color_id as usize]
+                            self.colors[color_id as usize]
                         };
+                    self.dbg_bg_data.set(
+                        (x % (2*H_SIZE) as u16) as usize,
+                        (y % (2*V_SIZE) as u16) as usize,
+                        color);
                 }
             }
         }
@@ -154,46 +179,17 @@ impl Render {
                     let x = sprite.x + j as u8;
                     let color_id = palette[(palette_id * 4 +
                         sprite.data[i as usize][j as usize] + 0x10) as usize];
-                    self.dbg_pattern_data[y as usize % V_SIZE][x as usize % H_SIZE] =
-                    if i == 0 && j == 0 ||
+                    let color: u64 = if i == 0 && j == 0 ||
                             x == 0 || y == 0 || y == 64 ||
                             x == 255 || y == 239 {
                         0x0000FF
                     } else {
-                        COLORS[color_id as usize]
+                        self.colors[color_id as usize]
                     };
+                    self.dbg_pattern_data.set(x as usize % H_SIZE, y as usize % V_SIZE, color);
                 }
             }
         }
         // self.is_pattern_rendered = true;
     }
-
-    fn render_sprite(&mut self, image: &Image) {
-        for sprite in image.sprite.iter() {
-            let palette:[u8; PALETTE_SIZE] = image.palette;
-            let is_vertical_reverse = sprite.attr & 0x80 > 0;
-            let is_horizontal_reverse = sprite.attr & 0x40 > 0;
-            let is_low_priority = sprite.attr & 0x20 > 0;
-            let palette_id = sprite.attr & 0x03;
-            let h = sprite.data.len();
-            for i in 0..h {
-                let y = (sprite.y as i16 +
-                    (if is_vertical_reverse {h-1-i} else {i}) as i16) as u8;
-                for j in 0..8 {
-                    let x = (sprite.x as i16 +
-                        if is_horizontal_reverse {7-j} else {j}) as u8;
-                    if is_low_priority && self.should_pixel_hide(image, x, y) {
-                        continue;
-                    }
-                    if sprite.data[i as usize][j as usize] > 0 &&
-                            !(y >= V_SIZE as u8) {
-                        let color_id = palette[(palette_id * 4 +
-                            sprite.data[i as usize][j as usize] + 0x10) as usize];
-                        self.data[y as usize % V_SIZE][x as usize % H_SIZE] =
-                            COLORS[color_id as usize];
-                    }
-                }
-            }
-        }
-    }
 }
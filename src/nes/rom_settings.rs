@@ -0,0 +1,84 @@
+use std::fs;
+use std::path::Path;
+
+use super::checksum::crc32;
+use super::scale::{Overscan, PixelAspect, ScaleOptions};
+
+/*
+    [Per rom settings] settings/<rom file name>-<crc32 of the path>.txt
+    loaded on every boot, the settings given on the command line
+    replace the loaded ones and are saved for the next boot of the
+    same rom file. roms of the same name in other directories keep
+    their own settings.
+
+    # top bottom left right
+    overscan 8 8 0 0
+    aspect   8:7
+*/
+
+pub const SETTINGS_DIR: &str = "settings";
+
+pub fn settings_path(rom: &str) -> Result<String, String> {
+    let name = Path::new(rom).file_name()
+        .ok_or(format!("no settings for rom path \"{}\"", rom))?
+        .to_string_lossy().to_string();
+    let path = fs::canonicalize(rom).map_or(rom.to_string(), |p| p.to_string_lossy().to_string());
+    Ok(format!("{}/{}-{:08X}.txt", SETTINGS_DIR, name, crc32(path.as_bytes())))
+}
+
+pub fn parse(text: &str, options: &mut ScaleOptions) -> Result<(), String> {
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match (words[0], words.len()) {
+            ("overscan", 5) => options.overscan = Overscan::parse(&words[1..].join(","))
+                .map_err(|e| format!("line {}: {}", i + 1, e))?,
+            ("aspect", 2) => options.aspect = PixelAspect::parse(words[1])
+                .map_err(|e| format!("line {}: {}", i + 1, e))?,
+            _ => return Err(format!("line {}: invalid setting {}", i + 1, line)),
+        }
+    }
+    Ok(())
+}
+
+pub fn format(options: &ScaleOptions) -> String {
+    let o = &options.overscan;
+    format!("# top bottom left right\noverscan {} {} {} {}\naspect   {}\n",
+        o.top, o.bottom, o.left, o.right, options.aspect.name())
+}
+
+// missing file keeps the options
+pub fn load(rom: &str, options: &mut ScaleOptions) -> Result<(), String> {
+    let path = settings_path(rom)?;
+    match fs::read_to_string(&path) {
+        Ok(text) => parse(&text, options).map_err(|e| format!("{}: {}", path, e)),
+        Err(_) => Ok(()),
+    }
+}
+
+pub fn save(rom: &str, options: &ScaleOptions) -> Result<(), String> {
+    let path = settings_path(rom)?;
+    fs::create_dir_all(SETTINGS_DIR).map_err(|e| e.to_string())?;
+    fs::write(path, format(options)).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_and_parse() {
+        let mut options = ScaleOptions::new();
+        options.overscan = Overscan::parse("8,4,2,1").unwrap();
+        options.aspect = PixelAspect::parse("8:7").unwrap();
+        let mut loaded = ScaleOptions::new();
+        parse(&format(&options), &mut loaded).unwrap();
+        assert_eq!((loaded.overscan, loaded.aspect), (options.overscan, options.aspect));
+        assert!(parse("overscan 1 2", &mut loaded).is_err());
+        assert!(settings_path("").is_err());
+        assert_ne!(settings_path("a/game.nes"), settings_path("b/game.nes"));
+    }
+}
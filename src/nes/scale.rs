@@ -0,0 +1,369 @@
+use super::ppu::H_SIZE;
+use super::render::FrameBuffer;
+
+/*
+    [Scale filters]
+    | name     | factor | description                              |
+    +----------+--------+------------------------------------------+
+    | nearest  |  1-8   | pixel doubling, e.g. nearest3            |
+    | scale2x  |   2    | EPX / AdvMAME2x                          |
+    | scale3x  |   3    | AdvMAME3x                                |
+
+    the overscan is cropped before the filter. after the filter,
+    scanlines darken the last row of every source pixel, every
+    other row at 1x, and the pixel aspect stretches the width of
+    square pixels.
+
+    [Pixel aspect]
+    | name | ratio | description                                 |
+    +------+-------+---------------------------------------------+
+    | 1:1  |  1/1  | square pixels                               |
+    | 8:7  |  8/7  | NTSC TV                                     |
+    | 11:8 | 11/8  | PAL TV                                      |
+*/
+
+pub const MAX_NEAREST_FACTOR: usize = 8;
+// the last row of a source pixel keeps this part of the brightness
+const SCANLINE_LEVEL: u32 = 5;
+const SCANLINE_DIVISOR: u32 = 8;
+pub const FILTER_NAMES: &str = "nearest, nearest2-8, scale2x, scale3x";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScaleFilter {
+    Nearest(usize),
+    Scale2x,
+    Scale3x,
+}
+
+impl ScaleFilter {
+    pub fn parse(name: &str) -> Result<ScaleFilter, String> {
+        match name {
+            "scale2x" => Ok(ScaleFilter::Scale2x),
+            "scale3x" => Ok(ScaleFilter::Scale3x),
+            "nearest" => Ok(ScaleFilter::Nearest(1)),
+            _ => match name.strip_prefix("nearest").and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) if (1..=MAX_NEAREST_FACTOR).contains(&n) => Ok(ScaleFilter::Nearest(n)),
+                _ => Err(format!("unknown filter {}, expected {}", name, FILTER_NAMES)),
+            },
+        }
+    }
+
+    pub fn factor(&self) -> usize {
+        match self {
+            ScaleFilter::Nearest(n) => *n,
+            ScaleFilter::Scale2x => 2,
+            ScaleFilter::Scale3x => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PixelAspect {
+    Square,
+    Ntsc,
+    Pal,
+}
+
+impl PixelAspect {
+    pub fn parse(name: &str) -> Result<PixelAspect, String> {
+        match name {
+            "1:1" => Ok(PixelAspect::Square),
+            "8:7" => Ok(PixelAspect::Ntsc),
+            "11:8" => Ok(PixelAspect::Pal),
+            _ => Err(format!("unknown pixel aspect {}", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PixelAspect::Square => "1:1",
+            PixelAspect::Ntsc => "8:7",
+            PixelAspect::Pal => "11:8",
+        }
+    }
+
+    // width : height of one pixel
+    pub fn ratio(&self) -> (usize, usize) {
+        match self {
+            PixelAspect::Square => (1, 1),
+            PixelAspect::Ntsc => (8, 7),
+            PixelAspect::Pal => (11, 8),
+        }
+    }
+}
+
+// PPU pixels cut from each edge
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Overscan {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl Overscan {
+    pub fn new() -> Overscan {
+        Overscan {
+            top: 0,
+            bottom: 0,
+            left: 0,
+            right: 0,
+        }
+    }
+
+    // top,bottom,left,right
+    pub fn parse(text: &str) -> Result<Overscan, String> {
+        let edges: Vec<usize> = text.split(',')
+            .map(|s| s.trim().parse::<usize>())
+            .collect::<Result<Vec<usize>, _>>()
+            .map_err(|_| format!("invalid overscan {}", text))?;
+        if edges.len() != 4 {
+            return Err(format!("expected top,bottom,left,right, got {}", text));
+        }
+        Ok(Overscan {
+            top: edges[0],
+            bottom: edges[1],
+            left: edges[2],
+            right: edges[3],
+        })
+    }
+
+    pub fn is_none(&self) -> bool {
+        *self == Overscan::new()
+    }
+
+    pub fn cropped_size(&self, width: usize, height: usize) -> (usize, usize) {
+        (
+            width.saturating_sub(self.left + self.right).max(1),
+            height.saturating_sub(self.top + self.bottom).max(1),
+        )
+    }
+
+    // the edges are in PPU pixels, wider screens such as NTSC are cropped in proportion
+    pub fn crop(&self, src: &FrameBuffer) -> FrameBuffer {
+        let ratio: usize = (src.width / H_SIZE).max(1);
+        let left: usize = (self.left * ratio).min(src.width - 1);
+        let top: usize = self.top.min(src.height - 1);
+        let (width, height) = Overscan {
+            left: self.left * ratio,
+            right: self.right * ratio,
+            ..*self
+        }.cropped_size(src.width, src.height);
+        let mut out: FrameBuffer = FrameBuffer::new(width, height);
+        for y in 0..height {
+            let from: usize = (top + y) * src.width + left;
+            out.pixels[y * width..(y + 1) * width]
+                .copy_from_slice(&src.pixels[from..from + width]);
+        }
+        out
+    }
+}
+
+impl Default for Overscan {
+    fn default() -> Overscan {
+        Overscan::new()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScaleOptions {
+    pub filter: ScaleFilter,
+    pub is_scanline: bool,
+    pub aspect: PixelAspect,
+    pub overscan: Overscan,
+}
+
+impl ScaleOptions {
+    pub fn new() -> ScaleOptions {
+        ScaleOptions {
+            filter: ScaleFilter::Nearest(1),
+            is_scanline: false,
+            aspect: PixelAspect::Square,
+            overscan: Overscan::new(),
+        }
+    }
+
+    // output is the same as input
+    pub fn is_identity(&self) -> bool {
+        self.filter == ScaleFilter::Nearest(1) && !self.is_scanline &&
+            self.aspect == PixelAspect::Square && self.overscan.is_none()
+    }
+
+    pub fn output_size(&self, width: usize, height: usize) -> (usize, usize) {
+        let (width, height) = self.overscan.cropped_size(width, height);
+        let n: usize = self.filter.factor();
+        let (numerator, denominator) = self.aspect.ratio();
+        (n * width * numerator / denominator, n * height)
+    }
+}
+
+impl Default for ScaleOptions {
+    fn default() -> ScaleOptions {
+        ScaleOptions::new()
+    }
+}
+
+// index of a pixel of src, edges are repeated
+fn index(src: &FrameBuffer, x: usize, y: usize, dx: isize, dy: isize) -> usize {
+    let x_: usize = (x as isize + dx).clamp(0, src.width as isize - 1) as usize;
+    let y_: usize = (y as isize + dy).clamp(0, src.height as isize - 1) as usize;
+    y_ * src.width + x_
+}
+
+fn at(src: &FrameBuffer, x: usize, y: usize, dx: isize, dy: isize) -> u32 {
+    src.pixels[index(src, x, y, dx, dy)]
+}
+
+fn nearest(src: &FrameBuffer, out: &mut FrameBuffer, n: usize) {
+    for y in 0..out.height {
+        for x in 0..out.width {
+            out.pixels[y * out.width + x] = src.get(x / n, y / n);
+        }
+    }
+}
+
+fn scale2x(src: &FrameBuffer, out: &mut FrameBuffer) {
+    for y in 0..src.height {
+        for x in 0..src.width {
+            let p = |dx: isize, dy: isize| at(src, x, y, dx, dy);
+            let (b, d, e, f, h) = (p(0, -1), p(-1, 0), p(0, 0), p(1, 0), p(0, 1));
+            let mut e_: [u32; 4] = [e; 4];
+            if b != h && d != f {
+                if d == b { e_[0] = d; }
+                if b == f { e_[1] = f; }
+                if d == h { e_[2] = d; }
+                if h == f { e_[3] = f; }
+            }
+            for (i, c) in e_.iter().enumerate() {
+                out.pixels[(2 * y + i / 2) * out.width + 2 * x + i % 2] = *c;
+            }
+        }
+    }
+}
+
+fn scale3x(src: &FrameBuffer, out: &mut FrameBuffer) {
+    for y in 0..src.height {
+        for x in 0..src.width {
+            let p = |dx: isize, dy: isize| at(src, x, y, dx, dy);
+            let (a, b, c) = (p(-1, -1), p(0, -1), p(1, -1));
+            let (d, e, f) = (p(-1, 0), p(0, 0), p(1, 0));
+            let (g, h, i) = (p(-1, 1), p(0, 1), p(1, 1));
+            let mut e_: [u32; 9] = [e; 9];
+            if b != h && d != f {
+                if d == b { e_[0] = d; }
+                if (d == b && e != c) || (b == f && e != a) { e_[1] = b; }
+                if b == f { e_[2] = f; }
+                if (d == b && e != g) || (d == h && e != a) { e_[3] = d; }
+                if (b == f && e != i) || (h == f && e != c) { e_[5] = f; }
+                if d == h { e_[6] = d; }
+                if (d == h && e != i) || (h == f && e != g) { e_[7] = h; }
+                if h == f { e_[8] = f; }
+            }
+            for (k, c) in e_.iter().enumerate() {
+                out.pixels[(3 * y + k / 3) * out.width + 3 * x + k % 3] = *c;
+            }
+        }
+    }
+}
+
+// last row of each source pixel, every other row at 1x
+fn darken_scanlines(out: &mut FrameBuffer, n: usize) {
+    let step: usize = n.max(2);
+    for y in (step - 1..out.height).step_by(step) {
+        for pixel in out.pixels[y * out.width..(y + 1) * out.width].iter_mut() {
+            let mut c: u32 = 0xFF000000;
+            for shift in [16, 8, 0] {
+                c |= (((*pixel >> shift) & 0xFF) * SCANLINE_LEVEL / SCANLINE_DIVISOR) << shift;
+            }
+            *pixel = c;
+        }
+    }
+}
+
+fn stretch(src: &FrameBuffer, width: usize) -> FrameBuffer {
+    let mut out: FrameBuffer = FrameBuffer::new(width, src.height);
+    for y in 0..src.height {
+        for x in 0..width {
+            out.pixels[y * width + x] = src.get(x * src.width / width, y);
+        }
+    }
+    out
+}
+
+pub fn apply(src: &FrameBuffer, options: &ScaleOptions) -> FrameBuffer {
+    let cropped: FrameBuffer;
+    let src: &FrameBuffer = if options.overscan.is_none() {
+        src
+    } else {
+        cropped = options.overscan.crop(src);
+        &cropped
+    };
+    let n: usize = options.filter.factor();
+    let mut out: FrameBuffer = FrameBuffer::new(n * src.width, n * src.height);
+    match options.filter {
+        ScaleFilter::Nearest(n) => nearest(src, &mut out, n),
+        ScaleFilter::Scale2x => scale2x(src, &mut out),
+        ScaleFilter::Scale3x => scale3x(src, &mut out),
+    }
+    if options.is_scanline {
+        darken_scanlines(&mut out, n);
+    }
+    if options.aspect != PixelAspect::Square {
+        let (numerator, denominator) = options.aspect.ratio();
+        out = stretch(&out, out.width * numerator / denominator);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_image_stays_flat() {
+        let mut src = FrameBuffer::new(16, 8);
+        src.pixels.iter_mut().for_each(|p| *p = 0xFF336699);
+        for name in ["nearest3", "scale2x", "scale3x"] {
+            let mut options = ScaleOptions::new();
+            options.filter = ScaleFilter::parse(name).unwrap();
+            options.aspect = PixelAspect::Ntsc;
+            let out = apply(&src, &options);
+            assert_eq!((out.width, out.height), options.output_size(16, 8), "{}", name);
+            assert!(out.pixels.iter().all(|p| *p == 0xFF336699), "{}", name);
+        }
+    }
+
+    fn image(width: usize, height: usize, pixels: &[u32]) -> FrameBuffer {
+        let mut src = FrameBuffer::new(width, height);
+        src.pixels.copy_from_slice(pixels);
+        src
+    }
+
+    fn scale(src: &FrameBuffer, name: &str) -> FrameBuffer {
+        let mut options = ScaleOptions::new();
+        options.filter = ScaleFilter::parse(name).unwrap();
+        apply(src, &options)
+    }
+
+    const K: u32 = 0xFF000000;
+    const W: u32 = 0xFFFFFFFF;
+
+    #[test]
+    fn scale2x_diagonal() {
+        // B and D match, the top left sub pixel of E takes their color
+        let src = image(3, 3, &[K, W, K, W, K, K, K, K, K]);
+        let out = scale(&src, "scale2x");
+        assert_eq!([out.get(2, 2), out.get(3, 2), out.get(2, 3), out.get(3, 3)], [W, K, K, K]);
+        // the white pixels keep their corners
+        assert_eq!([out.get(2, 0), out.get(3, 0), out.get(2, 1), out.get(3, 1)], [W; 4]);
+    }
+
+    #[test]
+    fn scanlines_at_1x() {
+        let mut options = ScaleOptions::new();
+        options.is_scanline = true;
+        assert!(!options.is_identity());
+        let out = apply(&image(1, 4, &[W; 4]), &options);
+        assert_eq!(out.pixels, vec![W, 0xFF9F9F9F, W, 0xFF9F9F9F]);
+    }
+}
@@ -0,0 +1,171 @@
+use std::fs;
+
+use super::checksum::{adler32, crc32, crc32_update};
+use super::render::FrameBuffer;
+
+/*
+    [Screenshot] screenshots/<rom>_<frame>.png and <rom>_<frame>_raw.png
+    the raw image is the 256x240 PPU output, the other one is the
+    filtered and scaled output shown in the window.
+
+    [PNG text chunks]
+    | keyword   | value                                       |
+    +-----------+---------------------------------------------+
+    | Software  | rustnes <version>                           |
+    | ROM CRC32 | crc32 of PRG and CHR rom, without header    |
+    | Frame     | frame number since power on                 |
+
+    pixels are stored in uncompressed deflate blocks.
+*/
+
+pub const SCREENSHOT_DIR: &str = "screenshots";
+const PNG_SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+const STORED_BLOCK_MAX: usize = 0xFFFF;
+
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    pub rom_crc32: u32,
+    pub frame: u64,
+}
+
+impl Metadata {
+    pub fn text(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("Software", format!("rustnes {}", env!("CARGO_PKG_VERSION"))),
+            ("ROM CRC32", format!("{:08X}", self.rom_crc32)),
+            ("Frame", self.frame.to_string()),
+        ]
+    }
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    out.extend_from_slice(&crc32_update(crc32(kind), data).to_be_bytes());
+}
+
+// zlib stream of stored deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = data.chunks(STORED_BLOCK_MAX).collect();
+    for (i, block) in blocks.iter().enumerate() {
+        out.push((i + 1 == blocks.len()) as u8);
+        out.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    if blocks.is_empty() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+// 8 bit RGB
+pub fn encode_png(fb: &FrameBuffer, text: &[(&str, String)]) -> Vec<u8> {
+    let mut out: Vec<u8> = PNG_SIGNATURE.to_vec();
+    let mut header: Vec<u8> = Vec::new();
+    header.extend_from_slice(&(fb.width as u32).to_be_bytes());
+    header.extend_from_slice(&(fb.height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(&mut out, b"IHDR", &header);
+    for (keyword, value) in text.iter() {
+        let mut data: Vec<u8> = keyword.as_bytes().to_vec();
+        data.push(0);
+        data.extend_from_slice(value.as_bytes());
+        write_chunk(&mut out, b"tEXt", &data);
+    }
+    let mut raw: Vec<u8> = Vec::with_capacity((3 * fb.width + 1) * fb.height);
+    for y in 0..fb.height {
+        // filter type none
+        raw.push(0);
+        for pixel in fb.row(y).iter() {
+            raw.extend_from_slice(&[(pixel >> 16) as u8, (pixel >> 8) as u8, *pixel as u8]);
+        }
+    }
+    write_chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+pub fn save_png(path: &str, fb: &FrameBuffer, metadata: &Metadata) -> Result<(), String> {
+    fs::write(path, encode_png(fb, &metadata.text()))
+        .map_err(|e| format!("{}: {}", path, e))
+}
+
+// saves the raw and the shown frame, returns their paths
+pub fn capture(
+    name: &str,
+    raw: &FrameBuffer,
+    shown: &FrameBuffer,
+    metadata: &Metadata
+) -> Result<(String, String), String> {
+    fs::create_dir_all(SCREENSHOT_DIR).map_err(|e| e.to_string())?;
+    let base = format!("{}/{}_{}", SCREENSHOT_DIR, name, metadata.frame);
+    let raw_path = format!("{}_raw.png", base);
+    let shown_path = format!("{}.png", base);
+    save_png(&raw_path, raw, metadata)?;
+    save_png(&shown_path, shown, metadata)?;
+    Ok((raw_path, shown_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::inflate::inflate;
+
+    // kind and data of every chunk, checking the length and crc
+    fn chunks(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        let mut list = Vec::new();
+        let mut p: usize = PNG_SIGNATURE.len();
+        while p < png.len() {
+            let len: usize = u32::from_be_bytes([png[p], png[p + 1], png[p + 2], png[p + 3]]) as usize;
+            let kind: [u8; 4] = [png[p + 4], png[p + 5], png[p + 6], png[p + 7]];
+            let data: &[u8] = &png[p + 8 .. p + 8 + len];
+            let crc: &[u8] = &png[p + 8 + len .. p + 12 + len];
+            assert_eq!(crc, crc32_update(crc32(&kind), data).to_be_bytes());
+            list.push((kind, data.to_vec()));
+            p += 12 + len;
+        }
+        assert_eq!(p, png.len());
+        list
+    }
+
+    #[test]
+    fn png_layout() {
+        // 601 bytes per row, more than one stored block
+        let mut fb = FrameBuffer::new(200, 120);
+        for y in 0..fb.height {
+            for x in 0..fb.width {
+                fb.set(x, y, (x as u64) << 16 | (y as u64) << 8 | 0x5A);
+            }
+        }
+        let metadata = Metadata { rom_crc32: 0x1234ABCD, frame: 42 };
+        let png = encode_png(&fb, &metadata.text());
+        assert_eq!(png[0..8], PNG_SIGNATURE);
+
+        let list = chunks(&png);
+        let kinds: Vec<&[u8; 4]> = list.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [b"IHDR", b"tEXt", b"tEXt", b"tEXt", b"IDAT", b"IEND"]);
+        assert_eq!(list[0].1, [0, 0, 0, 200, 0, 0, 0, 120, 8, 2, 0, 0, 0]);
+        assert_eq!(list[2].1, b"ROM CRC32\x001234ABCD");
+        assert_eq!(list[3].1, b"Frame\x0042");
+        assert!(list[1].1.starts_with(b"Software\x00rustnes "));
+        assert!(list[5].1.is_empty());
+
+        // zlib header, stored blocks and adler32 of the raw rows
+        let idat: &[u8] = &list[4].1;
+        assert_eq!(idat[0..2], [0x78, 0x01]);
+        let (raw, used) = inflate(&idat[2..]).unwrap();
+        assert_eq!(idat[2 + used ..], adler32(&raw).to_be_bytes());
+        assert_eq!(raw.len(), 601 * 120);
+        for y in 0..fb.height {
+            let row: &[u8] = &raw[601 * y .. 601 * (y + 1)];
+            assert_eq!(row[0], 0);
+            for x in 0..fb.width {
+                assert_eq!(row[1 + 3 * x .. 4 + 3 * x], [x as u8, y as u8, 0x5A]);
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::fs;
+
+use super::cpu::KeyPadRegister;
+
+/*
+    [Input script]
+    one entry per line, the buttons are held from that frame
+    until the next entry. '#' starts a comment, '-' releases all.
+
+    # frame  buttons
+    0        -
+    30       start
+    32       -
+    100      right a
+*/
+
+pub const BUTTON_A: u8 = 1 << 0;
+pub const BUTTON_B: u8 = 1 << 1;
+pub const BUTTON_SELECT: u8 = 1 << 2;
+pub const BUTTON_START: u8 = 1 << 3;
+pub const BUTTON_UP: u8 = 1 << 4;
+pub const BUTTON_DOWN: u8 = 1 << 5;
+pub const BUTTON_LEFT: u8 = 1 << 6;
+pub const BUTTON_RIGHT: u8 = 1 << 7;
+
+#[derive(Debug, Clone)]
+pub struct InputScript {
+    entries: Vec<(u32, u8)>,
+}
+
+impl InputScript {
+    pub fn new() -> InputScript {
+        InputScript {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<InputScript, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path, e))?;
+        InputScript::parse(&text)
+            .map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn parse(text: &str) -> Result<InputScript, String> {
+        let mut script = InputScript::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let frame: u32 = words.next().unwrap().parse()
+                .map_err(|_| format!("line {}: invalid frame", i + 1))?;
+            let mut buttons: u8 = 0;
+            for w in words {
+                buttons |= match w {
+                    "-" => 0,
+                    "a" => BUTTON_A,
+                    "b" => BUTTON_B,
+                    "select" => BUTTON_SELECT,
+                    "start" => BUTTON_START,
+                    "up" => BUTTON_UP,
+                    "down" => BUTTON_DOWN,
+                    "left" => BUTTON_LEFT,
+                    "right" => BUTTON_RIGHT,
+                    _ => return Err(format!("line {}: invalid button {}", i + 1, w)),
+                };
+            }
+            script.push(frame, buttons);
+        }
+        Ok(script)
+    }
+
+    pub fn push(&mut self, frame: u32, buttons: u8) {
+        self.entries.push((frame, buttons));
+        self.entries.sort_by_key(|e| e.0);
+    }
+
+    // buttons held during the given frame
+    pub fn buttons(&self, frame: u32) -> u8 {
+        self.entries.iter()
+            .take_while(|e| e.0 <= frame)
+            .last()
+            .map_or(0, |e| e.1)
+    }
+
+    pub fn apply(&self, frame: u32, keypad: &mut KeyPadRegister) {
+        let buttons = self.buttons(frame);
+        keypad.a = buttons & BUTTON_A > 0;
+        keypad.b = buttons & BUTTON_B > 0;
+        keypad.select = buttons & BUTTON_SELECT > 0;
+        keypad.start = buttons & BUTTON_START > 0;
+        keypad.up = buttons & BUTTON_UP > 0;
+        keypad.down = buttons & BUTTON_DOWN > 0;
+        keypad.left = buttons & BUTTON_LEFT > 0;
+        keypad.right = buttons & BUTTON_RIGHT > 0;
+    }
+}
+
+impl Default for InputScript {
+    fn default() -> InputScript {
+        InputScript::new()
+    }
+}
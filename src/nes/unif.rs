@@ -0,0 +1,234 @@
+use super::cassette::{ConsoleType, Header, HeaderFormat, Mirroring, Timing};
+use super::checksum::crc32;
+
+/*
+    [UNIF] "UNIF", 4 byte revision, 24 bytes of 0, then chunks of
+    4 byte id, 4 byte little endian length and data
+    | id        | data                                            |
+    +-----------+-------------------------------------------------+
+    | MAPR      | board name, null terminated                     |
+    | PRG0-PRGF | PRG ROM, concatenated in order                  |
+    | CHR0-CHRF | CHR ROM, concatenated in order                  |
+    | PCK0-PCKF | crc32 of PRGn                                   |
+    | CCK0-CCKF | crc32 of CHRn                                   |
+    | MIRR      | 0 H, 1 V, 2/3 single screen, 4 four screen,     |
+    |           | 5 selected by the mapper                        |
+    | BATR      | battery backed PRG RAM                          |
+    | TVCI      | 0 NTSC, 1 PAL, 2 both                           |
+    | NAME      | title, null terminated                          |
+    other chunks (READ, DINF, CTRL, VROR, ...) are ignored.
+*/
+
+pub const UNIF_HSIZE: usize = 32;
+
+// board name without the NES-/HVC-/UNL-/BMC- prefix, and NES 2.0 mapper
+// of https://www.nesdev.org/wiki/UNIF_to_NES_2.0_Mapping
+const BOARDS: [(&str, u16); 143] = [
+    ("NROM", 0), ("NROM-128", 0), ("NROM-256", 0), ("RROM", 0), ("RROM-128", 0),
+    ("SAROM", 1), ("SBROM", 1), ("SCROM", 1), ("SEROM", 1), ("SFROM", 1),
+    ("SGROM", 1), ("SHROM", 1), ("SJROM", 1), ("SKROM", 1), ("SLROM", 1),
+    ("SL1ROM", 1), ("SNROM", 1), ("SOROM", 1), ("SUROM", 1), ("SXROM", 1),
+    ("UNROM", 2), ("UOROM", 2),
+    ("CNROM", 3),
+    ("TBROM", 4), ("TEROM", 4), ("TFROM", 4), ("TGROM", 4), ("TKROM", 4),
+    ("TLROM", 4), ("TL1ROM", 4), ("TR1ROM", 4), ("TSROM", 4), ("TVROM", 4),
+    ("EKROM", 5), ("ELROM", 5), ("ETROM", 5), ("EWROM", 5),
+    ("ANROM", 7), ("AN1ROM", 7), ("AMROM", 7), ("AOROM", 7),
+    ("PNROM", 9), ("PEEOROM", 9),
+    ("FJROM", 10), ("FKROM", 10),
+    ("CPROM", 13),
+    ("BNROM", 34),
+    ("GNROM", 66), ("MHROM", 66),
+    ("TLSROM", 118), ("TKSROM", 118),
+    ("TQROM", 119),
+    ("Sachen-8259A", 141), ("Sachen-8259B", 138), ("Sachen-8259C", 139), ("Sachen-8259D", 137),
+    ("Sachen-74LS374N", 150),
+    // UNL- boards
+    ("SL1632", 14), ("CC-21", 27), ("AC08", 42), ("BB", 108), ("H2288", 123),
+    ("LH32", 125), ("22211", 132), ("SA-72008", 133), ("KS7032", 142), ("SA-NROM", 143),
+    ("SA-72007", 145), ("SA-016-1M", 146), ("TC-U01-1.5M", 147), ("SA-0037", 148),
+    ("SA-0036", 149), ("FS304", 162), ("8237", 215), ("8237A", 215), ("A9746", 219),
+    ("N625092", 221), ("603-5052", 238), ("OneBus", 256), ("PEC-586", 257), ("158B", 258),
+    ("SHERO", 262), ("YOKO", 264), ("CITYFIGHT", 266), ("DRIPGAME", 284), ("TF1201", 298),
+    ("KS7057", 302), ("KS7017", 303), ("SMB2J", 304), ("KS7031", 305), ("KS7016", 306),
+    ("KS7037", 307), ("TH2131-1", 308), ("LH51", 309), ("KS7013B", 312), ("MALISB", 325),
+    ("RT-01", 328), ("EDU2000", 329), ("KS7012", 346), ("KS7030", 347),
+    // BMC- multicarts
+    ("SuperHIK8in1", 45), ("SUPERVISION16in1", 53), ("D1038", 59), ("Super700in1", 62),
+    ("FK23C", 176), ("FK23CA", 176), ("Super24in1SC03", 176), ("42in1ResetSwitch", 233),
+    ("70in1", 236), ("70in1B", 236), ("F-15", 259), ("HPxx", 260), ("810544-C-A1", 261),
+    ("T-262", 265), ("80013-B", 274), ("GS-2004", 283), ("GS-2013", 283), ("A65AS", 285),
+    ("BS-5", 286), ("411120-C", 287), ("K-3088", 287), ("NTD-03", 290), ("11160", 299),
+    ("190in1", 300), ("8157", 301), ("64in1NoRepeat", 314), ("830134C", 315),
+    ("HP898F", 319), ("830425C-4391T", 320), ("K-3033", 322), ("12-IN-1", 331), ("WS", 332),
+    ("K-3046", 336), ("CTC-12IN1", 337), ("SA005-A", 338), ("K-3006", 339), ("K-3036", 340),
+    ("TJ-03", 341), ("GN-26", 344), ("L6IN1", 345), ("830118C", 348), ("G-146", 349),
+    ("891227", 350),
+];
+const BOARD_PREFIXES: [&str; 5] = ["NES-", "HVC-", "UNL-", "BMC-", "BTL-"];
+
+pub fn is_unif(buf: &[u8]) -> bool {
+    buf.len() >= 4 && &buf[0..4] == b"UNIF"
+}
+
+pub fn board_mapper(board: &str) -> Option<u16> {
+    let mut name: &str = board;
+    for prefix in BOARD_PREFIXES.iter() {
+        if let Some(rest) = name.strip_prefix(prefix) {
+            name = rest;
+            break;
+        }
+    }
+    BOARDS.iter().find(|(b, _)| b.eq_ignore_ascii_case(name)).map(|(_, m)| *m)
+}
+
+fn c_string(data: &[u8]) -> String {
+    let end: usize = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).to_string()
+}
+
+pub struct Unif {
+    pub header: Header,
+    pub board: String,
+    pub name: Option<String>,
+    pub prog_rom: Vec<u8>,
+    pub char_rom: Vec<u8>,
+}
+
+pub fn parse(buf: &[u8]) -> Result<Unif, String> {
+    if buf.len() < UNIF_HSIZE || !is_unif(buf) {
+        return Err("invalid UNIF header".to_string());
+    }
+    let mut board: Option<String> = None;
+    let mut name: Option<String> = None;
+    let mut prog: Vec<Option<&[u8]>> = vec![None; 16];
+    let mut char: Vec<Option<&[u8]>> = vec![None; 16];
+    let mut prog_crc: Vec<Option<u32>> = vec![None; 16];
+    let mut char_crc: Vec<Option<u32>> = vec![None; 16];
+    let mut mirroring: Mirroring = Mirroring::Horizontal;
+    let mut has_battery: bool = false;
+    let mut timing: Timing = Timing::Ntsc;
+
+    let mut p: usize = UNIF_HSIZE;
+    while p + 8 <= buf.len() {
+        let id: &[u8] = &buf[p..p + 4];
+        let len: usize = u32::from_le_bytes([buf[p + 4], buf[p + 5], buf[p + 6], buf[p + 7]]) as usize;
+        let data: &[u8] = buf.get(p + 8..p + 8 + len).ok_or(format!(
+            "UNIF chunk {} declares {} bytes, {} bytes left",
+            String::from_utf8_lossy(id), len, buf.len() - p - 8))?;
+        // PRGn, CHRn, PCKn, CCKn with n in 0-F, others such as PRGX are ignored
+        let slot: Option<usize> = (id[3] as char).to_digit(16).map(|n| n as usize);
+        match (&id[0..3], slot) {
+            (b"PRG", Some(n)) => prog[n] = Some(data),
+            (b"CHR", Some(n)) => char[n] = Some(data),
+            (b"PCK", Some(n)) if len >= 4 => prog_crc[n] = Some(u32::from_le_bytes([data[0], data[1], data[2], data[3]])),
+            (b"CCK", Some(n)) if len >= 4 => char_crc[n] = Some(u32::from_le_bytes([data[0], data[1], data[2], data[3]])),
+            _ => match id {
+                b"MAPR" => board = Some(c_string(data)),
+                b"NAME" => name = Some(c_string(data)),
+                b"BATR" => has_battery = true,
+                b"MIRR" if len >= 1 => mirroring = match data[0] {
+                    1 => Mirroring::Vertical,
+                    2 => Mirroring::SingleScreenA,
+                    3 => Mirroring::SingleScreenB,
+                    4 => Mirroring::FourScreen,
+                    _ => Mirroring::Horizontal,
+                },
+                b"TVCI" if len >= 1 => timing = match data[0] {
+                    1 => Timing::Pal,
+                    2 => Timing::MultiRegion,
+                    _ => Timing::Ntsc,
+                },
+                _ => (),
+            },
+        }
+        p += 8 + len;
+    }
+
+    let board: String = board.ok_or("UNIF has no MAPR chunk")?;
+    let mapper: u16 = board_mapper(&board).ok_or(format!("unknown UNIF board {}", board))?;
+    let mut prog_rom: Vec<u8> = Vec::new();
+    let mut char_rom: Vec<u8> = Vec::new();
+    for i in 0..16 {
+        for (kind, chunk, crc, rom) in [
+                ("PRG", prog[i], prog_crc[i], &mut prog_rom),
+                ("CHR", char[i], char_crc[i], &mut char_rom)].iter_mut() {
+            if let Some(data) = chunk {
+                if let Some(expected) = crc {
+                    if crc32(data) != *expected {
+                        println!("warning: UNIF {}{:X} crc32 {:08X}, expected {:08X}",
+                            kind, i, crc32(data), expected);
+                    }
+                }
+                rom.extend_from_slice(data);
+            }
+        }
+    }
+    if prog_rom.is_empty() {
+        return Err("UNIF has no PRG chunk".to_string());
+    }
+    let header = Header {
+        format: HeaderFormat::Unif,
+        mapper,
+        submapper: 0,
+        prog_rom_size: prog_rom.len(),
+        char_rom_size: char_rom.len(),
+        prog_ram_size: if has_battery { 0 } else { 0x2000 },
+        prog_nvram_size: if has_battery { 0x2000 } else { 0 },
+        char_ram_size: if char_rom.is_empty() { 0x2000 } else { 0 },
+        char_nvram_size: 0,
+        mirroring,
+        has_battery,
+        has_trainer: false,
+        console_type: ConsoleType::Nes,
+        timing,
+        vs_ppu_type: 0,
+        vs_hardware_type: 0,
+        misc_roms: 0,
+        expansion_device: 0,
+        is_disk_dude: false,
+    };
+    Ok(Unif {
+        header,
+        board,
+        name,
+        prog_rom,
+        char_rom,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(buf: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+        buf.extend_from_slice(id);
+        buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        buf.extend_from_slice(data);
+    }
+
+    #[test]
+    fn parse_chunks() {
+        let mut buf: Vec<u8> = b"UNIF".to_vec();
+        buf.extend_from_slice(&7u32.to_le_bytes());
+        buf.resize(UNIF_HSIZE, 0);
+        chunk(&mut buf, b"MAPR", b"NES-SNROM\0");
+        chunk(&mut buf, b"PRG1", &[2; 0x4000]);
+        chunk(&mut buf, b"PRG0", &[1; 0x4000]);
+        chunk(&mut buf, b"PRGX", &[3; 0x10]);
+        chunk(&mut buf, b"PCKZ", &[0; 4]);
+        chunk(&mut buf, b"PCK0", &crc32(&[1; 0x4000]).to_le_bytes());
+        chunk(&mut buf, b"MIRR", &[1]);
+        chunk(&mut buf, b"BATR", &[0]);
+        chunk(&mut buf, b"NAME", b"Test\0");
+        let unif = parse(&buf).unwrap();
+        assert_eq!((unif.header.mapper, unif.header.mirroring), (1, Mirroring::Vertical));
+        assert_eq!((unif.prog_rom[0], unif.prog_rom[0x4000], unif.prog_rom.len()), (1, 2, 0x8000));
+        assert_eq!((unif.header.char_ram_size, unif.header.prog_nvram_size), (0x2000, 0x2000));
+        assert_eq!(unif.name, Some("Test".to_string()));
+        assert_eq!(board_mapper("UNL-Sachen-8259A"), Some(141));
+        assert_eq!(board_mapper("BMC-FK23C"), Some(176));
+        assert_eq!(board_mapper("UNL-KS7032"), Some(142));
+        assert_eq!(board_mapper("BMC-190in1"), Some(300));
+    }
+}
@@ -0,0 +1,302 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+use super::render::FrameBuffer;
+
+/*
+    [Video recording] uncompressed AVI
+    video  24 bit DIB, one '00db' chunk per frame
+    audio  16 bit mono PCM, one '01wb' chunk per frame
+
+    RIFF 'AVI '
+      LIST 'hdrl'
+        'avih'
+        LIST 'strl' 'strh' 'strf'   video
+        LIST 'strl' 'strh' 'strf'   audio
+      LIST 'movi'
+        '00db' '01wb' '00db' '01wb' ...
+      'idx1'
+
+    the frame rate is that of the region, 39375000 / 655171 = 60.0988 Hz
+    on NTSC. the audio of each frame is cut or padded with silence to
+    the samples due by the end of the frame, so it never drifts from
+    the video. the header is rewritten with the counts by finish().
+    AVI 1.0 is limited to 1GiB, about 90 seconds at 256x240.
+
+    the 2A03 APU does not produce samples yet, the audio track is
+    silent except the FDS expansion audio.
+*/
+
+pub const SAMPLE_RATE: u32 = 44100;
+pub const AUDIO_NOTE: &str = "the 2A03 APU is not emulated yet, only FDS audio is recorded";
+const MAX_FILE_SIZE: u64 = 0x4000_0000;
+const AVIF_HASINDEX: u32 = 0x10;
+const AVIIF_KEYFRAME: u32 = 0x10;
+
+pub struct AviWriter {
+    out: BufWriter<File>,
+    width: usize,
+    height: usize,
+    // frames per second as numerator, denominator
+    frame_rate: (u32, u32),
+    frames: u32,
+    samples: u64,
+    // size of the 'movi' list data after its fourcc
+    movi_size: u32,
+    // (chunk id, offset from 'movi', size)
+    index: Vec<([u8; 4], u32, u32)>,
+}
+
+fn push_u16(buf: &mut Vec<u8>, v: u16) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn push_u32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn push_chunk(buf: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    buf.extend_from_slice(id);
+    push_u32(buf, data.len() as u32);
+    buf.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        buf.push(0);
+    }
+}
+
+fn push_list(buf: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    buf.extend_from_slice(b"LIST");
+    push_u32(buf, data.len() as u32 + 4);
+    buf.extend_from_slice(kind);
+    buf.extend_from_slice(data);
+}
+
+fn stream_header(
+    kind: &[u8; 4], scale: u32, rate: u32, length: u32, buffer: u32, sample_size: u32,
+    rect: (u16, u16)
+) -> Vec<u8> {
+    let mut h: Vec<u8> = Vec::new();
+    h.extend_from_slice(kind);
+    push_u32(&mut h, 0);            // handler
+    push_u32(&mut h, 0);            // flags
+    push_u16(&mut h, 0);            // priority
+    push_u16(&mut h, 0);            // language
+    push_u32(&mut h, 0);            // initial frames
+    push_u32(&mut h, scale);
+    push_u32(&mut h, rate);
+    push_u32(&mut h, 0);            // start
+    push_u32(&mut h, length);
+    push_u32(&mut h, buffer);
+    push_u32(&mut h, 0xFFFFFFFF);   // quality
+    push_u32(&mut h, sample_size);
+    push_u16(&mut h, 0);
+    push_u16(&mut h, 0);
+    push_u16(&mut h, rect.0);
+    push_u16(&mut h, rect.1);
+    h
+}
+
+impl AviWriter {
+    pub fn create(
+        path: &str, width: usize, height: usize, frame_rate: (u32, u32)
+    ) -> Result<AviWriter, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut writer = AviWriter {
+            out: BufWriter::new(file),
+            width,
+            height,
+            frame_rate,
+            frames: 0,
+            samples: 0,
+            movi_size: 4,
+            index: Vec::new(),
+        };
+        let header = writer.header();
+        writer.out.write_all(&header).map_err(|e| e.to_string())?;
+        Ok(writer)
+    }
+
+    // DIB rows are 4 byte aligned
+    fn row_size(&self) -> usize {
+        (3 * self.width + 3) & !3
+    }
+
+    fn frame_size(&self) -> u32 {
+        (self.row_size() * self.height) as u32
+    }
+
+    fn samples_per_frame_max(&self) -> u32 {
+        (SAMPLE_RATE as u64 * self.frame_rate.1 as u64 / self.frame_rate.0 as u64) as u32 + 1
+    }
+
+    // everything before the 'movi' data
+    fn header(&self) -> Vec<u8> {
+        let mut avih: Vec<u8> = Vec::new();
+        push_u32(&mut avih,
+            (1_000_000 * self.frame_rate.1 as u64 / self.frame_rate.0 as u64) as u32);
+        let fps: u32 = self.frame_rate.0.div_ceil(self.frame_rate.1);
+        push_u32(&mut avih, (self.frame_size() + 2 * self.samples_per_frame_max()) * fps);
+        push_u32(&mut avih, 0);
+        push_u32(&mut avih, AVIF_HASINDEX);
+        push_u32(&mut avih, self.frames);
+        push_u32(&mut avih, 0);
+        push_u32(&mut avih, 2);
+        push_u32(&mut avih, self.frame_size());
+        push_u32(&mut avih, self.width as u32);
+        push_u32(&mut avih, self.height as u32);
+        avih.extend_from_slice(&[0; 16]);
+
+        let mut bitmap: Vec<u8> = Vec::new();
+        push_u32(&mut bitmap, 40);
+        push_u32(&mut bitmap, self.width as u32);
+        // positive height, rows are bottom up
+        push_u32(&mut bitmap, self.height as u32);
+        push_u16(&mut bitmap, 1);
+        push_u16(&mut bitmap, 24);
+        push_u32(&mut bitmap, 0);
+        push_u32(&mut bitmap, self.frame_size());
+        bitmap.extend_from_slice(&[0; 16]);
+        let mut video: Vec<u8> = Vec::new();
+        push_chunk(&mut video, b"strh", &stream_header(
+            b"vids", self.frame_rate.1, self.frame_rate.0, self.frames,
+            self.frame_size(), 0, (self.width as u16, self.height as u16)));
+        push_chunk(&mut video, b"strf", &bitmap);
+
+        let mut wave: Vec<u8> = Vec::new();
+        push_u16(&mut wave, 1);
+        push_u16(&mut wave, 1);
+        push_u32(&mut wave, SAMPLE_RATE);
+        push_u32(&mut wave, 2 * SAMPLE_RATE);
+        push_u16(&mut wave, 2);
+        push_u16(&mut wave, 16);
+        let mut audio: Vec<u8> = Vec::new();
+        push_chunk(&mut audio, b"strh", &stream_header(
+            b"auds", 2, 2 * SAMPLE_RATE, self.samples as u32,
+            2 * self.samples_per_frame_max(), 2, (0, 0)));
+        push_chunk(&mut audio, b"strf", &wave);
+
+        let mut hdrl: Vec<u8> = Vec::new();
+        push_chunk(&mut hdrl, b"avih", &avih);
+        push_list(&mut hdrl, b"strl", &video);
+        push_list(&mut hdrl, b"strl", &audio);
+
+        let mut out: Vec<u8> = Vec::new();
+        push_list(&mut out, b"hdrl", &hdrl);
+        let riff_size: u32 = 4 + out.len() as u32 + 8 + self.movi_size +
+            8 + 16 * self.index.len() as u32;
+        let mut header: Vec<u8> = b"RIFF".to_vec();
+        push_u32(&mut header, riff_size);
+        header.extend_from_slice(b"AVI ");
+        header.extend(out);
+        header.extend_from_slice(b"LIST");
+        push_u32(&mut header, self.movi_size);
+        header.extend_from_slice(b"movi");
+        header
+    }
+
+    fn write_chunk(&mut self, id: &[u8; 4], data: &[u8]) -> Result<(), String> {
+        let mut chunk: Vec<u8> = Vec::with_capacity(data.len() + 9);
+        push_chunk(&mut chunk, id, data);
+        if self.movi_size as u64 + chunk.len() as u64 > MAX_FILE_SIZE {
+            return Err("recording reached the AVI size limit".to_string());
+        }
+        self.out.write_all(&chunk).map_err(|e| e.to_string())?;
+        self.index.push((*id, self.movi_size, data.len() as u32));
+        self.movi_size += chunk.len() as u32;
+        Ok(())
+    }
+
+    // audio is cut or padded to the samples due, empty audio is silence
+    pub fn write_frame(&mut self, fb: &FrameBuffer, audio: &[i16]) -> Result<(), String> {
+        if fb.width != self.width || fb.height != self.height {
+            return Err(format!("frame size {}x{} changed from {}x{}",
+                fb.width, fb.height, self.width, self.height));
+        }
+        let mut data: Vec<u8> = Vec::with_capacity(self.frame_size() as usize);
+        for y in (0..self.height).rev() {
+            for pixel in fb.row(y).iter() {
+                data.extend_from_slice(&[*pixel as u8, (pixel >> 8) as u8, (pixel >> 16) as u8]);
+            }
+            data.resize(data.len() + self.row_size() - 3 * self.width, 0);
+        }
+        self.write_chunk(b"00db", &data)?;
+        self.frames += 1;
+
+        // samples due by the end of this frame
+        let due: u64 = self.frames as u64 * SAMPLE_RATE as u64 * self.frame_rate.1 as u64 /
+            self.frame_rate.0 as u64;
+        let count: usize = (due - self.samples) as usize;
+        let mut pcm: Vec<u8> = Vec::with_capacity(2 * count);
+        for i in 0..count {
+            pcm.extend_from_slice(&audio.get(i).copied().unwrap_or(0).to_le_bytes());
+        }
+        self.write_chunk(b"01wb", &pcm)?;
+        self.samples += count as u64;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<u32, String> {
+        let mut idx1: Vec<u8> = Vec::with_capacity(16 * self.index.len());
+        for (id, offset, size) in self.index.iter() {
+            idx1.extend_from_slice(id);
+            push_u32(&mut idx1, AVIIF_KEYFRAME);
+            push_u32(&mut idx1, *offset);
+            push_u32(&mut idx1, *size);
+        }
+        let mut chunk: Vec<u8> = Vec::new();
+        push_chunk(&mut chunk, b"idx1", &idx1);
+        self.out.write_all(&chunk).map_err(|e| e.to_string())?;
+        let header = self.header();
+        self.out.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
+        self.out.write_all(&header).map_err(|e| e.to_string())?;
+        self.out.flush().map_err(|e| e.to_string())?;
+        Ok(self.frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(buf: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]])
+    }
+
+    // position of the first fourcc after pos
+    fn find(buf: &[u8], pos: usize, id: &[u8; 4]) -> usize {
+        pos + buf[pos..].windows(4).position(|w| w == id).unwrap()
+    }
+
+    #[test]
+    fn chunk_sizes_and_index() {
+        let path = std::env::temp_dir().join("rustnes_video_test.avi");
+        let path = path.to_str().unwrap();
+        let mut writer = AviWriter::create(path, 3, 2, (60, 1)).unwrap();
+        let fb = FrameBuffer::new(3, 2);
+        writer.write_frame(&fb, &[]).unwrap();
+        writer.write_frame(&fb, &[1; 2000]).unwrap();
+        writer.write_frame(&fb, &[2; 10]).unwrap();
+        assert_eq!(writer.finish(), Ok(3));
+        let buf = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(&buf[0..4], b"RIFF");
+        assert_eq!(u32_at(&buf, 4) as usize, buf.len() - 8);
+        // 3 frames of 735 samples
+        let strh = find(&buf, 0, b"auds");
+        assert_eq!(u32_at(&buf, strh + 32), 3 * 735);
+        let movi = find(&buf, 0, b"movi");
+        let idx1 = find(&buf, movi, b"idx1");
+        assert_eq!(u32_at(&buf, movi - 4) as usize, idx1 - movi);
+        assert_eq!(u32_at(&buf, idx1 + 4) as usize, 16 * 6);
+        // every index entry points at its chunk, rows are 12 bytes
+        for i in 0..6 {
+            let entry: usize = idx1 + 8 + 16 * i;
+            let offset: usize = u32_at(&buf, entry + 8) as usize;
+            let size: u32 = u32_at(&buf, entry + 12);
+            assert_eq!(&buf[movi + offset..movi + offset + 4], &buf[entry..entry + 4]);
+            assert_eq!(u32_at(&buf, movi + offset + 4), size);
+            assert_eq!(size, if i % 2 == 0 { 24 } else { 2 * 735 });
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use super::cassette::{ConsoleType, Header};
+use super::colors::{self, COLOR_NUM, TABLE_SIZE};
+
+/*
+    [Vs. UniSystem] arcade board with an RGB PPU
+    NES 2.0 byte 13 selects the PPU and the hardware
+    | ppu type | PPU          | description                            |
+    +----------+--------------+----------------------------------------+
+    | 0, 1     | RP2C03B/G    | 2C03 palette                           |
+    | 2-5      | RP2C04-0001  | 2C03 colors in a scrambled order,      |
+    |          |  ... -0004   | see PALETTE_2C04                       |
+    | 6, 7     | RC2C03B/C    | 2C03 palette                           |
+    | 8-12     | RC2C05-01    | 2C03 palette, $2000/$2001 swapped,     |
+    |          |  ... -05     | PPUSTATUS bit 0-5 is an id             |
+
+    [Inputs]
+    | addr  | bit | description                                  |
+    +-------+-----+----------------------------------------------+
+    | $4016 | 0   | r controller serial data                     |
+    |       | 2   | r service button                             |
+    |       | 3-4 | r DIP switch 1-2                             |
+    |       | 5-6 | r coin 1-2                                   |
+    |       | 2   | w CHR bank of mapper 99                      |
+    | $4017 | 0   | r controller serial data                     |
+    |       | 2-7 | r DIP switch 3-8                             |
+    | $4020 | 0   | w coin counter                               |
+
+    many games read player 1 at $4017, --vs-swap swaps the controllers.
+    the protection chips of hardware type 1-4 and the second CPU of
+    the Vs. DualSystem (5, 6) are not emulated.
+
+    iNES headers have no byte 13, such dumps fall back to RP2C03 unless
+    --vs-ppu selects the PPU, e.g. --vs-ppu RP2C04-0003 or 2c05-02.
+
+    [PlayChoice-10] support only selects the 2C03 palette, the game runs
+    as a plain NES cartridge. the Z80 instruction screen, its INST-ROM
+    and the play timer are not emulated.
+*/
+
+// 2C03 color index of each color index of the 2C04 variants
+const PALETTE_2C04: [[u8; COLOR_NUM]; 4] = [
+    [
+        0x35, 0x23, 0x16, 0x22, 0x1C, 0x09, 0x1D, 0x15, 0x20, 0x00, 0x27, 0x05, 0x04, 0x28, 0x08, 0x20,
+        0x21, 0x3E, 0x1F, 0x29, 0x3C, 0x32, 0x36, 0x12, 0x3F, 0x2B, 0x2E, 0x1E, 0x3D, 0x2D, 0x24, 0x01,
+        0x0E, 0x31, 0x33, 0x2A, 0x2C, 0x0C, 0x1B, 0x14, 0x2E, 0x07, 0x34, 0x06, 0x13, 0x02, 0x26, 0x2E,
+        0x2E, 0x19, 0x10, 0x0A, 0x39, 0x03, 0x37, 0x17, 0x0F, 0x11, 0x0B, 0x0D, 0x38, 0x25, 0x18, 0x3A,
+    ],
+    [
+        0x2E, 0x27, 0x18, 0x39, 0x3A, 0x25, 0x1C, 0x31, 0x16, 0x13, 0x38, 0x34, 0x20, 0x23, 0x3C, 0x0B,
+        0x0F, 0x21, 0x06, 0x3D, 0x1B, 0x29, 0x1E, 0x22, 0x1D, 0x24, 0x0E, 0x2B, 0x32, 0x08, 0x2E, 0x03,
+        0x04, 0x36, 0x26, 0x33, 0x11, 0x1F, 0x10, 0x02, 0x14, 0x3F, 0x00, 0x09, 0x12, 0x2E, 0x28, 0x20,
+        0x3E, 0x0D, 0x2A, 0x17, 0x0C, 0x01, 0x15, 0x19, 0x2E, 0x2C, 0x07, 0x37, 0x35, 0x05, 0x0A, 0x2D,
+    ],
+    [
+        0x14, 0x25, 0x3A, 0x10, 0x0B, 0x20, 0x31, 0x09, 0x01, 0x2E, 0x36, 0x08, 0x15, 0x3D, 0x3E, 0x3C,
+        0x22, 0x1C, 0x05, 0x12, 0x19, 0x18, 0x17, 0x1B, 0x00, 0x03, 0x2E, 0x02, 0x16, 0x06, 0x34, 0x35,
+        0x23, 0x0F, 0x0E, 0x37, 0x0D, 0x27, 0x26, 0x20, 0x29, 0x04, 0x21, 0x24, 0x11, 0x2D, 0x2E, 0x1F,
+        0x2C, 0x1E, 0x39, 0x33, 0x07, 0x2A, 0x28, 0x1D, 0x0A, 0x2E, 0x32, 0x38, 0x13, 0x2B, 0x3F, 0x0C,
+    ],
+    [
+        0x18, 0x03, 0x1C, 0x28, 0x2E, 0x35, 0x01, 0x17, 0x10, 0x1F, 0x2A, 0x0E, 0x36, 0x37, 0x0B, 0x39,
+        0x25, 0x1E, 0x12, 0x34, 0x2E, 0x1D, 0x06, 0x26, 0x3E, 0x1B, 0x22, 0x19, 0x04, 0x2E, 0x3A, 0x21,
+        0x05, 0x0A, 0x07, 0x02, 0x13, 0x14, 0x00, 0x15, 0x0C, 0x3D, 0x11, 0x0F, 0x0D, 0x38, 0x2D, 0x24,
+        0x33, 0x20, 0x08, 0x16, 0x3F, 0x2B, 0x20, 0x3C, 0x2E, 0x27, 0x23, 0x31, 0x29, 0x32, 0x2C, 0x09,
+    ],
+];
+
+// PPUSTATUS bit 0-5 of RC2C05-01 to -05, -05 has none
+const STATUS_ID_2C05: [Option<u8>; 5] = [Some(0x1B), Some(0x3D), Some(0x1C), Some(0x1B), None];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VsPpu {
+    Rp2c03,
+    // 0-3 for RP2C04-0001 to -0004
+    Rp2c04(usize),
+    // 0-4 for RC2C05-01 to -05
+    Rc2c05(usize),
+}
+
+impl VsPpu {
+    pub fn from_header(header: &Header) -> Option<VsPpu> {
+        match header.console_type {
+            ConsoleType::VsSystem => Some(match header.vs_ppu_type {
+                2..=5 => VsPpu::Rp2c04(header.vs_ppu_type as usize - 2),
+                8..=12 => VsPpu::Rc2c05(header.vs_ppu_type as usize - 8),
+                _ => VsPpu::Rp2c03,
+            }),
+            ConsoleType::PlayChoice10 => Some(VsPpu::Rp2c03),
+            _ => None,
+        }
+    }
+
+    pub fn all() -> Vec<VsPpu> {
+        let mut list: Vec<VsPpu> = vec![VsPpu::Rp2c03];
+        list.extend((0..4).map(VsPpu::Rp2c04));
+        list.extend((0..5).map(VsPpu::Rc2c05));
+        list
+    }
+
+    // name with or without the RP/RC prefix, case insensitive
+    pub fn parse(text: &str) -> Result<VsPpu, String> {
+        let text: String = text.to_uppercase();
+        VsPpu::all().into_iter()
+            .find(|p| p.name() == text || p.name()[2..] == text)
+            .ok_or(format!("unknown Vs. PPU {}, expected RP2C03, \
+                RP2C04-0001 to -0004 or RC2C05-01 to -05", text))
+    }
+
+    // NES 2.0 byte 13 bit 0-3
+    pub fn ppu_type(&self) -> u8 {
+        match self {
+            VsPpu::Rp2c03 => 0,
+            VsPpu::Rp2c04(n) => 2 + *n as u8,
+            VsPpu::Rc2c05(n) => 8 + *n as u8,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            VsPpu::Rp2c03 => "RP2C03".to_string(),
+            VsPpu::Rp2c04(n) => format!("RP2C04-000{}", n + 1),
+            VsPpu::Rc2c05(n) => format!("RC2C05-0{}", n + 1),
+        }
+    }
+
+    // color table reordered to the color indexes of this PPU
+    pub fn remap(&self, table: &[u64]) -> Vec<u64> {
+        match self {
+            VsPpu::Rp2c04(n) => (0..TABLE_SIZE)
+                .map(|i| table[i / COLOR_NUM * COLOR_NUM + PALETTE_2C04[*n][i % COLOR_NUM] as usize])
+                .collect(),
+            _ => table.to_vec(),
+        }
+    }
+
+    pub fn colors(&self) -> Vec<u64> {
+        self.remap(&colors::rgb_2c03_table())
+    }
+
+    pub fn status_id(&self) -> Option<u8> {
+        match self {
+            VsPpu::Rc2c05(n) => STATUS_ID_2C05[*n],
+            _ => None,
+        }
+    }
+
+    // PPUCTRL and PPUMASK trade addresses
+    pub fn is_control_swapped(&self) -> bool {
+        matches!(self, VsPpu::Rc2c05(_))
+    }
+}
+
+#[derive(Debug)]
+pub struct VsSystem {
+    // switch 1 is bit 0
+    pub dip: u8,
+    pub coin1: bool,
+    pub coin2: bool,
+    pub service: bool,
+    pub is_swap_controllers: bool,
+    has_chr_bank: bool,
+}
+
+impl VsSystem {
+    pub fn new(header: &Header, dip: u8, is_swap_controllers: bool) -> Option<VsSystem> {
+        if header.console_type != ConsoleType::VsSystem {
+            return None;
+        }
+        match header.vs_hardware_type {
+            0 => (),
+            1..=4 => println!("warning: Vs. System protection type {} is not emulated",
+                header.vs_hardware_type),
+            _ => println!("warning: Vs. DualSystem runs the main CPU only"),
+        }
+        Some(VsSystem {
+            dip,
+            coin1: false,
+            coin2: false,
+            service: false,
+            is_swap_controllers,
+            has_chr_bank: header.mapper == 99,
+        })
+    }
+
+    // bits besides the controller data of $4016
+    pub fn read_4016(&self) -> u8 {
+        (self.service as u8) << 2 | (self.dip & 0x03) << 3 |
+            (self.coin1 as u8) << 5 | (self.coin2 as u8) << 6
+    }
+
+    // bits besides the controller data of $4017
+    pub fn read_4017(&self) -> u8 {
+        self.dip & 0xFC
+    }
+
+    // 8KiB CHR bank of mapper 99
+    pub fn write_4016(&self, data: u8) -> Option<usize> {
+        if self.has_chr_bank { Some((data as usize >> 2) & 0x01) } else { None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Cassette;
+
+    // scrambled palettes only reorder the 2C03 colors
+    #[test]
+    fn palette_2c04_reorders_2c03() {
+        let rgb: Vec<u64> = colors::rgb_2c03_table();
+        for n in 0..4 {
+            let table: Vec<u64> = VsPpu::Rp2c04(n).remap(&rgb);
+            for (i, color) in table.iter().take(COLOR_NUM).enumerate() {
+                assert_eq!(*color, rgb[PALETTE_2C04[n][i] as usize]);
+            }
+            let mut sorted: Vec<u64> = table[..COLOR_NUM].to_vec();
+            sorted.sort();
+            sorted.dedup();
+            let mut expected: Vec<u64> = rgb[..COLOR_NUM].to_vec();
+            expected.sort();
+            expected.dedup();
+            assert_eq!(sorted, expected);
+        }
+    }
+
+    #[test]
+    fn parse_names() {
+        // iNES Vs. System rom, --vs-ppu goes through byte 13 of the header
+        let mut rom: Vec<u8> = super::super::bench::synthetic_rom();
+        rom[7] = 0x01;
+        let mut cas = Cassette::from_bytes("vs", rom).unwrap();
+        assert_eq!(VsPpu::from_header(&cas.header), Some(VsPpu::Rp2c03));
+        for ppu in VsPpu::all() {
+            assert_eq!(VsPpu::parse(&ppu.name()), Ok(ppu));
+            cas.apply_vs_ppu(Some(ppu));
+            assert_eq!(VsPpu::from_header(&cas.header), Some(ppu));
+        }
+        assert_eq!(VsPpu::parse("rp2c04-0003"), Ok(VsPpu::Rp2c04(2)));
+        assert_eq!(VsPpu::parse("2c05-02"), Ok(VsPpu::Rc2c05(1)));
+        assert!(VsPpu::parse("2C04-0005").is_err());
+        assert!(VsPpu::parse("2C02").is_err());
+    }
+}